@@ -0,0 +1,324 @@
+//! Data models for the activity payload that Discord renders as Rich Presence.
+//!
+//! These mirror the shape that the Discord client expects in a `SET_ACTIVITY` command. Fields
+//! that are `None` are omitted from the payload entirely, as Discord treats empty strings as
+//! invalid.
+
+use serde::{Deserialize, Serialize, Serializer};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The asset key for the Slippi logo, uploaded to the Discord application.
+pub const SLIPPI_LOGO_ASSET: &str = "slippi";
+
+/// The most characters Discord accepts in any of an activity's text fields.
+pub const MAX_FIELD_CHARS: usize = 128;
+
+/// How Discord introduces an activity, e.g "Playing Slippi" or "Competing in Slippi".
+///
+/// In settings files these are given by name (e.g, `"competing"`); in the payload, as the
+/// integer the Discord protocol uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityType {
+    Playing,
+    Competing,
+}
+
+impl ActivityType {
+    /// The value Discord uses for this type.
+    pub fn protocol_value(self) -> u8 {
+        match self {
+            ActivityType::Playing => 0,
+            ActivityType::Competing => 5,
+        }
+    }
+}
+
+/// An activity, as rendered by Discord.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Activity {
+    /// Discord assumes `ActivityType::Playing` if this is left out.
+    #[serde(rename = "type", serialize_with = "serialize_kind", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ActivityType>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<Timestamps>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assets: Option<Assets>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub party: Option<Party>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<Secrets>,
+
+    /// Up to two buttons. Discord won't accept these alongside `secrets`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buttons: Option<Vec<Button>>,
+}
+
+/// Unix timestamps (in milliseconds) that Discord uses to render an elapsed or remaining
+/// time counter. If `end` is set, Discord counts down to it; otherwise it counts up from `start`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Timestamps {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+}
+
+/// Image keys (and their hover text) for an activity.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Assets {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_image: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_text: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_image: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_text: Option<String>,
+}
+
+/// The party the player is in. Discord requires this (alongside a join secret) to show a
+/// "Join" button.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Party {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// `[current, max]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<[u32; 2]>,
+}
+
+/// Secrets that Discord hands back to other clients when they join via this activity.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Secrets {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spectate: Option<String>,
+}
+
+/// A button linking out to a URL.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Button {
+    pub label: String,
+    pub url: String,
+}
+
+impl Button {
+    pub fn new(label: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            url: url.into(),
+        }
+    }
+}
+
+impl Activity {
+    /// The baseline activity we show as soon as we've connected: just the Slippi
+    /// application, with the logo as the large image.
+    pub fn slippi() -> Self {
+        Self {
+            assets: Some(Assets {
+                large_image: Some(SLIPPI_LOGO_ASSET.to_string()),
+                large_text: Some("Slippi".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Runs every text field through `sanitize_presence_field`, so that user-provided text
+    /// (e.g from `Config::details_template`) can't get the whole update rejected. Returns the
+    /// fields that had to be cut short, as they were cut.
+    pub(crate) fn sanitize(&mut self) -> Vec<String> {
+        let assets = self.assets.as_mut();
+        let asset_fields = assets
+            .into_iter()
+            .flat_map(|assets| [&mut assets.large_text, &mut assets.small_text]);
+
+        let mut truncated = Vec::new();
+
+        for text in [&mut self.details, &mut self.state].into_iter().chain(asset_fields).flatten() {
+            let (sanitized, was_truncated) = sanitize_field(text);
+
+            if was_truncated {
+                truncated.push(sanitized.clone());
+            }
+
+            *text = sanitized;
+        }
+
+        truncated
+    }
+}
+
+fn serialize_kind<S: Serializer>(kind: &Option<ActivityType>, serializer: S) -> Result<S::Ok, S::Error> {
+    kind.map(ActivityType::protocol_value).serialize(serializer)
+}
+
+/// Makes `field` acceptable to Discord: control characters are dropped (with line breaks and
+/// tabs becoming spaces), and anything past `MAX_FIELD_CHARS` is cut off.
+///
+/// The cut is made between graphemes, so that e.g an emoji made up of several characters is
+/// either kept whole or dropped entirely.
+pub fn sanitize_presence_field(field: &str) -> String {
+    sanitize_field(field).0
+}
+
+/// `sanitize_presence_field`, along with whether anything had to be cut off.
+fn sanitize_field(field: &str) -> (String, bool) {
+    let cleaned: String = field
+        .chars()
+        .filter_map(|c| match c {
+            '\n' | '\r' | '\t' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect();
+
+    if cleaned.chars().count() <= MAX_FIELD_CHARS {
+        return (cleaned, false);
+    }
+
+    let mut sanitized = String::new();
+    let mut chars = 0;
+
+    for grapheme in cleaned.graphemes(true) {
+        chars += grapheme.chars().count();
+
+        if chars > MAX_FIELD_CHARS {
+            break;
+        }
+
+        sanitized.push_str(grapheme);
+    }
+
+    // A (pathological) first grapheme can be longer than the limit all by itself, leaving no
+    // boundary to cut on; the limit still holds, so it's cut mid-grapheme instead.
+    if sanitized.is_empty() {
+        sanitized = cleaned.chars().take(MAX_FIELD_CHARS).collect();
+    }
+
+    (sanitized, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_serialize() {
+        let mut activity = Activity::default();
+        assert_eq!(serde_json::to_string(&activity).unwrap(), "{}");
+
+        activity.buttons = Some(vec![Button::new("View Profile", "https://slippi.gg/user/abcd-123")]);
+        assert_eq!(
+            serde_json::to_string(&activity).unwrap(),
+            r#"{"buttons":[{"label":"View Profile","url":"https://slippi.gg/user/abcd-123"}]}"#
+        );
+
+        activity
+            .buttons
+            .as_mut()
+            .unwrap()
+            .push(Button::new("Stream", "https://twitch.tv/slippi"));
+        assert_eq!(
+            serde_json::to_string(&activity).unwrap(),
+            concat!(
+                r#"{"buttons":[{"label":"View Profile","url":"https://slippi.gg/user/abcd-123"},"#,
+                r#"{"label":"Stream","url":"https://twitch.tv/slippi"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn activity_types_serialize_as_integers() {
+        let activity = Activity {
+            kind: Some(ActivityType::Competing),
+            ..Default::default()
+        };
+
+        assert_eq!(serde_json::to_string(&activity).unwrap(), r#"{"type":5}"#);
+    }
+
+    #[test]
+    fn fields_are_stripped_of_control_characters() {
+        assert_eq!(sanitize_presence_field("Fox\nvs\tMarth\u{7}"), "Fox vs Marth");
+        assert_eq!(sanitize_presence_field("Fox vs Marth"), "Fox vs Marth");
+    }
+
+    #[test]
+    fn fields_are_truncated_on_a_char_boundary() {
+        // Each of these is two bytes, so a byte-based cut would land mid-character.
+        let long = "é".repeat(MAX_FIELD_CHARS + 10);
+        let sanitized = sanitize_presence_field(&long);
+
+        assert_eq!(sanitized.chars().count(), MAX_FIELD_CHARS);
+        assert!(sanitized.chars().all(|c| c == 'é'));
+        assert_eq!(
+            sanitize_presence_field(&long[..MAX_FIELD_CHARS * 2]),
+            long[..MAX_FIELD_CHARS * 2]
+        );
+    }
+
+    #[test]
+    fn fields_are_truncated_on_a_grapheme_boundary() {
+        // A family emoji is seven characters (four people, joined by three ZWJs); 19 of them
+        // only fit in part.
+        let family = "👨\u{200D}👩\u{200D}👧\u{200D}👦";
+        let sanitized = sanitize_presence_field(&family.repeat(19));
+
+        assert_eq!(sanitized, family.repeat(MAX_FIELD_CHARS / 7));
+
+        // Flags are pairs of regional indicators, which a char-based cut at an odd count would
+        // split.
+        let flags = format!("x{}", "🇯🇵".repeat(MAX_FIELD_CHARS));
+        let sanitized = sanitize_presence_field(&flags);
+        assert_eq!(sanitized, format!("x{}", "🇯🇵".repeat((MAX_FIELD_CHARS - 1) / 2)));
+
+        // Without a boundary to cut on, it's cut mid-grapheme rather than going over.
+        let accented = format!("e{}", "\u{301}".repeat(MAX_FIELD_CHARS));
+        assert_eq!(sanitize_presence_field(&accented).chars().count(), MAX_FIELD_CHARS);
+    }
+
+    #[test]
+    fn sanitizing_covers_every_text_field() {
+        let mut activity = Activity {
+            details: Some("a\nb".to_string()),
+            state: Some(format!("c\nd{}", "x".repeat(MAX_FIELD_CHARS))),
+            assets: Some(Assets {
+                large_image: Some("e\nf".to_string()),
+                small_text: Some("g\nh".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let truncated = activity.sanitize();
+
+        let assets = activity.assets.as_ref().unwrap();
+        assert_eq!(activity.details.as_deref(), Some("a b"));
+        assert!(activity.state.as_deref().unwrap().starts_with("c d"));
+        assert_eq!(truncated, vec![activity.state.clone().unwrap()]);
+        assert_eq!(assets.small_text.as_deref(), Some("g h"));
+
+        // Asset keys aren't text, so they're left as is.
+        assert_eq!(assets.large_image.as_deref(), Some("e\nf"));
+    }
+}
@@ -0,0 +1,214 @@
+//! The seam between the background thread's event loop and the Discord client.
+//!
+//! In practice this is always a `Connection`, but tests can drive the loop against a
+//! `MockBackend` instead, without a running Discord client.
+
+use std::time::{Duration, Instant};
+
+use crate::{Activity, OsdCallback, Result};
+
+/// Something that activities can be sent to.
+///
+/// Backends that manage a connection can override the remaining methods; the defaults describe
+/// a backend that's always available.
+pub(crate) trait DiscordBackend: Send {
+    /// Sets the activity for this process.
+    fn set_activity(&mut self, activity: &Activity) -> Result<()>;
+
+    /// Clears the activity for this process.
+    fn clear(&mut self) -> Result<()>;
+
+    /// Performs any initial connection.
+    fn connect(&mut self, _now: Instant) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called on each pass of the event loop, e.g to reconnect if needed. Returns whether a
+    /// connection was just (re)established, in which case the activity should be resent.
+    fn maintain(&mut self, _now: Instant) -> bool {
+        false
+    }
+
+    /// Whether activities can currently be sent. Pending activities are held until they can.
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    /// How long until the backend would like `maintain` to be called again, if it's waiting
+    /// on something.
+    fn time_until_retry(&self, _now: Instant) -> Option<Duration> {
+        None
+    }
+
+    /// Sets the callback that's notified of connection status changes.
+    fn set_osd_callback(&mut self, _callback: Option<OsdCallback>) {}
+
+    /// Sets how many reconnect attempts in a row to make before giving up (or `None` for no
+    /// limit), starting the count over. If we'd already given up, this tries again.
+    fn set_max_reconnect_attempts(&mut self, _max_attempts: Option<u32>, _now: Instant) {}
+
+    /// Sets how long to wait on the Discord client to finish each handshake.
+    fn set_connect_timeout(&mut self, _timeout: Duration) {}
+
+    /// Drops the current connection (if any) and reconnects on the next `maintain`, without
+    /// waiting out any backoff.
+    fn reconnect(&mut self, _now: Instant) {}
+
+    /// Switches to a different Discord application. Backends that hold a connection should
+    /// drop it and reconnect under the new ID on the next `maintain`.
+    fn set_application_id(&mut self, _application_id: &str, _now: Instant) {}
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) use mock::MockBackend;
+
+// Outside of this crate's own tests, only `DiscordHandler::with_mock_backend` uses this, which
+// never looks at what it recorded.
+#[cfg(any(test, feature = "test-support"))]
+#[cfg_attr(not(test), allow(dead_code))]
+mod mock {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Records every call made against it: `Some` for `set_activity`, and `None` for `clear`.
+    /// Clones share the same state, so a test can hold onto one while the event loop owns
+    /// another.
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct MockBackend {
+        calls: Arc<Mutex<Vec<Option<Activity>>>>,
+        dropped: Arc<AtomicBool>,
+        offline: Arc<AtomicBool>,
+        rejecting: Arc<AtomicBool>,
+        rate_limited: Arc<AtomicBool>,
+        unresponsive: Arc<AtomicBool>,
+        stalled: Arc<AtomicBool>,
+        handshake_failed: Arc<AtomicBool>,
+        application_ids: Arc<Mutex<Vec<String>>>,
+        max_reconnect_attempts: Arc<Mutex<Vec<Option<u32>>>>,
+        connect_timeout: Arc<Mutex<Duration>>,
+    }
+
+    impl MockBackend {
+        pub fn calls(&self) -> Vec<Option<Activity>> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        /// Every application ID that's been switched to, in order.
+        pub fn application_ids(&self) -> Vec<String> {
+            self.application_ids.lock().unwrap().clone()
+        }
+
+        /// Every reconnect attempt limit that's been set, in order.
+        pub fn max_reconnect_attempts(&self) -> Vec<Option<u32>> {
+            self.max_reconnect_attempts.lock().unwrap().clone()
+        }
+
+        /// Simulates the connection dropping. It's reestablished on the next `maintain`.
+        pub fn drop_connection(&self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+
+        /// Simulates Discord never finishing the initial handshake, which times out after
+        /// whatever `set_connect_timeout` was given. The next `maintain` after this is unset
+        /// retries it.
+        pub fn set_unresponsive(&self, unresponsive: bool) {
+            self.unresponsive.store(unresponsive, Ordering::SeqCst);
+        }
+
+        /// Simulates a write that never returns: `set_activity` and `clear` block for as long
+        /// as it's set.
+        pub fn set_stalled(&self, stalled: bool) {
+            self.stalled.store(stalled, Ordering::SeqCst);
+        }
+
+        /// Simulates Discord refusing every activity, for as long as it's set. Rejected ones
+        /// aren't recorded as calls.
+        pub fn set_rejecting(&self, rejecting: bool) {
+            self.rejecting.store(rejecting, Ordering::SeqCst);
+        }
+
+        /// Simulates Discord rate limiting every activity, for as long as it's set. As with
+        /// `set_rejecting`, these aren't recorded as calls.
+        pub fn set_rate_limited(&self, rate_limited: bool) {
+            self.rate_limited.store(rate_limited, Ordering::SeqCst);
+        }
+
+        /// Simulates Discord not running (or coming back), for as long as it's set.
+        pub fn set_offline(&self, offline: bool) {
+            self.offline.store(offline, Ordering::SeqCst);
+        }
+
+        fn wait_while_stalled(&self) {
+            while self.stalled.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    impl DiscordBackend for MockBackend {
+        fn set_activity(&mut self, activity: &Activity) -> Result<()> {
+            self.wait_while_stalled();
+
+            if self.rejecting.load(Ordering::SeqCst) {
+                return Err(crate::DiscordRPCError::Command("Activity rejected".to_string()));
+            }
+
+            if self.rate_limited.load(Ordering::SeqCst) {
+                return Err(crate::DiscordRPCError::RateLimited("You are being rate limited.".to_string()));
+            }
+
+            self.calls.lock().unwrap().push(Some(activity.clone()));
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<()> {
+            self.wait_while_stalled();
+            self.calls.lock().unwrap().push(None);
+            Ok(())
+        }
+
+        fn connect(&mut self, _now: Instant) -> Result<()> {
+            match self.unresponsive.load(Ordering::SeqCst) {
+                true => {
+                    self.handshake_failed.store(true, Ordering::SeqCst);
+                    Err(crate::DiscordRPCError::HandshakeTimeout(
+                        *self.connect_timeout.lock().unwrap(),
+                    ))
+                },
+
+                false => Ok(()),
+            }
+        }
+
+        fn maintain(&mut self, _now: Instant) -> bool {
+            if self.handshake_failed.load(Ordering::SeqCst) {
+                return !self.unresponsive.load(Ordering::SeqCst) && self.handshake_failed.swap(false, Ordering::SeqCst);
+            }
+
+            self.dropped.swap(false, Ordering::SeqCst)
+        }
+
+        fn is_connected(&self) -> bool {
+            !self.offline.load(Ordering::SeqCst) && !self.handshake_failed.load(Ordering::SeqCst)
+        }
+
+        fn reconnect(&mut self, _now: Instant) {
+            self.drop_connection();
+        }
+
+        fn set_max_reconnect_attempts(&mut self, max_attempts: Option<u32>, _now: Instant) {
+            self.max_reconnect_attempts.lock().unwrap().push(max_attempts);
+        }
+
+        fn set_connect_timeout(&mut self, timeout: Duration) {
+            *self.connect_timeout.lock().unwrap() = timeout;
+        }
+
+        fn set_application_id(&mut self, application_id: &str, _now: Instant) {
+            self.application_ids.lock().unwrap().push(application_id.to_string());
+            self.drop_connection();
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Maps Melee's internal character ids to display names and Discord asset keys.
+//!
+//! These are the *internal* ids (i.e, what's in player memory during a match), not the
+//! external ids used on the character select screen. Note that Sheik and Zelda have their
+//! own ids, as a transformation swaps the live character, and that the Ice Climbers are
+//! split into Popo and Nana.
+
+use crate::activity::SLIPPI_LOGO_ASSET;
+
+/// Returns the display name for an internal character id, if it's a playable character.
+pub fn character_name(id: u8) -> Option<&'static str> {
+    let name = match id {
+        0 => "Mario",
+        1 => "Fox",
+        2 => "Captain Falcon",
+        3 => "Donkey Kong",
+        4 => "Kirby",
+        5 => "Bowser",
+        6 => "Link",
+        7 => "Sheik",
+        8 => "Ness",
+        9 => "Peach",
+        10 | 11 => "Ice Climbers",
+        12 => "Pikachu",
+        13 => "Samus",
+        14 => "Yoshi",
+        15 => "Jigglypuff",
+        16 => "Mewtwo",
+        17 => "Luigi",
+        18 => "Marth",
+        19 => "Zelda",
+        20 => "Young Link",
+        21 => "Dr. Mario",
+        22 => "Falco",
+        23 => "Pichu",
+        24 => "Mr. Game & Watch",
+        25 => "Ganondorf",
+        26 => "Roy",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Returns the Discord asset key for an internal character id, if it's a playable character.
+pub fn character_asset_key(id: u8) -> Option<&'static str> {
+    let key = match id {
+        0 => "mario",
+        1 => "fox",
+        2 => "captain_falcon",
+        3 => "donkey_kong",
+        4 => "kirby",
+        5 => "bowser",
+        6 => "link",
+        7 => "sheik",
+        8 => "ness",
+        9 => "peach",
+        10 | 11 => "ice_climbers",
+        12 => "pikachu",
+        13 => "samus",
+        14 => "yoshi",
+        15 => "jigglypuff",
+        16 => "mewtwo",
+        17 => "luigi",
+        18 => "marth",
+        19 => "zelda",
+        20 => "young_link",
+        21 => "dr_mario",
+        22 => "falco",
+        23 => "pichu",
+        24 => "mr_game_and_watch",
+        25 => "ganondorf",
+        26 => "roy",
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Returns the Discord asset keys for each of a character's costumes, indexed by costume id.
+/// The default costume (id 0) uses the plain character asset.
+fn costume_asset_keys(id: u8) -> &'static [&'static str] {
+    match id {
+        0 => &["mario", "mario_1", "mario_2", "mario_3", "mario_4"],
+        1 => &["fox", "fox_1", "fox_2", "fox_3"],
+        2 => &[
+            "captain_falcon",
+            "captain_falcon_1",
+            "captain_falcon_2",
+            "captain_falcon_3",
+            "captain_falcon_4",
+            "captain_falcon_5",
+        ],
+        3 => &[
+            "donkey_kong",
+            "donkey_kong_1",
+            "donkey_kong_2",
+            "donkey_kong_3",
+            "donkey_kong_4",
+        ],
+        4 => &["kirby", "kirby_1", "kirby_2", "kirby_3", "kirby_4", "kirby_5"],
+        5 => &["bowser", "bowser_1", "bowser_2", "bowser_3"],
+        6 => &["link", "link_1", "link_2", "link_3", "link_4"],
+        7 => &["sheik", "sheik_1", "sheik_2", "sheik_3", "sheik_4"],
+        8 => &["ness", "ness_1", "ness_2", "ness_3"],
+        9 => &["peach", "peach_1", "peach_2", "peach_3", "peach_4"],
+        10 | 11 => &["ice_climbers", "ice_climbers_1", "ice_climbers_2", "ice_climbers_3"],
+        12 => &["pikachu", "pikachu_1", "pikachu_2", "pikachu_3"],
+        13 => &["samus", "samus_1", "samus_2", "samus_3", "samus_4"],
+        14 => &["yoshi", "yoshi_1", "yoshi_2", "yoshi_3", "yoshi_4", "yoshi_5"],
+        15 => &["jigglypuff", "jigglypuff_1", "jigglypuff_2", "jigglypuff_3", "jigglypuff_4"],
+        16 => &["mewtwo", "mewtwo_1", "mewtwo_2", "mewtwo_3"],
+        17 => &["luigi", "luigi_1", "luigi_2", "luigi_3"],
+        18 => &["marth", "marth_1", "marth_2", "marth_3", "marth_4"],
+        19 => &["zelda", "zelda_1", "zelda_2", "zelda_3", "zelda_4"],
+        20 => &["young_link", "young_link_1", "young_link_2", "young_link_3", "young_link_4"],
+        21 => &["dr_mario", "dr_mario_1", "dr_mario_2", "dr_mario_3", "dr_mario_4"],
+        22 => &["falco", "falco_1", "falco_2", "falco_3"],
+        23 => &["pichu", "pichu_1", "pichu_2", "pichu_3"],
+        24 => &[
+            "mr_game_and_watch",
+            "mr_game_and_watch_1",
+            "mr_game_and_watch_2",
+            "mr_game_and_watch_3",
+        ],
+        25 => &["ganondorf", "ganondorf_1", "ganondorf_2", "ganondorf_3", "ganondorf_4"],
+        26 => &["roy", "roy_1", "roy_2", "roy_3", "roy_4"],
+        _ => &[],
+    }
+}
+
+/// Returns the Discord asset key for a character in a specific costume (color). Costumes
+/// without their own asset fall back to the character's default one, and unknown characters
+/// to the Slippi logo.
+pub fn character_asset_key_with_costume(id: u8, costume_id: u8) -> &'static str {
+    let keys = costume_asset_keys(id);
+
+    keys.get(usize::from(costume_id))
+        .or_else(|| keys.first())
+        .copied()
+        .unwrap_or(SLIPPI_LOGO_ASSET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_valid_ids_have_names() {
+        for id in 0..=25 {
+            assert!(character_name(id).is_some(), "id {id} has no name");
+            assert!(character_asset_key(id).is_some(), "id {id} has no asset key");
+        }
+    }
+
+    #[test]
+    fn transformation_ids_resolve() {
+        assert_eq!(character_name(7), Some("Sheik"));
+        assert_eq!(character_name(19), Some("Zelda"));
+    }
+
+    #[test]
+    fn unknown_ids_are_none() {
+        // Master Hand, the Wireframes, etc are not playable.
+        assert_eq!(character_name(27), None);
+        assert_eq!(character_asset_key(255), None);
+    }
+
+    #[test]
+    fn costumes_fall_back_to_the_default_asset() {
+        assert_eq!(character_asset_key_with_costume(1, 0), "fox");
+        assert_eq!(character_asset_key_with_costume(1, 3), "fox_3");
+        assert_eq!(character_asset_key_with_costume(1, 4), "fox");
+        assert_eq!(character_asset_key_with_costume(2, 5), "captain_falcon_5");
+        assert_eq!(character_asset_key_with_costume(18, u8::MAX), "marth");
+        assert_eq!(character_asset_key_with_costume(200, 0), SLIPPI_LOGO_ASSET);
+    }
+}
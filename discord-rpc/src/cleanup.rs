@@ -0,0 +1,182 @@
+//! Best-effort clearing of the Discord activity when the process is asked to exit.
+//!
+//! Normally the activity is cleared when the `DiscordHandler` is dropped, but a process that's
+//! killed by a signal never gets that far. On Unix, `install` listens for SIGINT and SIGTERM on
+//! a dedicated thread; when one arrives, it clears the activity (waiting a bounded amount of
+//! time for that to go through) and then carries out the signal's default action, so the
+//! process still exits as it otherwise would have. Hosts that handle these signals themselves
+//! and don't want to exit straight away shouldn't install the hook.
+//!
+//! Once the handler shuts down, the listener is closed again and the signals are left to
+//! whoever else is handling them (or to their default action).
+//!
+//! Windows has no equivalent signals, so this is a no-op there. A killed process's named pipe
+//! is closed by the OS, which Discord notices and clears our activity for us, though not
+//! always immediately.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dolphin_integrations::Log;
+
+use crate::verbosity::{self, Verbosity};
+use crate::{Message, Result};
+
+/// State shared between a `DiscordHandler` and its cleanup hook.
+#[derive(Debug)]
+pub(crate) struct CleanupState {
+    installed: AtomicBool,
+
+    /// `None` once the handler has gone away, at which point there's nothing left to clean up.
+    tx: Mutex<Option<Sender<Message>>>,
+
+    /// The handler's, so the hook logs as it would.
+    verbosity: Verbosity,
+
+    /// For closing the signal listener once there's nothing left to clean up.
+    #[cfg(unix)]
+    signals: Mutex<Option<signal_hook::iterator::Handle>>,
+}
+
+impl CleanupState {
+    pub fn new(tx: Sender<Message>, verbosity: Verbosity) -> Arc<Self> {
+        Arc::new(Self {
+            installed: AtomicBool::new(false),
+            tx: Mutex::new(Some(tx)),
+            verbosity,
+            #[cfg(unix)]
+            signals: Mutex::new(None),
+        })
+    }
+
+    /// Called when the handler shuts down normally, after which the hook does nothing and the
+    /// signal listener (if it was installed) is closed.
+    pub fn detach(&self) {
+        if let Ok(mut tx) = self.tx.lock() {
+            tx.take();
+        }
+
+        #[cfg(unix)]
+        if let Some(signals) = self.signals.lock().ok().as_deref().and_then(Option::as_ref) {
+            signals.close();
+        }
+    }
+
+    /// Whether the signal listener has been installed, and not yet closed.
+    #[cfg(all(test, unix))]
+    pub fn is_listening(&self) -> bool {
+        let signals = self.signals.lock().unwrap();
+        signals.as_ref().is_some_and(|signals| !signals.is_closed())
+    }
+
+    /// Asks the background thread to clear the activity and stop, waiting up to `timeout` for it
+    /// to confirm. Does nothing if the handler (or its thread) is already gone. Returns whether
+    /// there was a thread to ask, even if it didn't answer in time.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub fn run(&self, timeout: Duration) -> bool {
+        let (ack_tx, ack_rx) = channel();
+
+        let sent = match self.tx.lock() {
+            Ok(tx) => tx.as_ref().is_some_and(|tx| tx.send(Message::ClearAndStop(ack_tx)).is_ok()),
+            Err(_) => false,
+        };
+
+        if !sent {
+            return false;
+        }
+
+        match ack_rx.recv_timeout(timeout) {
+            Ok(()) => true,
+
+            Err(RecvTimeoutError::Timeout) => {
+                verbosity::log!(
+                    self.verbosity,
+                    warn,
+                    target: Log::DiscordRPC,
+                    "Timed out clearing Discord activity during cleanup"
+                );
+
+                true
+            },
+
+            // The thread was already on its way out, and dropped the request with the rest.
+            Err(RecvTimeoutError::Disconnected) => false,
+        }
+    }
+}
+
+/// Installs the signal listener for `state`. Calling this more than once for the same state
+/// is a no-op.
+#[cfg(unix)]
+pub(crate) fn install(state: &Arc<CleanupState>, timeout: Duration) -> Result<()> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    use crate::DiscordRPCError::*;
+
+    if state.installed.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let mut signals = Signals::new([SIGINT, SIGTERM]).map_err(|e| {
+        state.installed.store(false, Ordering::SeqCst);
+        GenericIO(e)
+    })?;
+
+    if let Ok(mut handle) = state.signals.lock() {
+        *handle = Some(signals.handle());
+    }
+
+    let state = state.clone();
+
+    std::thread::Builder::new()
+        .name("SlippiDiscordRPCCleanup".to_string())
+        .spawn(move || {
+            // This ends without a signal once `detach` closes the listener.
+            if let Some(signal) = signals.forever().next() {
+                verbosity::log!(
+                    state.verbosity,
+                    info,
+                    target: Log::DiscordRPC,
+                    signal,
+                    "Received exit signal, clearing Discord activity"
+                );
+
+                // A handler that's already gone had nothing to clear, so the signal is left to
+                // whoever else is handling it, as it would be without us.
+                if !state.run(timeout) {
+                    return;
+                }
+
+                if let Err(e) = signal_hook::low_level::emulate_default_handler(signal) {
+                    verbosity::log!(
+                        state.verbosity,
+                        error,
+                        target: Log::DiscordRPC,
+                        error = ?e,
+                        "Failed to re-raise exit signal"
+                    );
+                }
+            }
+        })
+        .map_err(ThreadSpawn)?;
+
+    Ok(())
+}
+
+/// See the module docs: there's nothing to hook on this platform.
+#[cfg(not(unix))]
+pub(crate) fn install(state: &Arc<CleanupState>, _timeout: Duration) -> Result<()> {
+    if !state.installed.swap(true, Ordering::SeqCst) {
+        verbosity::log!(
+            state.verbosity,
+            info,
+            target: Log::DiscordRPC,
+            "Exit signal cleanup is not supported on this platform"
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! A small abstraction over "what time is it", so that timing-dependent logic (rate
+//! limiting, etc) can be tested without sleeping.
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time.
+pub(crate) trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The real, monotonic system clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The current wall-clock time as a Unix timestamp in milliseconds, which is what Discord
+/// expects for activity timestamps.
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a test can advance
+/// a clock that's been handed off to the background thread.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub(crate) struct ManualClock {
+    now: std::sync::Arc<std::sync::Mutex<Instant>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            now: std::sync::Arc::new(std::sync::Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,1022 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use dolphin_integrations::Log;
+
+use crate::activity::SLIPPI_LOGO_ASSET;
+use crate::template::DEFAULT_DETAILS_TEMPLATE;
+#[cfg(feature = "discord")]
+use crate::{
+    characters::{character_asset_key, character_name},
+    stages::{stage_abbreviation, stage_asset_key, stage_name},
+    verbosity,
+    verbosity::Verbosity,
+    MatchmakingMode,
+};
+use crate::{ActivityType, DiscordRPCError, Result};
+use DiscordRPCError::*;
+
+/// The Discord application that presence is reported under by default.
+pub const DEFAULT_APPLICATION_ID: &str = "1096595344600604772";
+
+/// Controls how much of the current match is revealed in the presence.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyMode {
+    /// Show everything we know about the match.
+    #[default]
+    Full,
+
+    /// Show the local player's character, but leave out who they're playing against.
+    HideOpponent,
+
+    /// Only show that Slippi is running, with no match details at all.
+    Minimal,
+}
+
+/// A callback for surfacing short, user-facing status messages (e.g, "Connected to Discord")
+/// via Dolphin's OSD. It's invoked from the background thread.
+#[derive(Clone)]
+pub struct OsdCallback(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl OsdCallback {
+    pub fn new(callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    /// Passes `message` on to the callback.
+    pub fn call(&self, message: &str) {
+        (self.0)(message)
+    }
+}
+
+impl fmt::Debug for OsdCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OsdCallback(..)")
+    }
+}
+
+/// Configuration for the Discord handler.
+///
+/// `Config::default()` is fine for most callers; use `Config::builder()` to construct one
+/// with validation, or `Config::from_toml_str` to load one from a settings file.
+///
+/// Unknown fields are ignored when loading, so that older builds can read settings files
+/// written by newer ones.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The Discord application (client) ID used during the IPC handshake. Forks and test
+    /// setups can point this at their own application; changing it reconnects.
+    pub application_id: String,
+
+    /// The minimum amount of time between presence updates sent to Discord. Given in whole
+    /// seconds in settings files.
+    #[serde(with = "secs")]
+    pub update_interval: Duration,
+
+    /// How long the activity can go unchanged before it's resent, as some Discord clients
+    /// drop IPC connections that go quiet for too long. Zero disables this. Given in whole
+    /// seconds in settings files.
+    #[serde(with = "secs")]
+    pub heartbeat_interval: Duration,
+
+    /// Whether to slow updates down (to at most one every 15 seconds) while the presence
+    /// hasn't changed in a while, using `update_interval` only while it's busy.
+    pub adaptive_interval: bool,
+
+    /// How long the background thread waits without any messages, while not connected to
+    /// Discord, before assuming its handler has been leaked and exiting. Zero (the default)
+    /// disables this, since a player idling in menus sends nothing either. Once the thread has
+    /// exited, the handler's methods return `ChannelClosed`. Given in whole seconds in settings
+    /// files.
+    #[serde(with = "secs")]
+    pub idle_timeout: Duration,
+
+    /// How many times in a row to try reconnecting to Discord before giving up, or `None` to
+    /// keep trying forever. Once given up, the handler stays disconnected until a new config
+    /// is applied (or `DiscordHandler::reconnect` is called).
+    pub max_reconnect_attempts: Option<u32>,
+
+    /// How long to wait on the Discord client to answer the handshake (and the subscriptions
+    /// that follow it) before giving up on that attempt, and retrying later as with any other
+    /// failed connection. Given in whole seconds in settings files.
+    ///
+    /// The background thread can't stop in the middle of an attempt, so this also holds up
+    /// shutting down. The default is kept below how long `DiscordHandler::shutdown` waits.
+    #[serde(with = "secs")]
+    pub connect_timeout: Duration,
+
+    /// Whether to show an elapsed (or, for timed matches, remaining) match timer.
+    pub show_timer: bool,
+
+    /// Whether to show which characters are being played, in text and as the large image.
+    pub show_characters: bool,
+
+    /// Whether to show which stage the match is on.
+    pub show_stage: bool,
+
+    /// Whether to show stock counts alongside the matchup.
+    pub show_stocks: bool,
+
+    /// Whether to show each player's damage percent alongside the matchup, e.g for crew
+    /// battles where everyone's on their last stock. This can be on alongside `show_stocks`.
+    pub show_percents: bool,
+
+    /// Whether to show the kind of match (e.g, "Singles") and how it came about (e.g,
+    /// "Unranked"). A player's rank is still shown for ranked matches.
+    pub show_mode: bool,
+
+    /// Whether to show matches against a CPU (as "Practicing vs CPU"). When off, the presence
+    /// is cleared for them instead.
+    pub show_cpu_matches: bool,
+
+    /// The template for the activity details line while in a match. See `render_template`
+    /// for the supported placeholders.
+    pub details_template: String,
+
+    /// The language to show character, stage and mode names in, e.g "ja" or "es-MX". Names
+    /// without a translation (and unknown locales) are shown in English.
+    ///
+    /// Only those names are translated: the rest of the presence text, e.g "In a lobby" or
+    /// "Won as Fox!", is always in English (apart from a `details_template`, which is shown as
+    /// written).
+    pub locale: String,
+
+    /// How much of the current match to reveal.
+    pub privacy_mode: PrivacyMode,
+
+    /// Whether to show only what kind of thing is going on (e.g, "Playing Singles"), with the
+    /// Slippi logo and nothing that could identify anyone: no characters, connect codes,
+    /// names, ranks or buttons. This is for tournament streams, and overrides
+    /// `privacy_mode`.
+    pub stream_safe: bool,
+
+    /// How Discord introduces the activity. If unset, ranked matches are "Competing in" and
+    /// everything else is "Playing".
+    pub force_activity_type: Option<ActivityType>,
+
+    /// Whether to offer a Discord "Spectate" button while in a match that can be spectated.
+    pub enable_spectate: bool,
+
+    /// Whether to show an idle presence between matches. If disabled, the activity is cleared
+    /// instead, so that Slippi only shows up in Discord while actually playing.
+    pub show_idle_presence: bool,
+
+    /// Whether to show the elapsed in-game time (as counted by Melee's frame counter) in the
+    /// activity state while in a match.
+    pub show_ingame_time: bool,
+
+    /// How long a `GameEvent` replaces the activity state for. Zero disables these. Given in
+    /// whole seconds in settings files.
+    #[serde(with = "secs")]
+    pub highlight_duration: Duration,
+
+    /// How long the presence is shown in full after it moves on to something new (e.g, from
+    /// menus to a match), after which it's condensed down to the main part of each line, e.g
+    /// "Fox vs Marth" rather than "Fox vs Marth · 2:35 · Session: 7W-3L". The idea being that
+    /// whoever's going to look has most likely done so by then. Zero (the default) always
+    /// shows it in full. Given in whole seconds in settings files.
+    #[serde(with = "secs")]
+    pub verbose_when_recent: Duration,
+
+    /// A link to the player's Slippi profile, shown as a "View Profile" button.
+    ///
+    /// Discord doesn't allow buttons alongside "Join"/"Spectate", so buttons are left out
+    /// whenever those are offered (i.e, once a connect code is known).
+    pub profile_url: Option<String>,
+
+    /// A second button to show, as a (label, url) pair. The same caveat as `profile_url`
+    /// applies.
+    pub extra_button: Option<(String, String)>,
+
+    /// The port (0-3) whose player is shown as "you", i.e in the large image and first in the
+    /// matchup. If unset, this is the Slippi user's port for online matches, or port 0.
+    pub primary_port: Option<u8>,
+
+    /// Discord asset keys to show in place of the built-in ones, for applications with their
+    /// own art uploaded. These are keyed by what they replace:
+    ///
+    /// - `char:` and a character's English name or built-in asset key, e.g `char:Fox` or
+    ///   `char:captain_falcon`.
+    /// - `stage:` and a stage's English name or built-in asset key, or for legal stages its
+    ///   abbreviation, e.g `stage:Final Destination`, `stage:pokemon_stadium` or `stage:FD`.
+    /// - `mode:` and one of `Ranked`, `Unranked`, `Direct`, `Teams` or `Offline`.
+    ///
+    /// Keys that don't name anything are ignored. If two keys name the same thing, the one
+    /// using its English name wins.
+    pub asset_overrides: HashMap<String, String>,
+
+    /// The large image to show when there's no art for what would otherwise be shown, e.g a
+    /// character we don't recognize. Leave this empty to show no image at all in that case.
+    pub fallback_large_image: String,
+
+    /// The running Slippi version (e.g, "Slippi 3.4.0"), to show as the small image's text
+    /// when there's nothing else there, so that builds can be told apart in screenshots. A
+    /// player's rank (or anything else shown in the small image) takes precedence.
+    pub version_string: Option<String>,
+
+    /// A name for telling this handler's logs (and background thread) apart from another's,
+    /// e.g when several are embedded in one process. This is only read when the handler is
+    /// created.
+    pub instance_label: Option<String>,
+
+    /// Whether to save what the presence was showing when the handler goes away, so that one
+    /// created shortly afterwards (e.g, after restarting Dolphin) carries on from it. Only one
+    /// run by the same user, with the same `application_id` and `instance_label`, does. This
+    /// is only read when the handler is created and dropped.
+    pub persist_presence: bool,
+
+    /// Logs each activity (pretty-printed, at info level) instead of sending it to Discord,
+    /// for checking what the presence would show. Updates still wait on the connection and
+    /// update interval as they normally would.
+    pub dry_run: bool,
+
+    /// Called on connection status changes and when a new config is applied.
+    ///
+    /// The callback must not block for long, as it holds up the background thread. It's fine
+    /// for it to call back into (or drop) the `DiscordHandler`.
+    ///
+    /// A config update without a callback (e.g, one loaded from a watched file) keeps the one
+    /// that's already set.
+    #[serde(skip)]
+    pub osd_callback: Option<OsdCallback>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            application_id: DEFAULT_APPLICATION_ID.to_string(),
+            update_interval: Duration::from_secs(4),
+            heartbeat_interval: Duration::from_secs(60),
+            adaptive_interval: true,
+            idle_timeout: Duration::ZERO,
+            max_reconnect_attempts: None,
+            connect_timeout: Duration::from_secs(1),
+            show_timer: true,
+            show_characters: true,
+            show_stage: true,
+            show_stocks: true,
+            show_percents: false,
+            show_mode: true,
+            show_cpu_matches: false,
+            details_template: DEFAULT_DETAILS_TEMPLATE.to_string(),
+            locale: "en".to_string(),
+            privacy_mode: PrivacyMode::default(),
+            stream_safe: false,
+            force_activity_type: None,
+            enable_spectate: false,
+            show_idle_presence: true,
+            show_ingame_time: false,
+            highlight_duration: Duration::from_secs(5),
+            verbose_when_recent: Duration::ZERO,
+            profile_url: None,
+            extra_button: None,
+            primary_port: None,
+            asset_overrides: HashMap::new(),
+            fallback_large_image: SLIPPI_LOGO_ASSET.to_string(),
+            version_string: None,
+            instance_label: None,
+            persist_presence: false,
+            dry_run: false,
+            osd_callback: None,
+        }
+    }
+}
+
+impl Config {
+    /// The smallest update interval we'll accept. Discord itself rate limits activity updates,
+    /// so anything faster just gets dropped on the floor.
+    pub const MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// The smallest update interval that stays clear of Discord's rate limit (around five
+    /// updates every 20 seconds). Anything between this and `MIN_UPDATE_INTERVAL` is accepted,
+    /// but updates are still only sent this often.
+    pub const SAFE_UPDATE_INTERVAL: Duration = Duration::from_secs(4);
+
+    /// The version of the settings file format that `to_toml_string` writes. Files without a
+    /// `version` predate it, and count as version 1.
+    pub const VERSION: u32 = 2;
+
+    /// Returns a builder for a validated `Config`.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Parses and validates a `Config` from TOML, migrating it from an older version of the
+    /// format if need be. Anything left out falls back to the default.
+    pub fn from_toml_str(source: &str) -> Result<Config> {
+        let config = Self::migrate(source.parse()?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Serializes this `Config` to TOML, in the format `from_toml_str` expects. The OSD
+    /// callback isn't included.
+    pub fn to_toml_string(&self) -> Result<String> {
+        Ok(format!("version = {}\n{}", Self::VERSION, toml::to_string(self)?))
+    }
+
+    /// Upgrades a parsed settings file from whichever version of the format it was written in
+    /// to the current `Config`, without validating it.
+    ///
+    /// Version 1 files are missing every setting added since, which take their defaults. Files
+    /// from a newer version than this one are loaded as best we can, ignoring whatever we
+    /// don't know about.
+    pub fn migrate(mut raw: toml::Table) -> Result<Config> {
+        let version = match raw.remove("version") {
+            None => 1,
+            // Versions started at 1, so 0 can't have come from any `to_toml_string`.
+            Some(toml::Value::Integer(version)) => u32::try_from(version)
+                .ok()
+                .filter(|&version| version > 0)
+                .ok_or_else(|| InvalidConfig(format!("version must be a positive number, got {version}")))?,
+            Some(other) => {
+                return Err(InvalidConfig(format!("version must be a number, got a {}", other.type_str())));
+            },
+        };
+
+        if version > Self::VERSION {
+            tracing::warn!(
+                target: Log::DiscordRPC,
+                version,
+                "Settings file is from a newer version, some settings may be ignored"
+            );
+        }
+
+        Ok(raw.try_into()?)
+    }
+
+    /// The interval that updates are actually held to: `update_interval`, raised to
+    /// `SAFE_UPDATE_INTERVAL` if it's set any lower, as Discord would only throttle us. The
+    /// configured value itself is left alone, so that it's written back out as it was set.
+    pub(crate) fn effective_update_interval(&self) -> Duration {
+        // Handlers under test turn rate limiting off altogether, which nothing else can.
+        #[cfg(test)]
+        if self.update_interval.is_zero() {
+            return Duration::ZERO;
+        }
+
+        self.update_interval.max(Config::SAFE_UPDATE_INTERVAL)
+    }
+
+    /// Warns if `effective_update_interval` is any longer than what was asked for.
+    #[cfg(feature = "discord")]
+    pub(crate) fn warn_if_update_interval_is_raised(&self, verbosity: &Verbosity) {
+        let using = self.effective_update_interval();
+
+        if using != self.update_interval {
+            verbosity::log!(
+                verbosity,
+                warn,
+                target: Log::DiscordRPC,
+                requested = ?self.update_interval,
+                ?using,
+                "update_interval is below what Discord allows, raising it"
+            );
+        }
+    }
+
+    /// Drops any button whose URL isn't http(s), as Discord would reject the entire activity
+    /// over it.
+    #[cfg(feature = "discord")]
+    pub(crate) fn drop_invalid_buttons(&mut self, verbosity: &Verbosity) {
+        if let Some(url) = self.profile_url.as_deref().filter(|url| !is_http_url(url)) {
+            verbosity::log!(verbosity, warn, target: Log::DiscordRPC, url, "Ignoring invalid profile URL");
+            self.profile_url = None;
+        }
+
+        if let Some((_, url)) = self.extra_button.as_ref().filter(|(_, url)| !is_http_url(url)) {
+            verbosity::log!(
+                verbosity,
+                warn,
+                target: Log::DiscordRPC,
+                url,
+                "Ignoring extra button with an invalid URL"
+            );
+            self.extra_button = None;
+        }
+    }
+
+    /// Drops any asset override whose key doesn't name a character, stage or mode, as it
+    /// would never be used, and rewrites the rest to use the English name that presence looks
+    /// them up by.
+    #[cfg(feature = "discord")]
+    pub(crate) fn drop_unknown_asset_overrides(&mut self, verbosity: &Verbosity) {
+        for (key, asset) in std::mem::take(&mut self.asset_overrides) {
+            let Some(canonical) = canonical_asset_override_key(&key) else {
+                verbosity::log!(
+                    verbosity,
+                    debug,
+                    target: Log::DiscordRPC,
+                    key,
+                    "Ignoring unknown asset override"
+                );
+                continue;
+            };
+
+            if canonical == key {
+                self.asset_overrides.insert(canonical, asset);
+            } else {
+                self.asset_overrides.entry(canonical).or_insert(asset);
+            }
+        }
+    }
+
+    /// The asset override for `key` (e.g, "char:Fox"), if there is one.
+    pub(crate) fn asset_override(&self, key: &str) -> Option<String> {
+        self.asset_overrides.get(key).cloned()
+    }
+
+    /// Checks everything that `ConfigBuilder::build` and `from_toml_str` would reject.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.application_id.trim().is_empty() {
+            return Err(InvalidConfig("application_id must not be empty".to_string()));
+        }
+
+        // Discord IDs are "snowflakes", which are always unsigned 64-bit integers.
+        if self.application_id.parse::<u64>().is_err() {
+            return Err(InvalidConfig(format!(
+                "application_id must be a Discord snowflake (a 64-bit number), got {:?}",
+                self.application_id
+            )));
+        }
+
+        // It's zero under test to turn rate limiting off (see `effective_update_interval`).
+        let rate_limiting_off = cfg!(test) && self.update_interval.is_zero();
+
+        if self.update_interval < Config::MIN_UPDATE_INTERVAL && !rate_limiting_off {
+            return Err(InvalidConfig(format!(
+                "update_interval must be at least {:?}, got {:?}",
+                Config::MIN_UPDATE_INTERVAL,
+                self.update_interval
+            )));
+        }
+
+        if self.connect_timeout.is_zero() {
+            return Err(InvalidConfig("connect_timeout must not be zero".to_string()));
+        }
+
+        if let Some(port) = self.primary_port.filter(|port| *port > 3) {
+            return Err(InvalidConfig(format!("primary_port must be between 0 and 3, got {port}")));
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `url` is an absolute http(s) URL.
+fn is_http_url(url: &str) -> bool {
+    ["https://", "http://"]
+        .iter()
+        .any(|scheme| url.strip_prefix(scheme).is_some_and(|rest| !rest.is_empty()))
+}
+
+/// The `asset_overrides` key that presence would look `key` up by, if it names anything.
+#[cfg(feature = "discord")]
+fn canonical_asset_override_key(key: &str) -> Option<String> {
+    match key.split_once(':')? {
+        ("char", name) => (0..=u8::MAX)
+            .find(|&id| character_name(id) == Some(name) || character_asset_key(id) == Some(name))
+            .and_then(character_name)
+            .map(|name| format!("char:{name}")),
+
+        ("stage", name) => (0..=u8::MAX)
+            .map(u16::from)
+            .find(|&id| [stage_name(id), stage_asset_key(id), stage_abbreviation(id)].contains(&Some(name)))
+            .and_then(stage_name)
+            .map(|name| format!("stage:{name}")),
+
+        ("mode", name) => MatchmakingMode::ALL
+            .iter()
+            .find(|mode| mode.name() == name)
+            .map(|mode| format!("mode:{}", mode.name())),
+
+        _ => None,
+    }
+}
+
+/// (De)serializes a `Duration` as a whole number of seconds.
+mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        duration.as_secs().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+/// Builds a `Config`, falling back to the defaults for anything that isn't set.
+///
+/// The application ID is required.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigBuilder {
+    application_id: Option<String>,
+    update_interval: Option<Duration>,
+    heartbeat_interval: Option<Duration>,
+    adaptive_interval: Option<bool>,
+    idle_timeout: Option<Duration>,
+    max_reconnect_attempts: Option<u32>,
+    connect_timeout: Option<Duration>,
+    show_timer: Option<bool>,
+    show_characters: Option<bool>,
+    show_stage: Option<bool>,
+    show_stocks: Option<bool>,
+    show_percents: Option<bool>,
+    show_mode: Option<bool>,
+    show_cpu_matches: Option<bool>,
+    details_template: Option<String>,
+    locale: Option<String>,
+    privacy_mode: Option<PrivacyMode>,
+    stream_safe: Option<bool>,
+    force_activity_type: Option<ActivityType>,
+    enable_spectate: Option<bool>,
+    show_idle_presence: Option<bool>,
+    show_ingame_time: Option<bool>,
+    highlight_duration: Option<Duration>,
+    verbose_when_recent: Option<Duration>,
+    profile_url: Option<String>,
+    extra_button: Option<(String, String)>,
+    primary_port: Option<u8>,
+    asset_overrides: Option<HashMap<String, String>>,
+    fallback_large_image: Option<String>,
+    version_string: Option<String>,
+    instance_label: Option<String>,
+    persist_presence: Option<bool>,
+    dry_run: Option<bool>,
+    osd_callback: Option<OsdCallback>,
+}
+
+impl ConfigBuilder {
+    pub fn application_id(mut self, application_id: impl Into<String>) -> Self {
+        self.application_id = Some(application_id.into());
+        self
+    }
+
+    pub fn update_interval(mut self, update_interval: Duration) -> Self {
+        self.update_interval = Some(update_interval);
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = Some(heartbeat_interval);
+        self
+    }
+
+    pub fn adaptive_interval(mut self, adaptive_interval: bool) -> Self {
+        self.adaptive_interval = Some(adaptive_interval);
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn max_reconnect_attempts(mut self, max_reconnect_attempts: u32) -> Self {
+        self.max_reconnect_attempts = Some(max_reconnect_attempts);
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn show_timer(mut self, show_timer: bool) -> Self {
+        self.show_timer = Some(show_timer);
+        self
+    }
+
+    pub fn show_characters(mut self, show_characters: bool) -> Self {
+        self.show_characters = Some(show_characters);
+        self
+    }
+
+    pub fn show_stage(mut self, show_stage: bool) -> Self {
+        self.show_stage = Some(show_stage);
+        self
+    }
+
+    pub fn show_stocks(mut self, show_stocks: bool) -> Self {
+        self.show_stocks = Some(show_stocks);
+        self
+    }
+
+    pub fn show_percents(mut self, show_percents: bool) -> Self {
+        self.show_percents = Some(show_percents);
+        self
+    }
+
+    pub fn show_mode(mut self, show_mode: bool) -> Self {
+        self.show_mode = Some(show_mode);
+        self
+    }
+
+    pub fn show_cpu_matches(mut self, show_cpu_matches: bool) -> Self {
+        self.show_cpu_matches = Some(show_cpu_matches);
+        self
+    }
+
+    pub fn details_template(mut self, details_template: impl Into<String>) -> Self {
+        self.details_template = Some(details_template.into());
+        self
+    }
+
+    pub fn locale(mut self, locale: impl Into<String>) -> Self {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    pub fn privacy_mode(mut self, privacy_mode: PrivacyMode) -> Self {
+        self.privacy_mode = Some(privacy_mode);
+        self
+    }
+
+    pub fn stream_safe(mut self, stream_safe: bool) -> Self {
+        self.stream_safe = Some(stream_safe);
+        self
+    }
+
+    pub fn force_activity_type(mut self, activity_type: ActivityType) -> Self {
+        self.force_activity_type = Some(activity_type);
+        self
+    }
+
+    pub fn enable_spectate(mut self, enable_spectate: bool) -> Self {
+        self.enable_spectate = Some(enable_spectate);
+        self
+    }
+
+    pub fn show_idle_presence(mut self, show_idle_presence: bool) -> Self {
+        self.show_idle_presence = Some(show_idle_presence);
+        self
+    }
+
+    pub fn show_ingame_time(mut self, show_ingame_time: bool) -> Self {
+        self.show_ingame_time = Some(show_ingame_time);
+        self
+    }
+
+    pub fn highlight_duration(mut self, highlight_duration: Duration) -> Self {
+        self.highlight_duration = Some(highlight_duration);
+        self
+    }
+
+    pub fn verbose_when_recent(mut self, verbose_when_recent: Duration) -> Self {
+        self.verbose_when_recent = Some(verbose_when_recent);
+        self
+    }
+
+    pub fn profile_url(mut self, profile_url: impl Into<String>) -> Self {
+        self.profile_url = Some(profile_url.into());
+        self
+    }
+
+    pub fn extra_button(mut self, label: impl Into<String>, url: impl Into<String>) -> Self {
+        self.extra_button = Some((label.into(), url.into()));
+        self
+    }
+
+    pub fn primary_port(mut self, primary_port: u8) -> Self {
+        self.primary_port = Some(primary_port);
+        self
+    }
+
+    /// Overrides the asset shown for `key`; see `Config::asset_overrides`.
+    pub fn asset_override(mut self, key: impl Into<String>, asset_key: impl Into<String>) -> Self {
+        self.asset_overrides
+            .get_or_insert_with(HashMap::new)
+            .insert(key.into(), asset_key.into());
+        self
+    }
+
+    pub fn fallback_large_image(mut self, fallback_large_image: impl Into<String>) -> Self {
+        self.fallback_large_image = Some(fallback_large_image.into());
+        self
+    }
+
+    pub fn version_string(mut self, version_string: impl Into<String>) -> Self {
+        self.version_string = Some(version_string.into());
+        self
+    }
+
+    pub fn instance_label(mut self, instance_label: impl Into<String>) -> Self {
+        self.instance_label = Some(instance_label.into());
+        self
+    }
+
+    pub fn persist_presence(mut self, persist_presence: bool) -> Self {
+        self.persist_presence = Some(persist_presence);
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    pub fn osd_callback(mut self, callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.osd_callback = Some(OsdCallback::new(callback));
+        self
+    }
+
+    /// Validates the configured values and produces a `Config`. An `update_interval` that's
+    /// valid but below `Config::SAFE_UPDATE_INTERVAL` is kept, but updates are held to the
+    /// latter (see `Config::effective_update_interval`).
+    pub fn build(self) -> Result<Config> {
+        let defaults = Config::default();
+
+        let application_id = self
+            .application_id
+            .ok_or_else(|| InvalidConfig("application_id is required".to_string()))?;
+
+        let config = Config {
+            application_id,
+            update_interval: self.update_interval.unwrap_or(defaults.update_interval),
+            heartbeat_interval: self.heartbeat_interval.unwrap_or(defaults.heartbeat_interval),
+            adaptive_interval: self.adaptive_interval.unwrap_or(defaults.adaptive_interval),
+            idle_timeout: self.idle_timeout.unwrap_or(defaults.idle_timeout),
+            max_reconnect_attempts: self.max_reconnect_attempts,
+            connect_timeout: self.connect_timeout.unwrap_or(defaults.connect_timeout),
+            show_timer: self.show_timer.unwrap_or(defaults.show_timer),
+            show_characters: self.show_characters.unwrap_or(defaults.show_characters),
+            show_stage: self.show_stage.unwrap_or(defaults.show_stage),
+            show_stocks: self.show_stocks.unwrap_or(defaults.show_stocks),
+            show_percents: self.show_percents.unwrap_or(defaults.show_percents),
+            show_mode: self.show_mode.unwrap_or(defaults.show_mode),
+            show_cpu_matches: self.show_cpu_matches.unwrap_or(defaults.show_cpu_matches),
+            details_template: self.details_template.unwrap_or(defaults.details_template),
+            locale: self.locale.unwrap_or(defaults.locale),
+            privacy_mode: self.privacy_mode.unwrap_or(defaults.privacy_mode),
+            stream_safe: self.stream_safe.unwrap_or(defaults.stream_safe),
+            force_activity_type: self.force_activity_type,
+            enable_spectate: self.enable_spectate.unwrap_or(defaults.enable_spectate),
+            show_idle_presence: self.show_idle_presence.unwrap_or(defaults.show_idle_presence),
+            show_ingame_time: self.show_ingame_time.unwrap_or(defaults.show_ingame_time),
+            highlight_duration: self.highlight_duration.unwrap_or(defaults.highlight_duration),
+            verbose_when_recent: self.verbose_when_recent.unwrap_or(defaults.verbose_when_recent),
+            profile_url: self.profile_url,
+            extra_button: self.extra_button,
+            primary_port: self.primary_port,
+            asset_overrides: self.asset_overrides.unwrap_or(defaults.asset_overrides),
+            fallback_large_image: self.fallback_large_image.unwrap_or(defaults.fallback_large_image),
+            version_string: self.version_string,
+            instance_label: self.instance_label,
+            persist_presence: self.persist_presence.unwrap_or(defaults.persist_presence),
+            dry_run: self.dry_run.unwrap_or(defaults.dry_run),
+            osd_callback: self.osd_callback,
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_fills_in_defaults() {
+        let config = Config::builder()
+            .application_id("1234")
+            .show_timer(false)
+            .privacy_mode(PrivacyMode::Minimal)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.application_id, "1234");
+        assert!(!config.show_timer);
+        assert_eq!(config.privacy_mode, PrivacyMode::Minimal);
+        assert_eq!(config.update_interval, Config::default().update_interval);
+        assert_eq!(config.details_template, DEFAULT_DETAILS_TEMPLATE);
+        assert!(config.osd_callback.is_none());
+    }
+
+    #[test]
+    fn builder_requires_an_application_id() {
+        assert!(matches!(Config::builder().build(), Err(InvalidConfig(_))));
+        assert!(matches!(
+            Config::builder().application_id("  ").build(),
+            Err(InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn builder_requires_a_snowflake_application_id() {
+        for id in ["slippi", "-1", "12 34", "18446744073709551616"] {
+            assert!(matches!(Config::builder().application_id(id).build(), Err(InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn builder_rejects_out_of_range_ports() {
+        let builder = Config::builder().application_id("1234");
+
+        assert_eq!(builder.clone().primary_port(3).build().unwrap().primary_port, Some(3));
+        assert!(matches!(builder.primary_port(4).build(), Err(InvalidConfig(_))));
+    }
+
+    #[test]
+    fn builder_rejects_a_zero_connect_timeout() {
+        let builder = Config::builder().application_id("1234");
+
+        assert_eq!(builder.clone().build().unwrap().connect_timeout, Duration::from_secs(1));
+        assert!(matches!(
+            builder.connect_timeout(Duration::ZERO).build(),
+            Err(InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn builder_rejects_short_intervals() {
+        let result = Config::builder()
+            .application_id("1234")
+            .update_interval(Duration::from_millis(500))
+            .build();
+
+        assert!(matches!(result, Err(InvalidConfig(_))));
+
+        // Short but valid intervals are raised to what Discord allows instead.
+        let config = Config::builder()
+            .application_id("1234")
+            .update_interval(Duration::from_secs(1))
+            .build()
+            .unwrap();
+
+        assert_eq!(config.update_interval, Duration::from_secs(1));
+        assert_eq!(config.effective_update_interval(), Config::SAFE_UPDATE_INTERVAL);
+    }
+
+    #[test]
+    fn short_intervals_are_raised_to_discords_limit() {
+        let mut config = Config {
+            update_interval: Duration::from_secs(2),
+            ..Default::default()
+        };
+
+        assert_eq!(config.effective_update_interval(), Config::SAFE_UPDATE_INTERVAL);
+
+        // Longer ones are left alone, so the adaptive interval still has room to speed up.
+        config.update_interval = Duration::from_secs(10);
+        assert_eq!(config.effective_update_interval(), Duration::from_secs(10));
+
+        // What was asked for is what's written back out.
+        let config = Config::from_toml_str("update_interval = 1").unwrap();
+        assert_eq!(config.update_interval, Duration::from_secs(1));
+        assert_eq!(config.effective_update_interval(), Config::SAFE_UPDATE_INTERVAL);
+
+        let written = Config::from_toml_str(&config.to_toml_string().unwrap()).unwrap();
+        assert_eq!(written.update_interval, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn malformed_toml_is_a_parse_error() {
+        let result = Config::from_toml_str("update_interval = [");
+        assert!(matches!(result, Err(ConfigParse(_))));
+    }
+
+    #[test]
+    fn empty_toml_is_the_default_config() {
+        let config = Config::from_toml_str("").unwrap();
+        assert_eq!(format!("{config:?}"), format!("{:?}", Config::default()));
+    }
+
+    #[test]
+    fn toml_roundtrips() {
+        let config = Config::builder()
+            .application_id("1234")
+            .update_interval(Duration::from_secs(10))
+            .details_template("{p1_char} on {stage}")
+            .privacy_mode(PrivacyMode::HideOpponent)
+            .enable_spectate(true)
+            .osd_callback(|_| {})
+            .build()
+            .unwrap();
+
+        let parsed = Config::from_toml_str(&config.to_toml_string().unwrap()).unwrap();
+
+        assert_eq!(parsed.application_id, "1234");
+        assert_eq!(parsed.update_interval, Duration::from_secs(10));
+        assert_eq!(parsed.details_template, "{p1_char} on {stage}");
+        assert_eq!(parsed.privacy_mode, PrivacyMode::HideOpponent);
+        assert!(parsed.enable_spectate);
+        assert!(parsed.osd_callback.is_none());
+    }
+
+    #[test]
+    fn toml_migrates_from_older_versions() {
+        let v1 = "application_id = \"1234\"\nupdate_interval = 8\nshow_timer = false\n";
+        let config = Config::from_toml_str(v1).unwrap();
+
+        let expected = Config {
+            application_id: "1234".to_string(),
+            update_interval: Duration::from_secs(8),
+            show_timer: false,
+            ..Default::default()
+        };
+
+        let current = config.to_toml_string().unwrap();
+        assert!(current.starts_with("version = 2\n"), "{current}");
+        assert_eq!(current, expected.to_toml_string().unwrap());
+
+        // Newer files load as best they can.
+        let future = Config::from_toml_str("version = 99\nshow_timer = false\npacked_setting = [1, 2]").unwrap();
+        assert!(!future.show_timer);
+
+        assert!(matches!(Config::from_toml_str("version = \"two\""), Err(InvalidConfig(_))));
+        assert!(matches!(Config::from_toml_str("version = -1"), Err(InvalidConfig(_))));
+    }
+
+    #[test]
+    fn toml_rejects_version_zero() {
+        match Config::from_toml_str(
+            "version = 0
+show_timer = false",
+        ) {
+            Err(InvalidConfig(message)) => assert_eq!(message, "version must be a positive number, got 0"),
+            other => panic!("Expected an invalid config, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn toml_ignores_unknown_and_missing_fields() {
+        let config = Config::from_toml_str("show_timer = false\nsome_future_setting = 3").unwrap();
+
+        assert!(!config.show_timer);
+        assert_eq!(config.application_id, DEFAULT_APPLICATION_ID);
+        assert_eq!(config.update_interval, Config::default().update_interval);
+    }
+
+    #[cfg(feature = "discord")]
+    #[test]
+    fn invalid_button_urls_are_dropped() {
+        let mut config = Config::builder()
+            .application_id("1234")
+            .profile_url("slippi.gg/user/abcd-123")
+            .extra_button("Stream", "https://twitch.tv/slippi")
+            .build()
+            .unwrap();
+
+        config.drop_invalid_buttons(&Verbosity::default());
+        assert_eq!(config.profile_url, None);
+        assert!(config.extra_button.is_some());
+
+        config.extra_button = Some(("Stream".to_string(), "javascript:alert(1)".to_string()));
+        config.drop_invalid_buttons(&Verbosity::default());
+        assert_eq!(config.extra_button, None);
+    }
+
+    #[cfg(feature = "discord")]
+    #[test]
+    fn unknown_asset_overrides_are_dropped() {
+        let mut config = Config::builder()
+            .application_id("1234")
+            .asset_override("char:Fox", "my_fox")
+            .asset_override("stage:Final Destination", "my_fd")
+            .asset_override("mode:Ranked", "my_ranked")
+            .asset_override("char:Waluigi", "waluigi")
+            .asset_override("mode:Casual", "casual")
+            .asset_override("Fox", "fox")
+            .build()
+            .unwrap();
+
+        config.drop_unknown_asset_overrides(&Verbosity::default());
+
+        let mut kept: Vec<_> = config.asset_overrides.keys().map(String::as_str).collect();
+        kept.sort();
+        assert_eq!(kept, ["char:Fox", "mode:Ranked", "stage:Final Destination"]);
+    }
+
+    #[cfg(feature = "discord")]
+    #[test]
+    fn asset_overrides_accept_short_keys() {
+        let mut config = Config::builder()
+            .application_id("1234")
+            .asset_override("stage:FD", "my_fd")
+            .asset_override("stage:pokemon_stadium", "my_ps")
+            .asset_override("char:captain_falcon", "my_falcon")
+            .asset_override("stage:BF", "short_bf")
+            .asset_override("stage:Battlefield", "my_bf")
+            .build()
+            .unwrap();
+
+        config.drop_unknown_asset_overrides(&Verbosity::default());
+
+        assert_eq!(config.asset_override("stage:Final Destination").as_deref(), Some("my_fd"));
+        assert_eq!(config.asset_override("stage:Pokémon Stadium").as_deref(), Some("my_ps"));
+        assert_eq!(config.asset_override("char:Captain Falcon").as_deref(), Some("my_falcon"));
+        assert_eq!(config.asset_override("stage:Battlefield").as_deref(), Some("my_bf"));
+        assert_eq!(config.asset_overrides.len(), 4);
+    }
+}
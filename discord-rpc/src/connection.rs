@@ -0,0 +1,462 @@
+//! Manages the lifetime of the IPC connection to the Discord client, including reconnecting
+//! (with exponential backoff) if the client goes away mid-session - e.g, if a user closes and
+//! reopens Discord while Slippi is running.
+
+use std::time::{Duration, Instant};
+
+use dolphin_integrations::Log;
+
+use crate::backend::DiscordBackend;
+use crate::ipc::{DiscordIPC, EventSinks};
+use crate::status::{ConnectionState, ConnectionStatus, SharedStatus};
+use crate::verbosity::{self, Verbosity};
+use crate::{Activity, Config, DiscordRPCError, OsdCallback, Result};
+use DiscordRPCError::*;
+
+/// Computes reconnect delays: 1s, 2s, 4s, ... capped at 30s, each then randomized by up to
+/// ±20% (so the first retry comes 0.8-1.2s in, and later ones 24-36s apart). Without the
+/// jitter, every Dolphin on a machine would retry in lockstep after Discord restarts.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    attempt: u32,
+    jitter: Jitter,
+}
+
+impl Backoff {
+    const BASE: Duration = Duration::from_secs(1);
+    const MAX: Duration = Duration::from_secs(30);
+
+    pub fn new() -> Self {
+        Self::with_jitter(Jitter::random(Jitter::SPREAD))
+    }
+
+    /// Creates a `Backoff` that draws its jitter from `jitter`, e.g a seeded one in tests.
+    pub fn with_jitter(jitter: Jitter) -> Self {
+        Self { attempt: 0, jitter }
+    }
+
+    /// Returns the delay before the next attempt, and bumps the attempt counter.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = Self::BASE
+            .checked_mul(2u32.saturating_pow(self.attempt))
+            .unwrap_or(Self::MAX)
+            .min(Self::MAX);
+
+        self.attempt = self.attempt.saturating_add(1);
+        delay.mul_f64(self.jitter.next_factor())
+    }
+
+    /// Should be called after a successful connection.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// A source of random factors in `1 ± spread`, for jittering delays. This is a plain
+/// xorshift generator: it only has to keep separate processes from lining up, not be
+/// unpredictable.
+#[derive(Debug)]
+pub(crate) struct Jitter {
+    state: u64,
+    spread: f64,
+}
+
+impl Jitter {
+    /// How far `Backoff` delays stray from their nominal value, as a fraction of it.
+    pub const SPREAD: f64 = 0.2;
+
+    /// Creates a `Jitter` seeded differently in every process.
+    pub fn random(spread: f64) -> Self {
+        use std::collections::hash_map::RandomState;
+        use std::hash::{BuildHasher, Hasher};
+
+        Self::seeded(RandomState::new().build_hasher().finish(), spread)
+    }
+
+    /// Creates a `Jitter` that always produces the same factors for the same `seed`.
+    pub fn seeded(seed: u64, spread: f64) -> Self {
+        // Xorshift gets stuck on zero, so that one seed is nudged.
+        Self {
+            state: seed.max(1),
+            spread: spread.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Returns the next factor, in `[1 - spread, 1 + spread]`.
+    pub fn next_factor(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        // The top 53 bits make an evenly distributed f64 in [0, 1).
+        let unit = (self.state >> 11) as f64 / (1u64 << 53) as f64;
+        1.0 + self.spread * (2.0 * unit - 1.0)
+    }
+}
+
+/// Counts failed reconnect attempts against `Config::max_reconnect_attempts`.
+#[derive(Debug, Default)]
+pub(crate) struct ReconnectBudget {
+    max_attempts: Option<u32>,
+    failed: u32,
+}
+
+impl ReconnectBudget {
+    /// Records a failed attempt, returning whether that was the last one allowed.
+    pub fn record_failure(&mut self) -> bool {
+        self.failed = self.failed.saturating_add(1);
+        self.is_spent()
+    }
+
+    /// Whether every allowed attempt has been made (and failed).
+    pub fn is_spent(&self) -> bool {
+        self.max_attempts.is_some_and(|max_attempts| self.failed >= max_attempts)
+    }
+
+    /// Starts the count over, allowing up to `max_attempts` (or, for `None`, any number).
+    pub fn reset(&mut self, max_attempts: Option<u32>) {
+        self.max_attempts = max_attempts;
+        self.failed = 0;
+    }
+}
+
+/// Owns the (possibly absent) IPC client and knows when to try reconnecting.
+pub(crate) struct Connection {
+    application_id: String,
+    sinks: EventSinks,
+    client: Option<DiscordIPC>,
+    backoff: Backoff,
+    budget: ReconnectBudget,
+    connect_timeout: Duration,
+    retry_at: Option<Instant>,
+    status: SharedStatus,
+    osd_callback: Option<OsdCallback>,
+    verbosity: Verbosity,
+}
+
+impl Connection {
+    /// Creates a new, not-yet-connected `Connection`. `status` is kept in sync with the
+    /// connection state so that it can be read from other threads, and events from Discord are
+    /// forwarded to `sinks`. Everything it logs goes through `verbosity`.
+    pub fn new(application_id: String, sinks: EventSinks, status: SharedStatus, verbosity: Verbosity) -> Self {
+        Self {
+            application_id,
+            sinks,
+            client: None,
+            backoff: Backoff::new(),
+            budget: ReconnectBudget::default(),
+            connect_timeout: Config::default().connect_timeout,
+            retry_at: None,
+            status,
+            osd_callback: None,
+            verbosity,
+        }
+    }
+
+    /// Sets (or clears) the activity. If the connection was lost, a reconnect is scheduled;
+    /// other errors (e.g, Discord rejecting the activity) leave the connection as-is.
+    fn write(&mut self, activity: Option<&Activity>, now: Instant) -> Result<()> {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+
+        let result = client.set_activity(activity);
+
+        // The reader gives up on frames it can't make sense of, as it's no longer in step.
+        if let Err(ConnectionLost(_) | BadOpcode(_) | FrameTooShort(..) | FrameTooLarge(_)) = &result {
+            self.on_disconnected(now);
+        }
+
+        result
+    }
+
+    fn on_connected(&mut self, client: DiscordIPC) {
+        verbosity::log!(self.verbosity, info, target: Log::DiscordRPC, "Connected to Discord");
+
+        self.client = Some(client);
+        self.backoff.reset();
+        self.budget.reset(self.budget.max_attempts);
+        self.retry_at = None;
+        self.status.set(ConnectionStatus::Connected);
+        self.notify("Connected to Discord");
+    }
+
+    fn on_unavailable(&mut self, now: Instant) {
+        let delay = self.backoff.next_delay();
+
+        match self.status.get() {
+            ConnectionStatus::Unavailable => {
+                verbosity::log!(
+                    self.verbosity,
+                    debug,
+                    target: Log::DiscordRPC,
+                    ?delay,
+                    "Discord is still not running"
+                );
+            },
+
+            _ => verbosity::log!(
+                self.verbosity,
+                info,
+                target: Log::DiscordRPC,
+                "Discord does not appear to be running, will keep trying in the background"
+            ),
+        }
+
+        self.retry_at = Some(now + delay);
+        self.status.set(ConnectionStatus::Unavailable);
+    }
+
+    fn on_disconnected(&mut self, now: Instant) {
+        verbosity::log!(self.verbosity, warn, target: Log::DiscordRPC, "Lost connection to Discord");
+
+        self.client = None;
+        self.retry_at = Some(now + self.backoff.next_delay());
+        self.status.set(ConnectionStatus::Disconnected);
+        self.notify("Lost connection to Discord");
+    }
+
+    /// Stops retrying once a reconnect attempt has failed for the last time.
+    fn on_reconnect_failed(&mut self) {
+        if !self.budget.record_failure() {
+            return;
+        }
+
+        let error = ReconnectGaveUp(self.budget.failed);
+        verbosity::log!(self.verbosity, warn, target: Log::DiscordRPC, "{error}");
+
+        self.retry_at = None;
+        self.status.record_error(&error);
+        self.status.transition(ConnectionState::GaveUp);
+        self.notify("Gave up reconnecting to Discord");
+    }
+
+    fn notify(&self, message: &str) {
+        if let Some(callback) = &self.osd_callback {
+            callback.call(message);
+        }
+    }
+}
+
+impl DiscordBackend for Connection {
+    fn set_activity(&mut self, activity: &Activity) -> Result<()> {
+        self.write(Some(activity), Instant::now())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.write(None, Instant::now())
+    }
+
+    /// Performs the initial connection and handshake.
+    ///
+    /// If Discord isn't running, this isn't treated as an error: we just keep retrying in the
+    /// background until it is. Any other failure (e.g, a client that's still starting up and
+    /// never finishes the handshake) is retried the same way, but is also returned.
+    fn connect(&mut self, now: Instant) -> Result<()> {
+        match DiscordIPC::connect(&self.application_id, &self.sinks, self.connect_timeout, &self.verbosity) {
+            Ok(client) => self.on_connected(client),
+            Err(DiscordUnavailable) => self.on_unavailable(now),
+
+            Err(e) => {
+                let delay = self.backoff.next_delay();
+                verbosity::log!(
+                    self.verbosity,
+                    warn,
+                    target: Log::DiscordRPC,
+                    error = ?e,
+                    ?delay,
+                    "Failed to connect to Discord"
+                );
+                self.retry_at = Some(now + delay);
+                self.status.set(ConnectionStatus::Disconnected);
+                return Err(e);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// If we've lost the connection and the backoff delay has elapsed, attempts to reconnect.
+    fn maintain(&mut self, now: Instant) -> bool {
+        match self.retry_at {
+            Some(retry_at) if self.client.is_none() && now >= retry_at => {},
+            _ => return false,
+        }
+
+        // Retrying while Discord isn't running is expected and would be noisy; that case was
+        // already logged once when we noticed it.
+        if self.status.get() != ConnectionStatus::Unavailable {
+            verbosity::log!(
+                self.verbosity,
+                info,
+                target: Log::DiscordRPC,
+                attempt = self.backoff.attempt,
+                "Attempting to reconnect to Discord"
+            );
+
+            self.status.transition(ConnectionState::Reconnecting);
+            self.notify("Reconnecting to Discord...");
+        }
+
+        match DiscordIPC::connect(&self.application_id, &self.sinks, self.connect_timeout, &self.verbosity) {
+            Ok(client) => {
+                self.on_connected(client);
+                return true;
+            },
+
+            Err(DiscordUnavailable) => self.on_unavailable(now),
+
+            Err(e) => {
+                let delay = self.backoff.next_delay();
+                verbosity::log!(
+                    self.verbosity,
+                    warn,
+                    target: Log::DiscordRPC,
+                    error = ?e,
+                    ?delay,
+                    "Failed to reconnect to Discord"
+                );
+                self.retry_at = Some(now + delay);
+                self.status.set(ConnectionStatus::Disconnected);
+            },
+        }
+
+        self.on_reconnect_failed();
+        false
+    }
+
+    /// Whether we currently hold a live connection.
+    fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// How long until the next reconnect attempt, if we're waiting on one.
+    fn time_until_retry(&self, now: Instant) -> Option<Duration> {
+        self.retry_at.map(|retry_at| retry_at.saturating_duration_since(now))
+    }
+
+    /// Sets the callback that's notified of connection status changes.
+    fn set_osd_callback(&mut self, callback: Option<OsdCallback>) {
+        self.osd_callback = callback;
+    }
+
+    /// Starts the count of reconnect attempts over, retrying straight away if we'd given up.
+    fn set_max_reconnect_attempts(&mut self, max_attempts: Option<u32>, now: Instant) {
+        if self.client.is_none() && self.budget.is_spent() {
+            verbosity::log!(
+                self.verbosity,
+                info,
+                target: Log::DiscordRPC,
+                "Trying to reconnect to Discord again"
+            );
+            self.backoff.reset();
+            self.retry_at = Some(now);
+        }
+
+        self.budget.reset(max_attempts);
+    }
+
+    /// Applies to the next connection attempt onwards.
+    fn set_connect_timeout(&mut self, timeout: Duration) {
+        self.connect_timeout = timeout;
+    }
+
+    /// Drops the current connection (if any) and schedules an immediate reconnect, starting
+    /// the count of attempts over.
+    fn reconnect(&mut self, now: Instant) {
+        verbosity::log!(self.verbosity, info, target: Log::DiscordRPC, "Reconnecting to Discord");
+
+        self.backoff.reset();
+        self.budget.reset(self.budget.max_attempts);
+        self.retry_at = Some(now);
+
+        if self.client.take().is_some() {
+            self.status.set(ConnectionStatus::Connecting);
+        }
+    }
+
+    /// Drops the current connection (if any) and schedules an immediate reconnect under the
+    /// new application ID.
+    fn set_application_id(&mut self, application_id: &str, now: Instant) {
+        if application_id == self.application_id {
+            return;
+        }
+
+        verbosity::log!(
+            self.verbosity,
+            info,
+            target: Log::DiscordRPC,
+            application_id,
+            "Discord application ID changed, reconnecting"
+        );
+
+        self.application_id = application_id.to_string();
+        self.backoff.reset();
+        self.retry_at = Some(now);
+
+        if self.client.take().is_some() {
+            self.status.set(ConnectionStatus::Connecting);
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.client.is_some() {
+            self.status.set(ConnectionStatus::Disconnected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        let mut backoff = Backoff::with_jitter(Jitter::seeded(1, 0.0));
+        let delays: Vec<u64> = (0..7).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_bounds() {
+        let mut backoff = Backoff::with_jitter(Jitter::seeded(0x5EED, Jitter::SPREAD));
+        let delays: Vec<Duration> = (0..100).map(|_| backoff.next_delay()).collect();
+
+        assert!(delays[0] >= Duration::from_millis(800) && delays[0] <= Duration::from_millis(1200));
+        for delay in &delays[5..] {
+            assert!(
+                *delay >= Duration::from_secs(24) && *delay <= Duration::from_secs(36),
+                "{delay:?}"
+            );
+        }
+
+        // The point is for delays to differ, and the same seed always gives the same ones.
+        assert!(delays[5..].windows(2).any(|pair| pair[0] != pair[1]));
+
+        let mut again = Backoff::with_jitter(Jitter::seeded(0x5EED, Jitter::SPREAD));
+        assert_eq!((0..100).map(|_| again.next_delay()).collect::<Vec<_>>(), delays);
+    }
+
+    #[test]
+    fn reconnect_budgets_run_out_until_reset() {
+        let mut budget = ReconnectBudget::default();
+        assert!((0..100).all(|_| !budget.record_failure()));
+
+        budget.reset(Some(3));
+        assert!(!budget.record_failure());
+        assert!(!budget.record_failure());
+        assert!(!budget.is_spent());
+        assert!(budget.record_failure());
+        assert!(budget.is_spent());
+
+        budget.reset(Some(3));
+        assert!(!budget.is_spent());
+        assert!(!budget.record_failure());
+    }
+}
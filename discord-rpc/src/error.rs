@@ -0,0 +1,120 @@
+use thiserror::Error;
+
+use DiscordRPCError::*;
+
+#[derive(Error, Debug)]
+pub enum DiscordRPCError {
+    #[error("{0}")]
+    GenericIO(#[from] std::io::Error),
+
+    #[error("Failed to spawn thread: {0}")]
+    ThreadSpawn(std::io::Error),
+
+    #[error("The background thread panicked: {0}")]
+    ThreadPanic(String),
+
+    #[error("The channel sender has disconnected, implying no further messages will be received.")]
+    ChannelSenderDisconnected(#[from] std::sync::mpsc::RecvError),
+
+    #[error("The DiscordRPC background thread has exited, so no further messages can be sent to it.")]
+    ChannelClosed,
+
+    #[error("Failed to read the game state out of emulated memory {0} times in a row.")]
+    MemoryRead(u32),
+
+    #[error("Failed to (de)serialize an IPC payload: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Another DiscordHandler is already running in this process.")]
+    AlreadyRunning,
+
+    #[error("Discord does not appear to be running.")]
+    DiscordUnavailable,
+
+    #[error("Lost the connection to the Discord client: {0}")]
+    ConnectionLost(String),
+
+    #[error("Received an unexpected frame from the Discord client: {0}")]
+    InvalidFrame(String),
+
+    #[error("Received a frame with an unknown opcode ({0}) from the Discord client.")]
+    BadOpcode(u32),
+
+    /// The frame ended early, after the enclosed number of bytes (of however many it should
+    /// have had, the header included).
+    #[error("Received a truncated frame from the Discord client: expected {0} bytes, got {1}.")]
+    FrameTooShort(usize, usize),
+
+    /// The frame's header claimed a payload of the enclosed number of bytes, which is more
+    /// than we'll read.
+    #[error("Received a frame from the Discord client with an oversized payload ({0} bytes).")]
+    FrameTooLarge(usize),
+
+    #[error("Received a malformed payload from the Discord client: {0}")]
+    PayloadParse(serde_json::Error),
+
+    #[error("The Discord client rejected the handshake: {0}")]
+    Handshake(String),
+
+    #[error("The Discord client accepted the connection, but didn't finish the handshake within {0:?}.")]
+    HandshakeTimeout(std::time::Duration),
+
+    #[error("Gave up reconnecting to Discord after {0} attempts.")]
+    ReconnectGaveUp(u32),
+
+    #[error("The Discord client rejected a command: {0}")]
+    Command(String),
+
+    #[error("The Discord client is rate limiting us: {0}")]
+    RateLimited(String),
+
+    #[error("Failed to parse DiscordRPC configuration: {0}")]
+    ConfigParse(#[from] toml::de::Error),
+
+    #[error("Failed to serialize DiscordRPC configuration: {0}")]
+    ConfigSerialize(#[from] toml::ser::Error),
+
+    #[error("Invalid DiscordRPC configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Unknown DiscordRPC Error")]
+    Unknown,
+}
+
+/// A coarse grouping of `DiscordRPCError`s, e.g for picking what hint to show next to one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// Talking to the Discord client failed (or it isn't running).
+    Connection,
+
+    /// The Discord client understood us but refused, e.g an activity it considers invalid.
+    Rejected,
+
+    /// The configuration couldn't be loaded or is invalid.
+    Config,
+
+    /// Something went wrong on our end.
+    Internal,
+}
+
+impl DiscordRPCError {
+    /// Which `ErrorCategory` this falls under.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            GenericIO(_) | DiscordUnavailable | ConnectionLost(_) | InvalidFrame(_) | BadOpcode(_) | FrameTooShort(..)
+            | FrameTooLarge(_) | PayloadParse(_) | Handshake(_) | HandshakeTimeout(_) | ReconnectGaveUp(_) => {
+                ErrorCategory::Connection
+            },
+            Command(_) | RateLimited(_) => ErrorCategory::Rejected,
+            ConfigParse(_) | ConfigSerialize(_) | InvalidConfig(_) => ErrorCategory::Config,
+            ThreadSpawn(_)
+            | ThreadPanic(_)
+            | ChannelSenderDisconnected(_)
+            | ChannelClosed
+            | Json(_)
+            | MemoryRead(_)
+            | AlreadyRunning
+            | Unknown => ErrorCategory::Internal,
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+
+/// Whether a match is a 1v1 or a 2v2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameKind {
+    Singles,
+    Doubles,
+}
+
+/// How the match came about.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchmakingMode {
+    Ranked,
+    Unranked,
+    Direct,
+    Teams,
+
+    /// Local play, i.e not via Slippi Online.
+    #[default]
+    Offline,
+}
+
+impl MatchmakingMode {
+    pub(crate) const ALL: [Self; 5] = [Self::Ranked, Self::Unranked, Self::Direct, Self::Teams, Self::Offline];
+
+    /// What this mode is called in `Config::asset_overrides` keys, e.g "Ranked".
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Ranked => "Ranked",
+            Self::Unranked => "Unranked",
+            Self::Direct => "Direct",
+            Self::Teams => "Teams",
+            Self::Offline => "Offline",
+        }
+    }
+
+    /// The Discord asset key for this mode's icon, if it has one.
+    pub(crate) fn asset_key(self) -> Option<&'static str> {
+        match self {
+            Self::Ranked => Some("ranked"),
+            Self::Unranked => Some("unranked"),
+            Self::Direct => Some("direct"),
+            Self::Teams => Some("teams"),
+            Self::Offline => None,
+        }
+    }
+}
+
+/// A team in a teams match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TeamColor {
+    Red,
+    Blue,
+    Green,
+}
+
+impl TeamColor {
+    /// Resolves the team id that Melee stores per port (0 is red, 1 blue and 2 green). Melee
+    /// only has the three teams, so anything else isn't one.
+    pub fn from_team_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Red),
+            1 => Some(Self::Blue),
+            2 => Some(Self::Green),
+            _ => None,
+        }
+    }
+
+    /// The Discord asset key for this team's icon.
+    pub(crate) fn asset_key(self) -> &'static str {
+        match self {
+            Self::Red => "team_red",
+            Self::Blue => "team_blue",
+            Self::Green => "team_green",
+        }
+    }
+
+    /// How this team is shown in presence text, e.g "Red Team".
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Red => "Red Team",
+            Self::Blue => "Blue Team",
+            Self::Green => "Green Team",
+        }
+    }
+}
+
+/// A player in one of the four ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Player {
+    pub character_id: u8,
+
+    /// The character's costume (color), as indexed on the character select screen.
+    pub costume_id: u8,
+
+    /// The player's team, for teams matches.
+    pub team: Option<TeamColor>,
+}
+
+/// Where a best-of set stands, from p1's side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetScore {
+    /// Which game of the set this is, counting from 1.
+    pub game: u8,
+    pub p1_wins: u8,
+    pub p2_wins: u8,
+}
+
+impl SetScore {
+    /// This score as seen from p2's side.
+    pub(crate) fn swapped(self) -> Self {
+        Self {
+            game: self.game,
+            p1_wins: self.p2_wins,
+            p2_wins: self.p1_wins,
+        }
+    }
+}
+
+/// The screens that lead into a match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectScreen {
+    Characters,
+    Stage,
+}
+
+/// A snapshot of the parts of Melee's state that we read out of RAM (and show in the presence).
+///
+/// Character and stage values use the same ids as `GameInfo`. Characters are the ones on stage
+/// right now, so Zelda shows up as Sheik once she's transformed (and vice versa).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GameState {
+    pub in_game: bool,
+
+    /// Which select screen the game is on, if it's on one (and so isn't in a match).
+    pub selecting: Option<SelectScreen>,
+
+    pub stage_id: u16,
+    pub p1_character_id: u8,
+    pub p2_character_id: u8,
+    pub p1_stocks: u8,
+    pub p2_stocks: u8,
+
+    /// p2's level, if they're a CPU. This is whatever the game has stored, which should be
+    /// from 1 to 9.
+    pub p2_cpu_level: Option<u8>,
+}
+
+/// Information about a match that has just started.
+///
+/// Character and stage values are the internal ids that Melee uses.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameInfo {
+    pub stage_id: u16,
+    pub p1_character_id: u8,
+    pub p2_character_id: u8,
+
+    /// The costume (color) each player picked. Only p1's is shown, as the large image.
+    pub p1_costume_id: u8,
+    pub p2_costume_id: u8,
+
+    pub kind: GameKind,
+
+    /// The match timer, if this is a timed match.
+    pub timer_seconds: Option<u32>,
+
+    pub matchmaking: MatchmakingMode,
+
+    /// Who's in each port. This only needs to be filled in for teams matches; for singles,
+    /// the p1/p2 character ids are enough.
+    pub players: [Option<Player>; 4],
+
+    /// The port (0-3) the Slippi user is playing on, for online matches.
+    pub local_port: Option<u8>,
+}
+
+impl GameInfo {
+    /// Swaps the character in `port` (0-indexed) for `character_id`, keeping their costume and
+    /// team, e.g once Zelda has transformed into Sheik.
+    pub(crate) fn set_character(&mut self, port: usize, character_id: u8) {
+        match port {
+            0 => self.p1_character_id = character_id,
+            1 => self.p2_character_id = character_id,
+            _ => {},
+        }
+
+        if let Some(Some(player)) = self.players.get_mut(port) {
+            player.character_id = character_id;
+        }
+    }
+
+    /// Who's in `port`, falling back to the p1/p2 fields for the first two ports.
+    pub(crate) fn player(&self, port: usize) -> Option<Player> {
+        let fallback = match port {
+            0 => Some((self.p1_character_id, self.p1_costume_id)),
+            1 => Some((self.p2_character_id, self.p2_costume_id)),
+            _ => None,
+        };
+
+        self.players.get(port).copied().flatten().or_else(|| {
+            fallback.map(|(character_id, costume_id)| Player {
+                character_id,
+                costume_id,
+                team: None,
+            })
+        })
+    }
+}
+
+/// A big moment in the match in progress, briefly called out in the presence. Ports are
+/// 0-indexed, as with `Config::primary_port`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GameEvent {
+    /// The player in `killer` took a stock.
+    Kill { killer: u8 },
+
+    /// The player in `port` landed a combo worth `damage` percent.
+    BigCombo { port: u8, damage: f32 },
+}
+
+/// How a match ended, from the local player's point of view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameResult {
+    Win,
+    Loss,
+
+    /// The match was abandoned (or its outcome is unknown), so there's no result to show.
+    NoContest,
+}
+
+/// Slippi's report on a match that's just ended. Ports are 0-indexed, as with `GameEvent`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameReport {
+    /// How long the match lasted, in frames.
+    pub duration_frames: u32,
+
+    /// The port of whoever won, if anyone did (e.g, not for a draw).
+    pub winner_port: Option<u8>,
+
+    /// How many stocks each port finished with.
+    pub stocks_remaining: [u8; 4],
+
+    /// How much damage each port dealt, in percent.
+    pub damage_dealt: [f32; 4],
+}
+
+/// Everything about a match that changes from frame to frame, bundled so that it can be sent
+/// once per batch of frames rather than as a message per field. Fields left as `None` stay as
+/// they were.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TickUpdate {
+    /// Each player's stocks, as (p1, p2).
+    pub stocks: Option<(u8, u8)>,
+
+    /// Each player's damage percent, as (p1, p2).
+    pub percents: Option<(f32, f32)>,
+
+    /// The match's frame counter.
+    pub frame: Option<i32>,
+
+    pub paused: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn team_ids_resolve_to_colors() {
+        let cases = [
+            (0, Some(TeamColor::Red), Some("team_red"), Some("Red Team")),
+            (1, Some(TeamColor::Blue), Some("team_blue"), Some("Blue Team")),
+            (2, Some(TeamColor::Green), Some("team_green"), Some("Green Team")),
+            (3, None, None, None),
+        ];
+
+        for (id, color, asset_key, name) in cases {
+            let resolved = TeamColor::from_team_id(id);
+            assert_eq!(resolved, color, "{id}");
+            assert_eq!(resolved.map(TeamColor::asset_key), asset_key, "{id}");
+            assert_eq!(resolved.map(TeamColor::name), name, "{id}");
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! An async face for `DiscordHandler`, for consumers that would rather await connection
+//! changes than poll channels.
+//!
+//! This isn't tied to any runtime: each stream polls its subscription channel without
+//! blocking, and the handler wakes whichever tasks are waiting whenever the state changes.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::Receiver;
+use std::task::{Context, Poll};
+
+use crate::status::SharedStatus;
+use crate::ConnectionState;
+
+/// An async view of a `DiscordHandler`'s connection, created by `DiscordHandler::handle`.
+///
+/// The handle can outlive the handler, but nothing changes after the handler is gone (and
+/// streams end once they've caught up).
+#[derive(Clone, Debug)]
+pub struct DiscordHandle {
+    status: SharedStatus,
+}
+
+impl DiscordHandle {
+    pub(crate) fn new(status: SharedStatus) -> Self {
+        Self { status }
+    }
+
+    /// Resolves once the handler is connected to Discord, straight away if it already is. This
+    /// also resolves if the handler goes away first, as it'll never connect after that.
+    ///
+    /// This waits for as long as it takes, so callers that want to give up should race it
+    /// against a timeout from their runtime.
+    pub async fn connected(&self) {
+        let mut states = self.state_stream();
+
+        while let Some(state) = states.recv().await {
+            if state == ConnectionState::Connected {
+                return;
+            }
+        }
+    }
+
+    /// Returns a stream of connection state transitions, starting with the current state.
+    /// This is the async counterpart of `DiscordHandler::subscribe_state`.
+    pub fn state_stream(&self) -> StateStream {
+        StateStream::new(self.status.clone())
+    }
+}
+
+/// Connection state transitions, as returned by `DiscordHandle::state_stream`.
+///
+/// `poll_next` has the same shape as `futures::Stream::poll_next`, so this can be wrapped in
+/// (or adapted to) whichever stream trait the caller's runtime uses.
+#[derive(Debug)]
+pub struct StateStream {
+    status: SharedStatus,
+    rx: Receiver<ConnectionState>,
+}
+
+impl StateStream {
+    fn new(status: SharedStatus) -> Self {
+        let rx = status.subscribe();
+        Self { status, rx }
+    }
+
+    /// Resolves to the next transition, or `None` once there won't be any more (i.e, the
+    /// handler is gone).
+    pub fn recv(&mut self) -> Recv<'_> {
+        Recv { stream: self }
+    }
+
+    /// Polls for the next transition, registering `cx` to be woken when there is one.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<ConnectionState>> {
+        self.status.poll_transition(&self.rx, cx)
+    }
+}
+
+/// The future returned by `StateStream::recv`.
+#[derive(Debug)]
+pub struct Recv<'a> {
+    stream: &'a mut StateStream,
+}
+
+impl Future for Recv<'_> {
+    type Output = Option<ConnectionState>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ConnectionStatus;
+
+    /// Just enough of an executor to drive one future to completion on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn connected_resolves_once_connected() {
+        let status = SharedStatus::new();
+        let handle = DiscordHandle::new(status.clone());
+
+        let connecting = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            status.set(ConnectionStatus::Unavailable);
+            status.set(ConnectionStatus::Connected);
+        });
+
+        block_on(handle.connected());
+        connecting.join().unwrap();
+
+        // Now that it's connected, this doesn't wait at all.
+        block_on(handle.connected());
+    }
+
+    #[test]
+    fn streams_relay_transitions_in_order() {
+        let status = SharedStatus::new();
+        let mut states = DiscordHandle::new(status.clone()).state_stream();
+
+        status.set(ConnectionStatus::Connected);
+        status.transition(ConnectionState::Reconnecting);
+
+        assert_eq!(block_on(states.recv()), Some(ConnectionState::Connecting));
+        assert_eq!(block_on(states.recv()), Some(ConnectionState::Connected));
+        assert_eq!(block_on(states.recv()), Some(ConnectionState::Reconnecting));
+    }
+
+    #[test]
+    fn streams_end_once_the_handler_is_gone() {
+        let status = SharedStatus::new();
+        let handle = DiscordHandle::new(status.clone());
+        let mut states = handle.state_stream();
+
+        let closing = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            status.set(ConnectionStatus::Disconnected);
+            status.close();
+        });
+
+        // The handle (as well as the stream) still holds onto the status, so this is down to
+        // the handler saying it's gone.
+        assert_eq!(block_on(states.recv()), Some(ConnectionState::Connecting));
+        assert_eq!(block_on(states.recv()), Some(ConnectionState::Disconnected));
+        assert_eq!(block_on(states.recv()), None);
+        closing.join().unwrap();
+
+        // Nor do later ones wait on anything.
+        assert_eq!(block_on(handle.state_stream().recv()), Some(ConnectionState::Disconnected));
+        block_on(handle.connected());
+    }
+}
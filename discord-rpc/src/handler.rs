@@ -0,0 +1,3155 @@
+//! The real `DiscordHandler`, which drives presence from a background thread.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dolphin_integrations::Log;
+
+use crate::backend::DiscordBackend;
+use crate::cleanup::{self, CleanupState};
+use crate::clock::{unix_millis, Clock, SystemClock};
+use crate::connection::Connection;
+use crate::ipc::EventSinks;
+use crate::memory::{self, DolphinMemory, MemoryReader, ReadFailures};
+use crate::metrics::Metrics;
+use crate::persist;
+use crate::presence::{self, LobbyRole, PresenceContext, PresenceSource};
+use crate::status::SharedStatus;
+use crate::subscribers::Subscribers;
+use crate::throttle::{AdaptiveThrottle, RateLimiter};
+use crate::verbosity::{self, Verbosity};
+use crate::watcher;
+use crate::{
+    Activity, AppState, Config, ConnectionState, ConnectionStatus, DiscordMetrics, DiscordRPCError, ErrorCategory, GameEvent,
+    GameInfo, GameReport, GameResult, GameState, JoinRequest, Matchmaking, MatchmakingMode, Message, Rank, RankTier, Result,
+    SetScore, SpectateRequest, TickUpdate, MAX_FIELD_CHARS,
+};
+use DiscordRPCError::*;
+
+/// How long we're willing to wait for the background thread to wind down (and clear
+/// the activity) when the handler is shut down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the background thread reads game state out of Dolphin's RAM.
+const MEMORY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a changed application ID has to stay put before we reconnect under it, so that
+/// a burst of config updates costs at most one reconnect.
+const APPLICATION_ID_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// How long the result of a match is shown for once it's over.
+const RESULT_DURATION: Duration = Duration::from_secs(5);
+
+/// How long we'll show the local player waiting on a rematch before assuming they've moved on.
+const REMATCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long the game has to stay paused (or unpaused) before the presence reflects it, so that
+/// mashing start doesn't burn through Discord's rate limit.
+const PAUSE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// The last game state the background thread read, for `DiscordHandler::current_state`.
+type SharedGameState = Arc<RwLock<Option<GameState>>>;
+
+/// How the match in progress came about, for `DiscordHandler::current_mode`.
+type SharedMode = Arc<RwLock<Option<MatchmakingMode>>>;
+
+/// Whether a `DiscordHandler` connected to the real Discord client exists. Discord only shows
+/// one activity per process, so a second one would just fight the first over it.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Claims a flag (in practice, `RUNNING`) for as long as it's held.
+#[derive(Debug)]
+struct InstanceGuard(&'static AtomicBool);
+
+impl InstanceGuard {
+    /// Claims `flag`, failing with `AlreadyRunning` if it's already claimed.
+    fn acquire(flag: &'static AtomicBool) -> Result<Self> {
+        match flag.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => Ok(Self(flag)),
+            Err(_) => Err(AlreadyRunning),
+        }
+    }
+}
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Whether the presence is being kept up to date, or has been suspended.
+#[derive(Debug)]
+enum Suspension {
+    Live,
+
+    /// Only this activity (where `None` is a clear) goes out, i.e as a heartbeat or after a
+    /// reconnect, until the presence is resumed.
+    Frozen(Box<Option<Activity>>),
+}
+
+/// A client that manages Discord Rich Presence from a background thread.
+#[derive(Debug)]
+pub struct DiscordHandler {
+    tx: Sender<Message>,
+    status: SharedStatus,
+    metrics: Metrics,
+    game_state: SharedGameState,
+    mode: SharedMode,
+    join_requests: Subscribers<JoinRequest>,
+    spectate_requests: Subscribers<SpectateRequest>,
+    cleanup: Arc<CleanupState>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+
+    // Carries `Config::instance_label` (if there is one) onto everything this handler logs.
+    span: tracing::Span,
+
+    // Signalled by the background thread the first time it's connected, for `new_connected`.
+    connected: Option<Receiver<()>>,
+
+    // Shared with every thread that logs on this handler's behalf, for `set_log_level`.
+    verbosity: Verbosity,
+}
+
+impl DiscordHandler {
+    /// Kicks off the background thread, which connects to the Discord client and
+    /// shows the Slippi application as the current activity.
+    ///
+    /// Only one handler can be running per process: until the existing one is dropped (or shut
+    /// down), this fails with `AlreadyRunning`.
+    pub fn new(ram_offset: usize, config: Config) -> Result<Self> {
+        let instance = InstanceGuard::acquire(&RUNNING)?;
+        let status = SharedStatus::new();
+        let sinks = EventSinks {
+            join_requests: Subscribers::new(),
+            spectate_requests: Subscribers::new(),
+        };
+
+        let verbosity = Verbosity::default();
+        let connection = Connection::new(
+            config.application_id.clone(),
+            sinks.clone(),
+            status.clone(),
+            verbosity.clone(),
+        );
+
+        Self::spawn(
+            ram_offset,
+            config,
+            connection,
+            status,
+            sinks,
+            SystemClock,
+            Some(instance),
+            std::env::temp_dir(),
+            verbosity,
+        )
+    }
+
+    /// Like `new`, but blocks until the background thread has connected to the Discord client,
+    /// failing with `DiscordUnavailable` (and shutting the handler back down) if that doesn't
+    /// happen within `timeout`.
+    pub fn new_connected(ram_offset: usize, config: Config, timeout: Duration) -> Result<Self> {
+        Self::new(ram_offset, config)?.wait_until_connected(timeout)
+    }
+
+    /// Waits for up to `timeout` for the background thread's first connection.
+    fn wait_until_connected(mut self, timeout: Duration) -> Result<Self> {
+        let connected = self.connected.take().map(|connected| connected.recv_timeout(timeout));
+
+        match connected {
+            Some(Ok(())) => Ok(self),
+
+            _ => {
+                verbosity::log!(
+                    self.verbosity,
+                    warn,
+                    target: Log::DiscordRPC,
+                    ?timeout,
+                    "Timed out waiting to connect to Discord"
+                );
+                Err(DiscordUnavailable)
+            },
+        }
+    }
+
+    /// Like `spawn`, for handlers that don't claim `RUNNING`. Presences are saved to the temp
+    /// directory, as `new` does, so tests that turn `Config::persist_presence` on should `spawn`
+    /// with a directory of their own.
+    #[cfg(test)]
+    fn with_backend<B: DiscordBackend + 'static, C: Clock + Clone + Send + 'static>(
+        ram_offset: usize,
+        config: Config,
+        backend: B,
+        status: SharedStatus,
+        sinks: EventSinks,
+        clock: C,
+    ) -> Result<Self> {
+        Self::spawn(
+            ram_offset,
+            config,
+            backend,
+            status,
+            sinks,
+            clock,
+            None,
+            std::env::temp_dir(),
+            Verbosity::default(),
+        )
+    }
+
+    /// Like `new`, but against a backend that only pretends to be connected to Discord, which
+    /// suits testing code that drives a handler. These don't count towards the one handler
+    /// that can run per process.
+    #[cfg(feature = "test-support")]
+    pub fn with_mock_backend(ram_offset: usize, config: Config) -> Result<Self> {
+        let sinks = EventSinks {
+            join_requests: Subscribers::new(),
+            spectate_requests: Subscribers::new(),
+        };
+
+        Self::spawn(
+            ram_offset,
+            config,
+            crate::backend::MockBackend::default(),
+            SharedStatus::new(),
+            sinks,
+            SystemClock,
+            None,
+            std::env::temp_dir(),
+            Verbosity::default(),
+        )
+    }
+
+    /// Waits for the background thread to handle everything sent so far, and then make another
+    /// pass of its loop, so that anything that's come due (e.g on a `ManualClock`) has happened.
+    #[cfg(test)]
+    fn sync(&self) {
+        for _ in 0..2 {
+            let (ack_tx, ack_rx) = channel();
+            self.send(Message::Sync(ack_tx), "Failed to sync with the DiscordHandler thread")
+                .unwrap();
+            ack_rx.recv().unwrap();
+        }
+    }
+
+    /// Kicks off the background thread against an arbitrary backend and clock. `new` uses a
+    /// live `Connection` and the system clock; tests can pass a `MockBackend` and `ManualClock`
+    /// instead. `status` and `sinks` should be the ones the backend reports into.
+    ///
+    /// The background thread holds onto `instance` until it exits, which might be after the
+    /// handler itself is gone (see `stop`). Only `new` has one, as other backends don't talk
+    /// to Discord. With `Config::persist_presence`, the presence is saved to and restored from
+    /// `persist_dir`. `verbosity` should be the one the backend logs through.
+    #[allow(clippy::too_many_arguments)]
+    fn spawn<B: DiscordBackend + 'static, C: Clock + Clone + Send + 'static>(
+        ram_offset: usize,
+        config: Config,
+        backend: B,
+        status: SharedStatus,
+        sinks: EventSinks,
+        clock: C,
+        instance: Option<InstanceGuard>,
+        persist_dir: PathBuf,
+        verbosity: Verbosity,
+    ) -> Result<Self> {
+        verbosity::log!(verbosity, info, target: Log::DiscordRPC, "Initializing DiscordRPC");
+
+        // This channel allows the main thread to send messages to the
+        // SlippiDiscordRPC thread
+        let (tx, rx) = channel::<Message>();
+        let (connected_tx, connected_rx) = sync_channel(1);
+
+        let metrics = Metrics::default();
+        let thread_metrics = metrics.clone();
+        let thread_status = status.clone();
+        let game_state = SharedGameState::default();
+        let thread_game_state = game_state.clone();
+        let mode = SharedMode::default();
+        let thread_mode = mode.clone();
+        let cleanup = CleanupState::new(tx.clone(), verbosity.clone());
+
+        let (thread_name, span) = match &config.instance_label {
+            Some(label) => (
+                format!("SlippiDiscordRPC[{label}]"),
+                tracing::info_span!(target: Log::DiscordRPC, "DiscordHandler", instance = %label),
+            ),
+
+            None => ("SlippiDiscordRPC".to_string(), tracing::Span::none()),
+        };
+
+        let thread_span = span.clone();
+        let thread_verbosity = verbosity.clone();
+
+        let thread = thread::Builder::new()
+            .name(thread_name)
+            .spawn(move || {
+                let _instance = instance;
+                let _entered = thread_span.entered();
+
+                let result = Self::start(
+                    rx,
+                    ram_offset,
+                    config,
+                    backend,
+                    clock,
+                    thread_metrics,
+                    &thread_status,
+                    connected_tx,
+                    thread_game_state,
+                    thread_mode,
+                    &persist_dir,
+                    &thread_verbosity,
+                );
+
+                if let Err(e) = result {
+                    thread_status.record_error(&e);
+                    verbosity::log!(
+                        thread_verbosity,
+                        error,
+                        target: Log::DiscordRPC,
+                        error = ?e,
+                        "SlippiDiscordRPC thread encountered an error: {e}"
+                    );
+                }
+            })
+            .map_err(ThreadSpawn)?;
+
+        Ok(Self {
+            tx,
+            status,
+            metrics,
+            game_state,
+            mode,
+            join_requests: sinks.join_requests,
+            spectate_requests: sinks.spectate_requests,
+            cleanup,
+            stopped: Arc::new(AtomicBool::new(false)),
+            thread: Some(thread),
+            span,
+            connected: Some(connected_rx),
+            verbosity,
+        })
+    }
+
+    /// This can be thought of as the handler's "main" function. It connects to the
+    /// Discord client and then runs on a loop, awaiting messages from the main thread.
+    ///
+    /// Activities are never sent directly; they're queued on a `RateLimiter` and flushed
+    /// whenever the update interval allows. If the connection drops, pending activities are
+    /// held until a reconnect succeeds. Discord forgets our activity when the connection drops,
+    /// so after reconnecting the last one we sent is replayed (unless there's a newer one).
+    ///
+    /// Alongside servicing messages, game state is polled out of RAM every
+    /// `MEMORY_POLL_INTERVAL`, so the presence follows along even if nothing is pushed to us.
+    ///
+    /// `connected` is signalled the first time we're connected, whether that's straight away
+    /// or after some retries. Each game state that's read is published to `shared_game_state`,
+    /// and the matchmaking mode of the match in progress to `shared_mode` whenever it might
+    /// have changed.
+    #[allow(clippy::too_many_arguments)]
+    fn start<B: DiscordBackend, C: Clock + Clone>(
+        rx: Receiver<Message>,
+        ram_offset: usize,
+        mut config: Config,
+        mut backend: B,
+        clock: C,
+        metrics: Metrics,
+        status: &SharedStatus,
+        connected: SyncSender<()>,
+        shared_game_state: SharedGameState,
+        shared_mode: SharedMode,
+        persist_dir: &Path,
+        verbosity: &Verbosity,
+    ) -> Result<()> {
+        let mut throttle = AdaptiveThrottle::new(clock.clone(), config.effective_update_interval(), config.adaptive_interval);
+        // Starting up counts as a change, as there's (probably) more to follow.
+        let mut limiter = RateLimiter::new(throttle.record_change());
+        // A handler that's only just gone away may have left us what it was showing.
+        let mut ctx = match config.persist_presence {
+            true => persist::load(&persist::path_for(persist_dir, &config), unix_millis(), verbosity).unwrap_or_default(),
+            false => PresenceContext::default(),
+        };
+
+        let mut memory = DolphinMemory::new(ram_offset);
+        ctx.waiting_for_emulator = !memory.is_mapped();
+        let mut game_state = GameState::default();
+        let mut read_failures = ReadFailures::default();
+        let mut next_poll = clock.now();
+        // The last update that made it to Discord (where `Some(None)` is a clear), for
+        // replaying after a reconnect and skipping identical updates.
+        let mut last_sent: Option<Option<Activity>> = None;
+        let mut last_write = clock.now();
+        let mut last_message = clock.now();
+
+        // When the `GameEvent` currently shown in place of the state expires.
+        let mut highlight_until: Option<Instant> = None;
+
+        // When the result of the last match stops being shown.
+        let mut result_until: Option<Instant> = None;
+
+        // When to stop waiting on a rematch.
+        let mut rematch_until: Option<Instant> = None;
+
+        // What the presence is showing (going by the priority of its source), and when to
+        // condense it (see `Config::verbose_when_recent`).
+        let condense_after =
+            |config: &Config| (!config.verbose_when_recent.is_zero()).then(|| clock.now() + config.verbose_when_recent);
+        let mut shown_source = ctx.source().priority();
+        let mut condense_at = condense_after(&config);
+
+        // The latest pause state we've been told about (and when, as a Unix timestamp), and when
+        // to apply it.
+        let mut pending_pause: Option<(bool, u64)> = None;
+        let mut apply_pause_at: Option<Instant> = None;
+
+        // The application ID we're connected (or connecting) under, and when to switch to the
+        // one in `config` if they differ.
+        let mut application_id = config.application_id.clone();
+        let mut switch_application_id_at: Option<Instant> = None;
+        let mut connected = Some(connected);
+
+        let mut suspension = Suspension::Live;
+
+        let publish_mode = |ctx: &PresenceContext| {
+            *shared_mode.write().expect("Unable to acquire mode lock") = ctx.game.as_ref().map(|info| info.matchmaking);
+        };
+
+        publish_mode(&ctx);
+
+        config.warn_if_update_interval_is_raised(verbosity);
+        config.drop_invalid_buttons(verbosity);
+        config.drop_unknown_asset_overrides(verbosity);
+        backend.set_osd_callback(config.osd_callback.clone());
+        backend.set_max_reconnect_attempts(config.max_reconnect_attempts, clock.now());
+        backend.set_connect_timeout(config.connect_timeout);
+        let initial = match ctx == PresenceContext::default() {
+            true => config.show_idle_presence.then(Activity::slippi),
+            false => presence::update(&ctx, &config),
+        };
+
+        match Self::connect_and_init(
+            &mut backend,
+            initial.as_ref(),
+            clock.now(),
+            &config,
+            &metrics,
+            status,
+            verbosity,
+        ) {
+            true => {
+                limiter.record_flush(clock.now());
+                last_sent = Some(initial);
+            },
+
+            // Held until we're connected.
+            false => {
+                if let Some(initial) = initial {
+                    limiter.queue(Some(initial));
+                }
+            },
+        }
+
+        loop {
+            if switch_application_id_at.is_some_and(|at| clock.now() >= at) {
+                switch_application_id_at = None;
+                application_id = config.application_id.clone();
+                backend.set_application_id(&application_id, clock.now());
+            }
+
+            if backend.maintain(clock.now()) {
+                metrics.record_reconnect();
+                status.clear_error();
+                limiter.reset();
+
+                // Discord has forgotten whatever we last sent, so it's no longer a reason to
+                // skip sending the same thing again.
+                let replay = last_sent.take();
+
+                if !limiter.has_pending() {
+                    limiter.queue(match &suspension {
+                        Suspension::Frozen(frozen) => Option::clone(frozen),
+                        Suspension::Live => replay.unwrap_or_else(|| presence::update(&ctx, &config)),
+                    });
+                }
+            }
+
+            if backend.is_connected() {
+                // Nobody may be waiting on this, which is fine.
+                if let Some(connected) = connected.take() {
+                    let _ = connected.try_send(());
+                }
+            }
+
+            if clock.now() >= next_poll {
+                next_poll = clock.now() + MEMORY_POLL_INTERVAL;
+
+                match memory.is_mapped().then(|| memory.game_state()) {
+                    Some(Some(current)) => {
+                        read_failures.reset();
+
+                        if memory::sync_context(&mut ctx, &game_state, &current, unix_millis()) {
+                            limiter.set_interval(throttle.record_change());
+
+                            if limiter.queue(presence::update(&ctx, &config)) {
+                                metrics.record_coalesced();
+                            }
+                        }
+
+                        *shared_game_state.write().expect("Unable to acquire game state lock") = Some(current.clone());
+                        publish_mode(&ctx);
+                        game_state = current;
+                    },
+
+                    // This is usually transient (e.g mid game swap), so we carry on with the last
+                    // state we read, and only report it if it keeps happening.
+                    Some(None) => {
+                        metrics.record_memory_read_failure();
+                        verbosity::log!(
+                            verbosity,
+                            debug,
+                            target: Log::DiscordRPC,
+                            "Failed to read the game state, keeping the last one"
+                        );
+
+                        if let Err(e) = read_failures.record() {
+                            verbosity::log!(
+                                verbosity,
+                                warn,
+                                target: Log::DiscordRPC,
+                                error = ?e,
+                                "The game state is still unreadable"
+                            );
+                            status.record_error(&e);
+                        }
+                    },
+
+                    // Nothing to read until we're told where memory is.
+                    None => {},
+                }
+            }
+
+            if highlight_until.is_some_and(|at| clock.now() >= at) {
+                highlight_until = None;
+
+                if ctx.highlight.take().is_some() {
+                    limiter.set_interval(throttle.record_change());
+
+                    if limiter.queue(presence::update(&ctx, &config)) {
+                        metrics.record_coalesced();
+                    }
+                }
+            }
+
+            if result_until.is_some_and(|at| clock.now() >= at) {
+                result_until = None;
+
+                ctx.last_report = None;
+
+                if ctx.last_result.take().is_some() {
+                    limiter.set_interval(throttle.record_change());
+
+                    if limiter.queue(presence::update(&ctx, &config)) {
+                        metrics.record_coalesced();
+                    }
+                }
+            }
+
+            if rematch_until.is_some_and(|at| clock.now() >= at) {
+                rematch_until = None;
+
+                if ctx.awaiting_rematch_since.take().is_some() {
+                    limiter.set_interval(throttle.record_change());
+
+                    if limiter.queue(presence::update(&ctx, &config)) {
+                        metrics.record_coalesced();
+                    }
+                }
+            }
+
+            if apply_pause_at.is_some_and(|at| clock.now() >= at) {
+                apply_pause_at = None;
+
+                if let Some((paused, at)) = pending_pause.take() {
+                    if ctx.set_paused(paused, at) {
+                        limiter.set_interval(throttle.record_change());
+
+                        if limiter.queue(presence::update(&ctx, &config)) {
+                            metrics.record_coalesced();
+                        }
+                    }
+                }
+            }
+
+            // Whatever queued the new source rendered it condensed, so it's rendered again in
+            // full (before that has a chance to go out).
+            let source = ctx.source().priority();
+
+            if source != shown_source {
+                shown_source = source;
+                condense_at = condense_after(&config);
+
+                if std::mem::take(&mut ctx.condensed) {
+                    limiter.queue(presence::update(&ctx, &config));
+                }
+            }
+
+            if condense_at.is_some_and(|at| clock.now() >= at) {
+                condense_at = None;
+                ctx.condensed = true;
+                limiter.set_interval(throttle.record_change());
+
+                if limiter.queue(presence::update(&ctx, &config)) {
+                    metrics.record_coalesced();
+                }
+            }
+
+            // Everything queued while suspended is dropped (in favour of the frozen presence, if
+            // that's yet to go out); the latest state is rebuilt on resume.
+            if let Suspension::Frozen(frozen) = &suspension {
+                let unsent = last_sent.as_ref() != Some(&**frozen);
+
+                if limiter.discard_if(|update| update != &**frozen) && unsent {
+                    limiter.queue(Option::clone(frozen));
+                }
+            }
+
+            // Some Discord clients drop connections that go quiet for too long, so the activity
+            // is resent every so often. Forgetting it means it won't be skipped as identical.
+            let heartbeat_at = match config.heartbeat_interval.is_zero() {
+                true => None,
+                false => Some(last_write + config.heartbeat_interval),
+            };
+
+            if backend.is_connected() && !limiter.has_pending() && heartbeat_at.is_some_and(|at| clock.now() >= at) {
+                last_write = clock.now();
+
+                if let Some(update) = last_sent.take() {
+                    verbosity::log!(
+                        verbosity,
+                        debug,
+                        target: Log::DiscordRPC,
+                        "Sending Discord activity heartbeat"
+                    );
+                    limiter.queue(update);
+                }
+            }
+
+            if backend.is_connected() {
+                // Rebuilding the presence often lands on exactly what Discord is already showing
+                // (e.g, a setting that doesn't affect the current screen), which isn't worth a
+                // round trip, let alone a slot in the rate limit.
+                if limiter.discard_if(|update| last_sent.as_ref() == Some(update)) {
+                    metrics.record_skipped_identical();
+                }
+
+                if let Some(update) = limiter.poll(clock.now()) {
+                    match flush(&mut backend, update.as_ref(), config.dry_run, &metrics, status, verbosity) {
+                        Ok(()) => {
+                            last_sent = Some(update);
+                            last_write = clock.now();
+                        },
+
+                        // Retrying straight away would only dig us in deeper, so back off for
+                        // a while, then try again (unless something newer has been queued).
+                        Err(RateLimited(_)) => {
+                            metrics.record_rate_limited();
+                            throttle.record_rate_limited();
+                            limiter.set_interval(throttle.interval());
+
+                            if !limiter.has_pending() {
+                                limiter.queue(update);
+                            }
+                        },
+
+                        // If the connection went with it, hold onto the update for when it's
+                        // back (unless something newer has been queued by then).
+                        Err(_) if !backend.is_connected() && !limiter.has_pending() => {
+                            limiter.queue(update);
+                        },
+
+                        Err(_) => {},
+                    }
+                }
+            }
+
+            // A handler that's been leaked (rather than dropped) never disconnects its sender, so
+            // this is the only way the thread can notice it's been orphaned.
+            let idle_at = match config.idle_timeout.is_zero() || backend.is_connected() {
+                true => None,
+                false => Some(last_message + config.idle_timeout),
+            };
+
+            if idle_at.is_some_and(|at| clock.now() >= at) {
+                verbosity::log!(
+                    verbosity,
+                    info,
+                    target: Log::DiscordRPC,
+                    "No messages or Discord connection for {:?}, stopping SlippiDiscordRPC thread",
+                    config.idle_timeout
+                );
+
+                return Ok(());
+            }
+
+            let deadline = match backend.is_connected() {
+                true => limiter.time_until_flush(clock.now()),
+                false => backend.time_until_retry(clock.now()),
+            };
+
+            let until_poll = next_poll.saturating_duration_since(clock.now());
+            let until_switch = switch_application_id_at.map(|at| at.saturating_duration_since(clock.now()));
+            let until_heartbeat = heartbeat_at.map(|at| at.saturating_duration_since(clock.now()));
+            let until_idle = idle_at.map(|at| at.saturating_duration_since(clock.now()));
+            let until_highlight = highlight_until.map(|at| at.saturating_duration_since(clock.now()));
+            let until_pause = apply_pause_at.map(|at| at.saturating_duration_since(clock.now()));
+            let until_result = result_until.map(|at| at.saturating_duration_since(clock.now()));
+            let until_rematch = rematch_until.map(|at| at.saturating_duration_since(clock.now()));
+            let until_condense = condense_at.map(|at| at.saturating_duration_since(clock.now()));
+            let timeout = [
+                deadline,
+                until_switch,
+                until_heartbeat,
+                until_idle,
+                until_highlight,
+                until_pause,
+                until_result,
+                until_rematch,
+                until_condense,
+            ]
+            .into_iter()
+            .flatten()
+            .fold(until_poll, Duration::min);
+
+            let message = match next_message(&rx, Some(timeout)) {
+                Some(message) => message,
+                None => continue,
+            };
+
+            last_message = clock.now();
+
+            // Whether the presence needs rebuilding as a result of this message.
+            let changed = match message {
+                Message::Dropping => {
+                    Self::save_presence(&ctx, &config, persist_dir, verbosity);
+
+                    let _ = flush(&mut backend, None, config.dry_run, &metrics, status, verbosity);
+                    return Ok(());
+                },
+
+                Message::ClearAndStop(ack) => {
+                    Self::save_presence(&ctx, &config, persist_dir, verbosity);
+
+                    let _ = flush(&mut backend, None, config.dry_run, &metrics, status, verbosity);
+                    let _ = ack.send(());
+                    return Ok(());
+                },
+
+                Message::UpdateConfig(mut new_config) => match new_config.validate() {
+                    // Only `build` and `from_toml_str` validate, and a `Config` can just as well be
+                    // put together by hand. Whatever's running now is kept instead.
+                    Err(e) => {
+                        verbosity::log!(verbosity, warn, target: Log::DiscordRPC, error = ?e, "Ignoring invalid config update");
+                        false
+                    },
+
+                    Ok(()) => {
+                        if new_config.osd_callback.is_none() {
+                            new_config.osd_callback = config.osd_callback.take();
+                        }
+
+                        // Everything else applies straight away, but reconnecting is expensive.
+                        switch_application_id_at = match new_config.application_id == application_id {
+                            true => None,
+                            false => Some(clock.now() + APPLICATION_ID_DEBOUNCE),
+                        };
+
+                        config = *new_config;
+                        config.warn_if_update_interval_is_raised(verbosity);
+                        config.drop_invalid_buttons(verbosity);
+                        config.drop_unknown_asset_overrides(verbosity);
+                        throttle.configure(config.effective_update_interval(), config.adaptive_interval);
+                        limiter.set_interval(throttle.interval());
+                        backend.set_osd_callback(config.osd_callback.clone());
+                        backend.set_max_reconnect_attempts(config.max_reconnect_attempts, clock.now());
+                        backend.set_connect_timeout(config.connect_timeout);
+
+                        // The new settings are shown in full, at least for a while.
+                        ctx.condensed = false;
+                        condense_at = condense_after(&config);
+
+                        if let Some(callback) = &config.osd_callback {
+                            callback.call("Discord Rich Presence settings applied");
+                        }
+
+                        true
+                    },
+                },
+
+                Message::GameStart(info) => {
+                    ctx.game = Some(info);
+                    ctx.game_from_memory = false;
+                    ctx.matchmaking = None;
+                    ctx.last_result = None;
+                    ctx.last_report = None;
+                    result_until = None;
+                    ctx.awaiting_rematch_since = None;
+                    rematch_until = None;
+                    ctx.lobby = None;
+                    ctx.started_at = unix_millis();
+                    true
+                },
+
+                // A pause that hasn't been applied yet belongs to the match that just ended.
+                // The result is shown for a little while before going back to whatever's next.
+                Message::GameEnd { result } => {
+                    let finished = ctx.game.take().filter(|_| result != GameResult::NoContest);
+                    ctx.end_game();
+                    ctx.last_result = finished.map(|info| (result, info));
+                    result_until = ctx.last_result.as_ref().map(|_| clock.now() + RESULT_DURATION);
+                    pending_pause = None;
+                    true
+                },
+
+                // This can arrive on either side of `GameEnd`, so it's held until the next match
+                // starts, but only shows while there's a result.
+                Message::GameReport(report) => {
+                    ctx.last_report = Some(report);
+                    ctx.last_result.is_some()
+                },
+
+                // Only a match in progress has anything to call out.
+                Message::Event(event) => match ctx.game.is_some() && !config.highlight_duration.is_zero() {
+                    true => {
+                        ctx.highlight = Some(event);
+                        highlight_until = Some(clock.now() + config.highlight_duration);
+                        true
+                    },
+
+                    false => false,
+                },
+
+                // Toggles within the debounce window collapse into the last one, which is applied
+                // as of when it arrived.
+                Message::SetPaused(paused) => {
+                    pending_pause = Some((paused, unix_millis()));
+                    apply_pause_at = Some(clock.now() + PAUSE_DEBOUNCE);
+                    false
+                },
+
+                // Anything with a higher priority hides these, so they only show once it's over.
+                Message::PlaybackStarted(info) => {
+                    ctx.playback = Some(info);
+                    matches!(ctx.source(), PresenceSource::Playback(_))
+                },
+
+                Message::PlaybackStopped => {
+                    ctx.playback = None;
+                    matches!(
+                        ctx.source(),
+                        PresenceSource::App(_)
+                            | PresenceSource::Selecting(_)
+                            | PresenceSource::WaitingForGame
+                            | PresenceSource::Menus
+                    )
+                },
+
+                Message::SetAppState(state) => {
+                    ctx.app_state = state;
+                    matches!(
+                        ctx.source(),
+                        PresenceSource::App(_)
+                            | PresenceSource::Selecting(_)
+                            | PresenceSource::WaitingForGame
+                            | PresenceSource::Menus
+                    )
+                },
+
+                Message::MatchmakingSearching(mode) => {
+                    ctx.matchmaking = Some(Matchmaking::Searching {
+                        mode,
+                        since: unix_millis(),
+                    });
+                    matches!(ctx.source(), PresenceSource::Matchmaking(_))
+                },
+
+                // This holds until the match starts, so there's no gap in between.
+                Message::MatchmakingFound => match ctx.matchmaking {
+                    Some(Matchmaking::Searching { mode, .. }) => {
+                        ctx.matchmaking = Some(Matchmaking::Found(mode));
+                        matches!(ctx.source(), PresenceSource::Matchmaking(_))
+                    },
+
+                    _ => false,
+                },
+
+                Message::MatchmakingCancelled => {
+                    let visible = matches!(ctx.source(), PresenceSource::Matchmaking(_));
+                    ctx.matchmaking = None;
+                    visible
+                },
+
+                // Like stocks, an opponent that arrives before the match has started is held.
+                Message::SetOpponent { code, display_name } => {
+                    ctx.set_opponent(code, display_name);
+                    ctx.game.is_some()
+                },
+
+                Message::SetConnectCode(connect_code) => {
+                    ctx.connect_code = Some(connect_code);
+                    true
+                },
+
+                // Polls happen on this thread too, so there's no read in flight to tear. Poll
+                // straight away so that we pick up whatever's at the new base.
+                Message::UpdateRamOffset(ram_offset) => {
+                    memory.set_ram_offset(ram_offset);
+                    next_poll = clock.now();
+
+                    let waiting = !memory.is_mapped();
+                    let changed = ctx.waiting_for_emulator != waiting;
+                    ctx.waiting_for_emulator = waiting;
+
+                    if changed {
+                        verbosity::log!(
+                            verbosity,
+                            debug,
+                            target: Log::DiscordRPC,
+                            mapped = !waiting,
+                            "Emulated memory has moved"
+                        );
+                    }
+
+                    changed && matches!(ctx.source(), PresenceSource::WaitingForGame | PresenceSource::Menus)
+                },
+
+                Message::SetSpectateId(spectate_id) => {
+                    ctx.spectate_id = spectate_id;
+                    true
+                },
+
+                Message::UpdateRank { tier, rating } => {
+                    ctx.rank = Some(Rank { tier, rating });
+                    true
+                },
+
+                // Stocks that arrive before the match has started are just held onto.
+                Message::UpdateStocks { p1, p2 } => {
+                    ctx.stocks = Some((p1, p2));
+                    ctx.game.is_some()
+                },
+
+                // These can arrive as often as every frame, so only bother rebuilding when
+                // they're shown.
+                Message::UpdatePercents { p1, p2 } => {
+                    ctx.percents = Some((p1, p2));
+                    ctx.game.is_some() && config.show_percents
+                },
+
+                // Each field counts as it would on its own, but there's only the one rebuild.
+                Message::UpdateTick(tick) => {
+                    let mut changed = false;
+
+                    if let Some((p1, p2)) = tick.stocks {
+                        ctx.stocks = Some((p1, p2));
+                        changed |= ctx.game.is_some();
+                    }
+
+                    if let Some((p1, p2)) = tick.percents {
+                        ctx.percents = Some((p1, p2));
+                        changed |= ctx.game.is_some() && config.show_percents;
+                    }
+
+                    if let Some(frame) = tick.frame {
+                        changed |= ctx.set_frame(frame) && ctx.game.is_some() && config.show_ingame_time;
+                    }
+
+                    // This is likely repeated every tick, so only an actual toggle restarts the
+                    // debounce.
+                    let paused = pending_pause.map_or(ctx.paused_at.is_some(), |(paused, _)| paused);
+
+                    if let Some(toggled) = tick.paused.filter(|&toggled| toggled != paused) {
+                        pending_pause = Some((toggled, unix_millis()));
+                        apply_pause_at = Some(clock.now() + PAUSE_DEBOUNCE);
+                    }
+
+                    changed
+                },
+
+                Message::UpdateSetScore { game, p1_wins, p2_wins } => {
+                    ctx.set_score = Some(SetScore { game, p1_wins, p2_wins });
+                    ctx.game.is_some()
+                },
+
+                Message::SetEnded => ctx.set_score.take().is_some() && ctx.game.is_some(),
+
+                Message::UpdateSessionRecord { wins, losses } => {
+                    ctx.session_record = Some((wins, losses));
+                    true
+                },
+
+                Message::ResetSession => ctx.session_record.take().is_some(),
+
+                // Like the result, this only shows once anything above it is over.
+                Message::AwaitingRematch => {
+                    ctx.awaiting_rematch_since = Some(unix_millis());
+                    rematch_until = Some(clock.now() + REMATCH_TIMEOUT);
+                    matches!(ctx.source(), PresenceSource::Rematch { .. })
+                },
+
+                Message::UpdateLobby { current, max, role } => {
+                    ctx.lobby = (max > 0).then_some((current, max));
+                    ctx.lobby_role = role;
+                    !matches!(
+                        ctx.source(),
+                        PresenceSource::Match(_) | PresenceSource::Result(..) | PresenceSource::Rematch { .. }
+                    )
+                },
+
+                Message::Flush => {
+                    limiter.expedite();
+                    false
+                },
+
+                // The presence is replayed once the new connection is up, as after any other
+                // reconnect.
+                Message::Reconnect => {
+                    backend.reconnect(clock.now());
+                    false
+                },
+
+                Message::SetSuspended(true) => {
+                    // Anything pending was built after what was last sent, and is the presence
+                    // as it stands. (A heartbeat or replay takes the latter to queue it.)
+                    if let Suspension::Live = suspension {
+                        let latest = limiter.pending().or(last_sent.as_ref());
+                        suspension = Suspension::Frozen(Box::new(latest.cloned().flatten()));
+                    }
+
+                    false
+                },
+
+                Message::SetSuspended(false) => match std::mem::replace(&mut suspension, Suspension::Live) {
+                    Suspension::Frozen(_) => {
+                        limiter.expedite();
+                        true
+                    },
+
+                    Suspension::Live => false,
+                },
+
+                Message::SetLogLevel(level) => {
+                    verbosity.set_level(level);
+                    verbosity::log!(verbosity, info, target: Log::DiscordRPC, ?level, "Changed the log level");
+                    false
+                },
+
+                #[cfg(test)]
+                Message::Sync(ack) => {
+                    let _ = ack.send(());
+                    false
+                },
+
+                // This arrives every frame, so only bother rebuilding when it'd be visible.
+                Message::UpdateFrame(frame) => ctx.set_frame(frame) && ctx.game.is_some() && config.show_ingame_time,
+            };
+
+            publish_mode(&ctx);
+
+            if changed {
+                limiter.set_interval(throttle.record_change());
+
+                if limiter.queue(presence::update(&ctx, &config)) {
+                    metrics.record_coalesced();
+                }
+            }
+        }
+    }
+
+    /// Whether the background thread currently holds a live connection to Discord.
+    pub fn is_connected(&self) -> bool {
+        self.status() == ConnectionStatus::Connected
+    }
+
+    /// A snapshot of the handler's counters, e.g for debugging presence that lags behind.
+    pub fn metrics(&self) -> DiscordMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// The current state of the connection to Discord. In particular, this distinguishes
+    /// Discord not running at all (`Unavailable`) from a dropped connection (`Disconnected`).
+    pub fn status(&self) -> ConnectionStatus {
+        self.status.get()
+    }
+
+    /// Describes the most recent failure the background thread ran into (e.g, Discord
+    /// rejecting an activity), if any. This is cleared once it reconnects to Discord.
+    pub fn last_error(&self) -> Option<String> {
+        self.status.last_error().map(|error| error.message)
+    }
+
+    /// Which `ErrorCategory` the error described by `last_error` falls under, if any.
+    pub fn last_error_category(&self) -> Option<ErrorCategory> {
+        self.status.last_error().map(|error| error.category)
+    }
+
+    /// The game state most recently read out of RAM, or `None` if nothing has been read yet
+    /// (e.g, because the RAM offset isn't known). Reads happen every `MEMORY_POLL_INTERVAL`,
+    /// so this can lag behind the game by up to that long.
+    pub fn current_state(&self) -> Option<GameState> {
+        self.game_state.read().expect("Unable to acquire game state lock").clone()
+    }
+
+    /// How the match in progress came about, or `None` between matches.
+    pub fn current_mode(&self) -> Option<MatchmakingMode> {
+        *self.mode.read().expect("Unable to acquire mode lock")
+    }
+
+    /// Returns a receiver for connection state transitions, starting with the current state.
+    /// The receiver can be dropped at any time.
+    pub fn subscribe_state(&self) -> Receiver<ConnectionState> {
+        self.status.subscribe()
+    }
+
+    /// Returns a `DiscordHandle`, for awaiting connection changes from async code.
+    #[cfg(feature = "async")]
+    pub fn handle(&self) -> crate::DiscordHandle {
+        crate::DiscordHandle::new(self.status.clone())
+    }
+
+    /// Passes a new configuration to the background thread.
+    ///
+    /// This, like the other methods that message the background thread, returns `ChannelClosed`
+    /// if it has exited (e.g, after a panic), in which case the handler should be recreated.
+    pub fn update_config(&self, config: Config) -> Result<()> {
+        self.send(
+            Message::UpdateConfig(Box::new(config)),
+            "Failed to send DiscordHandler config update",
+        )
+    }
+
+    /// Sends whatever activity is waiting on `Config::update_interval` without waiting out the
+    /// rest of it. Discord's own rate limit still applies, so if that's been hit the activity
+    /// goes out as soon as it allows instead.
+    pub fn flush(&self) -> Result<()> {
+        self.send(Message::Flush, "Failed to send DiscordHandler flush")
+    }
+
+    /// Drops the connection to Discord and reconnects straight away, e.g after the user has
+    /// restarted Discord. The presence is resent once it's back. This also starts the count
+    /// toward `Config::max_reconnect_attempts` over, so it retries even once that's given up.
+    pub fn reconnect(&self) -> Result<()> {
+        self.send(Message::Reconnect, "Failed to send DiscordHandler reconnect")
+    }
+
+    /// Freezes the presence as it is, ignoring any updates until `resume` (e.g, during a
+    /// recording). The connection to Discord stays up in the meantime.
+    pub fn suspend(&self) -> Result<()> {
+        self.send(Message::SetSuspended(true), "Failed to suspend DiscordHandler")
+    }
+
+    /// Lifts a `suspend`, sending the latest presence straight away.
+    pub fn resume(&self) -> Result<()> {
+        self.send(Message::SetSuspended(false), "Failed to resume DiscordHandler")
+    }
+
+    /// Sets how verbose this handler's logging is, from here on and from every thread it logs
+    /// from: only events at `level` or above make it through (to whatever the subscriber then
+    /// lets through). Until it's set, that's left to the subscriber alone. Keeping this at `WARN`
+    /// and lowering it to `DEBUG` for a while can help with chasing down why the presence
+    /// isn't what it should be, without a restart.
+    pub fn set_log_level(&self, level: tracing::Level) -> Result<()> {
+        self.send(Message::SetLogLevel(level), "Failed to send DiscordHandler log level")
+    }
+
+    /// Watches the TOML config file at `path`, applying it whenever it changes (and once it
+    /// exists, if it doesn't yet). Edits that fail to parse are logged and otherwise ignored.
+    /// See `Config::from_toml_str` for the format.
+    pub fn watch_config_file(&self, path: PathBuf) -> Result<()> {
+        watcher::spawn(path, self.tx.clone(), self.stopped.clone(), self.verbosity.clone())
+    }
+
+    /// Tells the background thread that the local player is waiting in a lobby with `current`
+    /// of `max` players, which is shown until the match starts. Pass a `max` of zero once
+    /// they've left the lobby without playing. `role` is whether they're there to play or to
+    /// spectate; the party size counts everyone either way.
+    pub fn update_lobby(&self, current: u32, max: u32, role: LobbyRole) -> Result<()> {
+        self.send(
+            Message::UpdateLobby { current, max, role },
+            "Failed to send DiscordHandler lobby update",
+        )
+    }
+
+    /// Notifies the background thread that a match has started.
+    pub fn notify_game_start(&self, info: GameInfo) -> Result<()> {
+        self.send(Message::GameStart(info), "Failed to notify DiscordHandler of game start")
+    }
+
+    /// Notifies the background thread that the user has started watching a replay of the
+    /// described match. If a live match is also in progress, that's shown instead.
+    pub fn notify_playback_start(&self, info: GameInfo) -> Result<()> {
+        self.send(
+            Message::PlaybackStarted(info),
+            "Failed to notify DiscordHandler of playback start",
+        )
+    }
+
+    /// Notifies the background thread that replay playback has stopped.
+    pub fn notify_playback_end(&self) -> Result<()> {
+        self.send(Message::PlaybackStopped, "Failed to notify DiscordHandler of playback end")
+    }
+
+    /// Passes a big moment in the current match to the background thread, which calls it
+    /// out in the presence for `Config::highlight_duration`.
+    pub fn notify_event(&self, event: GameEvent) -> Result<()> {
+        self.send(Message::Event(event), "Failed to send DiscordHandler game event")
+    }
+
+    /// Notifies the background thread that the match in progress has been paused (or
+    /// unpaused). The presence only follows once it's stayed that way for a moment.
+    pub fn set_paused(&self, paused: bool) -> Result<()> {
+        self.send(Message::SetPaused(paused), "Failed to update DiscordHandler pause state")
+    }
+
+    /// Tells the background thread what Slippi itself is up to (e.g, updating). This is only
+    /// shown when there's nothing more specific to show.
+    pub fn set_app_state(&self, state: AppState) -> Result<()> {
+        self.send(Message::SetAppState(state), "Failed to send DiscordHandler app state")
+    }
+
+    /// Notifies the background thread that the user has started searching for an online match.
+    pub fn notify_matchmaking_searching(&self, mode: MatchmakingMode) -> Result<()> {
+        self.send(
+            Message::MatchmakingSearching(mode),
+            "Failed to notify DiscordHandler of matchmaking search",
+        )
+    }
+
+    /// Notifies the background thread that an opponent was found. The presence says so until
+    /// the match starts.
+    pub fn notify_matchmaking_found(&self) -> Result<()> {
+        self.send(
+            Message::MatchmakingFound,
+            "Failed to notify DiscordHandler of matchmaking result",
+        )
+    }
+
+    /// Notifies the background thread that the user stopped searching for a match.
+    pub fn notify_matchmaking_cancelled(&self) -> Result<()> {
+        self.send(
+            Message::MatchmakingCancelled,
+            "Failed to notify DiscordHandler of matchmaking cancellation",
+        )
+    }
+
+    /// Passes updated stock counts to the background thread.
+    pub fn update_stocks(&self, p1: u8, p2: u8) -> Result<()> {
+        self.send(Message::UpdateStocks { p1, p2 }, "Failed to send DiscordHandler stock update")
+    }
+
+    /// Passes updated damage percents to the background thread. These are only shown with
+    /// `Config::show_percents`.
+    pub fn update_percents(&self, p1: f32, p2: f32) -> Result<()> {
+        self.send(
+            Message::UpdatePercents { p1, p2 },
+            "Failed to send DiscordHandler percent update",
+        )
+    }
+
+    /// Tells the background thread where the best-of set being played stands, e.g game 2 with
+    /// p1 up 1-0. This is shown alongside every match until `notify_set_ended` is called, or
+    /// replaced by the score of the next set.
+    pub fn update_set_score(&self, game: u8, p1_wins: u8, p2_wins: u8) -> Result<()> {
+        self.send(
+            Message::UpdateSetScore { game, p1_wins, p2_wins },
+            "Failed to send DiscordHandler set score",
+        )
+    }
+
+    /// Tells the background thread that the best-of set is over.
+    pub fn notify_set_ended(&self) -> Result<()> {
+        self.send(Message::SetEnded, "Failed to send DiscordHandler set end")
+    }
+
+    /// Passes the local player's wins and losses so far this session to the background thread,
+    /// which shows them (e.g "Session: 7W-3L") until `reset_session` is called. They're only
+    /// saved across restarts with `Config::persist_presence`.
+    pub fn update_session_record(&self, wins: u32, losses: u32) -> Result<()> {
+        self.send(
+            Message::UpdateSessionRecord { wins, losses },
+            "Failed to send DiscordHandler session record",
+        )
+    }
+
+    /// Tells the background thread to stop showing the session record.
+    pub fn reset_session(&self) -> Result<()> {
+        self.send(Message::ResetSession, "Failed to send DiscordHandler session reset")
+    }
+
+    /// Tells the background thread that the local player is waiting on a rematch, e.g once a
+    /// set has ended. This is shown (after the result) until the next match starts, or for
+    /// `REMATCH_TIMEOUT` if it never does.
+    pub fn notify_awaiting_rematch(&self) -> Result<()> {
+        self.send(
+            Message::AwaitingRematch,
+            "Failed to notify DiscordHandler of a pending rematch",
+        )
+    }
+
+    /// Passes the current match's frame counter to the background thread. This is cheap
+    /// enough to call every frame.
+    pub fn update_frame(&self, frame: i32) -> Result<()> {
+        self.send(Message::UpdateFrame(frame), "Failed to send DiscordHandler frame update")
+    }
+
+    /// Passes whatever's changed this batch of frames (stocks, percents, the frame counter
+    /// and pause state) to the background thread in one go, in place of a message for each.
+    /// They're all applied before anything is sent, so at most one update goes out for them.
+    /// Pausing is still debounced as with `set_paused`, so it's fine to pass it every time.
+    pub fn update_tick(&self, tick: TickUpdate) -> Result<()> {
+        self.send(Message::UpdateTick(tick), "Failed to send DiscordHandler tick update")
+    }
+
+    /// Passes the current opponent's connect code (and display name, which is shown instead
+    /// if it's set) to the background thread. This only lasts until the match ends.
+    pub fn set_opponent(&self, code: String, display_name: Option<String>) -> Result<()> {
+        self.send(
+            Message::SetOpponent { code, display_name },
+            "Failed to send DiscordHandler opponent",
+        )
+    }
+
+    /// Passes the local player's connect code to the background thread, which enables
+    /// Discord's "Ask to Join".
+    pub fn set_connect_code(&self, connect_code: String) -> Result<()> {
+        self.send(
+            Message::SetConnectCode(connect_code),
+            "Failed to send DiscordHandler connect code",
+        )
+    }
+
+    /// Passes a new base address for emulated RAM to the background thread. This should be
+    /// called whenever Dolphin remaps it. An offset of 0 means memory isn't mapped (yet), in
+    /// which case nothing is read and the presence waits for the game instead.
+    pub fn update_ram_offset(&self, ram_offset: usize) -> Result<()> {
+        self.send(
+            Message::UpdateRamOffset(ram_offset),
+            "Failed to send DiscordHandler RAM offset update",
+        )
+    }
+
+    /// Passes the local player's ranked tier and rating to the background thread.
+    pub fn update_rank(&self, tier: RankTier, rating: f32) -> Result<()> {
+        self.send(
+            Message::UpdateRank { tier, rating },
+            "Failed to send DiscordHandler rank update",
+        )
+    }
+
+    /// Returns a channel that receives a `JoinRequest` whenever the user accepts a Discord
+    /// invite to join another player. Each call returns a new, independent receiver.
+    pub fn join_requests(&self) -> Receiver<JoinRequest> {
+        self.join_requests.subscribe()
+    }
+
+    /// Passes the id that others can use to spectate the local player's current match (or
+    /// `None` if it can't be spectated) to the background thread. This is only surfaced in
+    /// Discord if `Config::enable_spectate` is set.
+    pub fn set_spectate_id(&self, spectate_id: Option<String>) -> Result<()> {
+        self.send(
+            Message::SetSpectateId(spectate_id),
+            "Failed to send DiscordHandler spectate id",
+        )
+    }
+
+    /// Returns a channel that receives a `SpectateRequest` whenever the user clicks "Spectate"
+    /// on another player's Discord activity. Each call returns a new, independent receiver.
+    pub fn spectate_requests(&self) -> Receiver<SpectateRequest> {
+        self.spectate_requests.subscribe()
+    }
+
+    /// Notifies the background thread that the current match has ended, with `result` for the
+    /// local player. Unless it's `GameResult::NoContest`, that's shown for a few seconds.
+    pub fn notify_game_end(&self, result: GameResult) -> Result<()> {
+        self.send(Message::GameEnd { result }, "Failed to notify DiscordHandler of game end")
+    }
+
+    /// Notifies the background thread of Slippi's report on the match that just ended, which
+    /// fills in the result shown for it.
+    pub fn notify_game_report(&self, report: GameReport) -> Result<()> {
+        self.send(Message::GameReport(report), "Failed to notify DiscordHandler of game report")
+    }
+
+    /// Registers a best-effort hook that clears the activity if the process receives SIGINT or
+    /// SIGTERM, so that it doesn't linger in Discord after e.g a crash or a kill. The process
+    /// still exits afterwards, as it would have without the hook.
+    ///
+    /// This is idempotent, and harmless if the background thread has already exited. It's a
+    /// no-op on Windows, which lacks equivalent signals; there, Discord eventually clears the
+    /// activity itself once the named pipe is closed by the OS.
+    pub fn install_cleanup_hook(&self) -> Result<()> {
+        cleanup::install(&self.cleanup, SHUTDOWN_TIMEOUT)
+    }
+
+    /// Shuts down the background thread, waiting (up to a bounded timeout) for it to clear
+    /// the activity and exit. This is what `Drop` does, but surfaces any error instead of
+    /// logging it.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop()
+    }
+
+    /// Connects to Discord (which, for a live `Connection`, means waiting on it to say it's
+    /// `READY`) and then sends `initial` straight away, so that there's never a moment where
+    /// we're connected but showing nothing. Returns whether `initial` was sent, which it isn't
+    /// if it's `None`, or if Discord isn't running (or ready) yet.
+    ///
+    /// A failed connection is recorded, but it's up to the backend to retry it, just as it
+    /// would a dropped one.
+    fn connect_and_init<B: DiscordBackend>(
+        backend: &mut B,
+        initial: Option<&Activity>,
+        now: Instant,
+        config: &Config,
+        metrics: &Metrics,
+        status: &SharedStatus,
+        verbosity: &Verbosity,
+    ) -> bool {
+        if let Err(e) = backend.connect(now) {
+            status.record_error(&e);
+            return false;
+        }
+
+        match initial {
+            Some(initial) if backend.is_connected() => {
+                flush(backend, Some(initial), config.dry_run, metrics, status, verbosity).is_ok()
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Saves `ctx` under `dir` for the next handler to pick up, if `Config::persist_presence`
+    /// is set.
+    fn save_presence(ctx: &PresenceContext, config: &Config, dir: &Path, verbosity: &Verbosity) {
+        if !config.persist_presence {
+            return;
+        }
+
+        if let Err(e) = persist::save(&persist::path_for(dir, config), ctx, unix_millis()) {
+            verbosity::log!(verbosity, warn, target: Log::DiscordRPC, error = ?e, "Failed to save the current presence");
+        }
+    }
+
+    /// Sends `message` to the background thread. If it's gone, this logs `description` and
+    /// returns `ChannelClosed`; the handler won't recover, so callers may want to recreate it.
+    fn send(&self, message: Message, description: &str) -> Result<()> {
+        let _entered = self.span.enter();
+
+        self.tx.send(message).map_err(|e| {
+            verbosity::log!(self.verbosity, error, target: Log::DiscordRPC, error = ?e, "{description}");
+            ChannelClosed
+        })
+    }
+
+    /// Notifies the background thread that we're going away and joins it.
+    ///
+    /// If the thread doesn't exit within `SHUTDOWN_TIMEOUT` (e.g, it's stuck waiting on an
+    /// unresponsive Discord client) it's left detached rather than blocking the caller. Until
+    /// it does exit, it keeps its claim on `RUNNING`.
+    ///
+    /// If we're being dropped from the background thread itself (i.e, from within the OSD
+    /// callback), we can't wait on it; it'll see `Dropping` and exit once the callback returns.
+    fn stop(&mut self) -> Result<()> {
+        let _entered = self.span.enter();
+        self.cleanup.detach();
+        self.stopped.store(true, Ordering::Relaxed);
+
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+
+        if let Err(e) = self.tx.send(Message::Dropping) {
+            verbosity::log!(
+                self.verbosity,
+                warn,
+                target: Log::DiscordRPC,
+                "Failed to notify child thread that DiscordHandler is dropping: {e}"
+            );
+        }
+
+        if thread.thread().id() == thread::current().id() {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+        while !thread.is_finished() {
+            if Instant::now() >= deadline {
+                verbosity::log!(
+                    self.verbosity,
+                    warn,
+                    target: Log::DiscordRPC,
+                    "Timed out waiting for SlippiDiscordRPC thread to exit"
+                );
+                return Ok(());
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        thread.join().map_err(|panic| {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .map(|reason| reason.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown".to_string());
+
+            ThreadPanic(reason)
+        })
+    }
+}
+
+/// Writes `activity` to the backend (or clears it, for `None`), timing the write. If it fails,
+/// the error is logged and recorded on `status` before being returned.
+///
+/// For a `dry_run`, the activity is only logged and the backend is left alone. Either way, its
+/// text is sanitized first (see `Activity::sanitize`).
+fn flush<B: DiscordBackend>(
+    backend: &mut B,
+    activity: Option<&Activity>,
+    dry_run: bool,
+    metrics: &Metrics,
+    status: &SharedStatus,
+    verbosity: &Verbosity,
+) -> Result<()> {
+    let _span = tracing::debug_span!(target: Log::DiscordRPC, "presence_flush").entered();
+    let started = Instant::now();
+
+    let activity = activity.cloned().map(|mut activity| {
+        for text in activity.sanitize() {
+            verbosity::log!(
+                verbosity,
+                debug,
+                target: Log::DiscordRPC,
+                "Truncated presence text to {MAX_FIELD_CHARS} characters: {text}"
+            );
+        }
+
+        activity
+    });
+
+    if dry_run {
+        verbosity::log!(
+            verbosity,
+            info,
+            target: Log::DiscordRPC,
+            "Dry run, not sending Discord activity: {}",
+            dry_run_payload(activity.as_ref())
+        );
+        return Ok(());
+    }
+
+    let result = match &activity {
+        Some(activity) => backend.set_activity(activity),
+        None => backend.clear(),
+    };
+
+    match result {
+        Ok(()) => {
+            metrics.record_sent();
+            verbosity::log!(verbosity, debug, target: Log::DiscordRPC, elapsed = ?started.elapsed(), "Sent Discord activity");
+            Ok(())
+        },
+
+        Err(e) => {
+            verbosity::log!(
+                verbosity,
+                warn,
+                target: Log::DiscordRPC,
+                error = ?e,
+                elapsed = ?started.elapsed(),
+                "Failed to set Discord activity"
+            );
+
+            status.record_error(&e);
+            Err(e)
+        },
+    }
+}
+
+/// How a dry run logs `activity`.
+fn dry_run_payload(activity: Option<&Activity>) -> String {
+    match activity {
+        Some(activity) => format!("{activity:#?}"),
+        None => "(cleared)".to_string(),
+    }
+}
+
+/// Waits for the next message, giving up after `timeout` (if one is set) so that the caller
+/// can flush anything pending. Returns `None` if the timeout elapsed.
+///
+/// Every sender having gone away means there's nobody left to drive us, which is no different
+/// from being told the handler is dropping, so it's reported as `Message::Dropping`.
+fn next_message(rx: &Receiver<Message>, timeout: Option<Duration>) -> Option<Message> {
+    let received = match timeout {
+        Some(timeout) => rx.recv_timeout(timeout),
+        None => rx.recv().map_err(RecvTimeoutError::from),
+    };
+
+    match received {
+        Ok(message) => Some(message),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => Some(Message::Dropping),
+    }
+}
+
+impl Drop for DiscordHandler {
+    fn drop(&mut self) {
+        verbosity::log!(self.verbosity, info, target: Log::DiscordRPC, "Dropping DiscordRPC");
+
+        if let Err(e) = self.stop() {
+            verbosity::log!(
+                self.verbosity,
+                error,
+                target: Log::DiscordRPC,
+                error = ?e,
+                "SlippiDiscordRPC thread did not shut down cleanly"
+            );
+        }
+
+        // Anything still watching the connection (e.g a `DiscordHandle`) has nothing more to
+        // wait for, even if the background thread hasn't exited yet.
+        self.status.close();
+    }
+}
+
+/// Replays `messages` against a handler driven by `backend` and `clock`, returning every
+/// activity that was sent (including the clear on shutdown).
+///
+/// Each message is stamped with how long after the start it arrives; the clock is moved on to
+/// that point first, and the loop is woken so that anything that was due by then (e.g a result
+/// expiring) happens before the message is handled. The loop is stepped one message at a time,
+/// and rate limiting and the heartbeat are off, so the activities only depend on the messages
+/// and their timing.
+#[cfg(test)]
+pub(crate) fn run_scenario(
+    messages: impl IntoIterator<Item = (Duration, Message)>,
+    clock: crate::clock::ManualClock,
+    backend: crate::backend::MockBackend,
+) -> Vec<Option<Activity>> {
+    let config = Config {
+        update_interval: Duration::ZERO,
+        adaptive_interval: false,
+        heartbeat_interval: Duration::ZERO,
+        ..Default::default()
+    };
+
+    let sinks = EventSinks {
+        join_requests: Subscribers::new(),
+        spectate_requests: Subscribers::new(),
+    };
+
+    let handler = DiscordHandler::with_backend(
+        crate::memory::blank_ram_offset(),
+        config,
+        backend.clone(),
+        SharedStatus::new(),
+        sinks,
+        clock.clone(),
+    )
+    .unwrap();
+    let mut elapsed = Duration::ZERO;
+
+    for (at, message) in messages {
+        clock.advance(at.saturating_sub(elapsed));
+        elapsed = elapsed.max(at);
+
+        handler.sync();
+        handler
+            .send(message, "Failed to replay a message to the DiscordHandler thread")
+            .unwrap();
+        handler.sync();
+    }
+
+    handler.shutdown().unwrap();
+    backend.calls()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::clock::ManualClock;
+    use crate::memory::blank_ram_offset;
+    use crate::{GameKind, MatchmakingMode, DEFAULT_APPLICATION_ID};
+
+    /// Spins up a handler against a `MockBackend` (and blank memory), with rate limiting
+    /// disabled so that every activity is flushed before the next message is handled.
+    fn mock_handler() -> (DiscordHandler, MockBackend) {
+        let config = Config {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+
+        mock_handler_with(config, SystemClock, MockBackend::default())
+    }
+
+    /// Like `mock_handler`, but configured with `config` (as is, so rate limiting is only off if
+    /// it says so) and driven by `clock` and `backend`.
+    fn mock_handler_with<C: Clock + Clone + Send + 'static>(
+        config: Config,
+        clock: C,
+        backend: MockBackend,
+    ) -> (DiscordHandler, MockBackend) {
+        let handler = DiscordHandler::with_backend(
+            blank_ram_offset(),
+            config,
+            backend.clone(),
+            SharedStatus::new(),
+            mock_sinks(),
+            clock,
+        )
+        .unwrap();
+
+        (handler, backend)
+    }
+
+    /// Waits (for up to a second) for `condition` to hold, failing the test if it doesn't.
+    #[track_caller]
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting for a condition to hold");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn mock_sinks() -> EventSinks {
+        EventSinks {
+            join_requests: Subscribers::new(),
+            spectate_requests: Subscribers::new(),
+        }
+    }
+
+    fn game() -> GameInfo {
+        GameInfo {
+            stage_id: 32,
+            p1_character_id: 1,
+            p2_character_id: 18,
+            p1_costume_id: 0,
+            p2_costume_id: 0,
+            kind: GameKind::Singles,
+            timer_seconds: None,
+            matchmaking: MatchmakingMode::Offline,
+            players: [None; 4],
+            local_port: None,
+        }
+    }
+
+    #[test]
+    fn match_lifecycle_produces_activities_in_order() {
+        let (handler, backend) = mock_handler();
+
+        handler.notify_game_start(game()).unwrap();
+        handler.update_stocks(4, 3).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        let details: Vec<Option<&str>> = calls
+            .iter()
+            .map(|call| call.as_ref().and_then(|activity| activity.details.as_deref()))
+            .collect();
+
+        assert_eq!(
+            details,
+            vec![
+                None,
+                Some("Battling on Final Destination"),
+                Some("Battling on Final Destination"),
+                Some("In menus"),
+                None,
+            ]
+        );
+
+        assert_eq!(calls[0], Some(Activity::slippi()));
+        assert_eq!(calls[2].as_ref().unwrap().state.as_deref(), Some("Fox (4) vs Marth (3)"));
+        assert_eq!(calls[4], None);
+    }
+
+    #[test]
+    fn a_recorded_match_replays_deterministically() {
+        let report = GameReport {
+            duration_frames: 222 * 60,
+            winner_port: Some(0),
+            stocks_remaining: [2, 0, 0, 0],
+            damage_dealt: [187.0, 0.0, 0.0, 0.0],
+        };
+
+        let secs = Duration::from_secs;
+        let scenario = [
+            (
+                secs(5),
+                Message::UpdateLobby {
+                    current: 1,
+                    max: 2,
+                    role: LobbyRole::Participant,
+                },
+            ),
+            (
+                secs(20),
+                Message::UpdateLobby {
+                    current: 2,
+                    max: 2,
+                    role: LobbyRole::Participant,
+                },
+            ),
+            (secs(30), Message::GameStart(game())),
+            (secs(90), Message::UpdateStocks { p1: 4, p2: 3 }),
+            (secs(252), Message::GameEnd { result: GameResult::Win }),
+            (secs(252), Message::GameReport(report)),
+            // Once the result has been up for a while, it gives way to the menus.
+            (secs(252) + RESULT_DURATION, Message::UpdateRamOffset(blank_ram_offset())),
+        ];
+
+        let calls = run_scenario(scenario, ManualClock::new(), MockBackend::default());
+        let shown: Vec<_> = calls
+            .iter()
+            .map(|call| {
+                call.as_ref()
+                    .map(|activity| (activity.details.clone(), activity.state.clone()))
+            })
+            .collect();
+
+        let shown_as = |details: &str, state: Option<&str>| Some((Some(details.to_string()), state.map(str::to_string)));
+
+        assert_eq!(
+            shown,
+            vec![
+                Some((None, None)),
+                shown_as("In a lobby", None),
+                shown_as("In a lobby", None),
+                shown_as("Battling on Final Destination", Some("Fox vs Marth")),
+                shown_as("Battling on Final Destination", Some("Fox (4) vs Marth (3)")),
+                shown_as("Won as Fox!", None),
+                shown_as("Won as Fox — 2 stocks left", Some("3:42 · 187% dealt")),
+                shown_as("In menus", None),
+                None,
+            ]
+        );
+
+        assert_eq!(calls[1].as_ref().unwrap().party.as_ref().unwrap().size, Some([1, 2]));
+        assert_eq!(calls[2].as_ref().unwrap().party.as_ref().unwrap().size, Some([2, 2]));
+    }
+
+    #[test]
+    fn rematches_are_awaited_until_the_next_match_or_a_timeout() {
+        let secs = Duration::from_secs;
+        let set_end = || {
+            [
+                (secs(0), Message::GameStart(game())),
+                (secs(60), Message::GameEnd { result: GameResult::Win }),
+                (secs(60), Message::SetEnded),
+                (secs(60), Message::AwaitingRematch),
+            ]
+        };
+
+        let details = |calls: Vec<Option<Activity>>| -> Vec<_> {
+            calls
+                .into_iter()
+                .map(|call| call.and_then(|activity| activity.details))
+                .collect()
+        };
+
+        let rematched = set_end().into_iter().chain([
+            (secs(60) + RESULT_DURATION, Message::UpdateRamOffset(blank_ram_offset())),
+            (secs(90), Message::GameStart(game())),
+        ]);
+
+        let calls = run_scenario(rematched, ManualClock::new(), MockBackend::default());
+        assert!(calls[3].as_ref().unwrap().timestamps.as_ref().unwrap().start.is_some());
+        assert_eq!(
+            details(calls),
+            vec![
+                None,
+                Some("Battling on Final Destination".to_string()),
+                Some("Won as Fox!".to_string()),
+                Some("Waiting for rematch".to_string()),
+                Some("Battling on Final Destination".to_string()),
+                None,
+            ]
+        );
+
+        let abandoned = set_end().into_iter().chain([
+            (secs(60) + RESULT_DURATION, Message::UpdateRamOffset(blank_ram_offset())),
+            (secs(60) + REMATCH_TIMEOUT, Message::UpdateRamOffset(blank_ram_offset())),
+        ]);
+
+        assert_eq!(
+            details(run_scenario(abandoned, ManualClock::new(), MockBackend::default())),
+            vec![
+                None,
+                Some("Battling on Final Destination".to_string()),
+                Some("Won as Fox!".to_string()),
+                Some("Waiting for rematch".to_string()),
+                Some("In menus".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn session_records_can_be_reset() {
+        let (handler, backend) = mock_handler();
+
+        handler.update_session_record(7, 3).unwrap();
+        handler.update_session_record(8, 3).unwrap();
+        handler.reset_session().unwrap();
+        handler.reset_session().unwrap();
+        handler.shutdown().unwrap();
+
+        let states: Vec<_> = backend
+            .calls()
+            .into_iter()
+            .map(|call| call.and_then(|activity| activity.state))
+            .collect();
+
+        assert_eq!(
+            states,
+            vec![
+                None,
+                Some("Session: 7W-3L".to_string()),
+                Some("Session: 8W-3L".to_string()),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn metrics_count_sent_and_coalesced_updates() {
+        let config = Config {
+            update_interval: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, SystemClock, MockBackend::default());
+        let metrics = handler.metrics.clone();
+
+        // The first activity goes straight out; everything after it is held for the interval,
+        // with each update replacing the last.
+        handler.notify_game_start(game()).unwrap();
+        handler.update_stocks(4, 3).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.shutdown().unwrap();
+
+        assert_eq!(
+            metrics.snapshot(),
+            DiscordMetrics {
+                updates_sent: 2,
+                updates_coalesced: 2,
+                updates_skipped_identical: 0,
+                reconnects: 0,
+                memory_read_failures: 0,
+                rate_limited_count: 0,
+            }
+        );
+
+        assert_eq!(backend.calls(), vec![Some(Activity::slippi()), None]);
+    }
+
+    #[test]
+    fn identical_updates_are_skipped() {
+        let (handler, backend) = mock_handler();
+        let metrics = handler.metrics.clone();
+
+        handler.set_connect_code("ABCD#123".to_string()).unwrap();
+        handler.set_connect_code("ABCD#123".to_string()).unwrap();
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 3);
+        assert!(calls[1].as_ref().unwrap().secrets.is_some());
+        assert_eq!(metrics.snapshot().updates_skipped_identical, 1);
+    }
+
+    #[test]
+    fn heartbeat_resends_the_activity_once_idle() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::ZERO,
+            heartbeat_interval: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+
+        wait_until(|| !backend.calls().is_empty());
+        clock.advance(Duration::from_secs(59));
+        handler.sync();
+        assert_eq!(backend.calls().len(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        wait_until(|| backend.calls().len() > 1);
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[1], calls[0]);
+    }
+
+    #[test]
+    fn activity_is_replayed_after_reconnecting() {
+        let (handler, backend) = mock_handler();
+
+        handler.notify_game_start(game()).unwrap();
+
+        // Let the game activity go out before dropping the connection.
+        wait_until(|| backend.calls().len() == 2);
+
+        // A message that doesn't change the presence, so that the loop makes a pass (and
+        // reconnects) without queueing anything new.
+        backend.drop_connection();
+        handler.update_ram_offset(blank_ram_offset()).unwrap();
+
+        let metrics = handler.metrics.clone();
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[2], calls[1]);
+        assert_eq!(
+            calls[2].as_ref().unwrap().details.as_deref(),
+            Some("Battling on Final Destination")
+        );
+        assert_eq!(metrics.snapshot().reconnects, 1);
+    }
+
+    #[test]
+    fn reconnecting_replays_the_presence() {
+        let (handler, backend) = mock_handler();
+        let metrics = handler.metrics.clone();
+
+        handler.notify_game_start(game()).unwrap();
+        wait_until(|| backend.calls().len() == 2);
+
+        // This is fine while connected, which just starts a new session.
+        handler.reconnect().unwrap();
+        wait_until(|| metrics.snapshot().reconnects > 0);
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[2], calls[1]);
+        assert_eq!(metrics.snapshot().reconnects, 1);
+    }
+
+    #[test]
+    fn changing_the_application_id_reconnects() {
+        let (handler, backend) = mock_handler();
+        let metrics = handler.metrics.clone();
+
+        handler
+            .update_config(Config {
+                update_interval: Duration::ZERO,
+                ..Default::default()
+            })
+            .unwrap();
+
+        handler
+            .update_config(Config {
+                application_id: "5678".to_string(),
+                update_interval: Duration::ZERO,
+                ..Default::default()
+            })
+            .unwrap();
+
+        wait_until(|| metrics.snapshot().reconnects > 0);
+        handler.shutdown().unwrap();
+
+        assert_eq!(backend.application_ids(), vec!["5678".to_string()]);
+        assert_eq!(metrics.snapshot().reconnects, 1);
+    }
+
+    #[test]
+    fn new_configs_reset_the_reconnect_attempts() {
+        let config = Config {
+            max_reconnect_attempts: Some(3),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, SystemClock, MockBackend::default());
+
+        // Even an unchanged limit starts the count over.
+        handler.update_config(Config::default()).unwrap();
+        handler.update_config(Config::default()).unwrap();
+        handler.shutdown().unwrap();
+
+        assert_eq!(backend.max_reconnect_attempts(), vec![Some(3), None, None]);
+    }
+
+    #[test]
+    fn invalid_config_updates_are_ignored() {
+        let (handler, backend) = mock_handler();
+
+        handler
+            .update_config(Config {
+                application_id: "not a snowflake".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        handler.shutdown().unwrap();
+
+        // Only the config the handler was started with was ever applied.
+        assert_eq!(backend.max_reconnect_attempts(), vec![None]);
+        assert!(backend.application_ids().is_empty());
+    }
+
+    #[test]
+    fn application_id_changes_are_debounced() {
+        let clock = ManualClock::new();
+        let config = Config {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let metrics = handler.metrics.clone();
+
+        for id in 1000..1010 {
+            handler
+                .update_config(Config {
+                    application_id: id.to_string(),
+                    update_interval: Duration::ZERO,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        handler.sync();
+        assert!(backend.application_ids().is_empty());
+
+        clock.advance(APPLICATION_ID_DEBOUNCE);
+        wait_until(|| metrics.snapshot().reconnects > 0);
+
+        // Give any stragglers a chance to (wrongly) go out.
+        clock.advance(APPLICATION_ID_DEBOUNCE);
+        handler.sync();
+        handler.shutdown().unwrap();
+
+        assert_eq!(backend.application_ids(), vec!["1009".to_string()]);
+        assert_eq!(metrics.snapshot().reconnects, 1);
+    }
+
+    #[test]
+    fn application_id_changes_that_revert_are_dropped() {
+        let clock = ManualClock::new();
+        let config = Config {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+
+        for application_id in ["5678", DEFAULT_APPLICATION_ID] {
+            handler
+                .update_config(Config {
+                    application_id: application_id.to_string(),
+                    update_interval: Duration::ZERO,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        handler.sync();
+        clock.advance(APPLICATION_ID_DEBOUNCE);
+        handler.sync();
+        handler.shutdown().unwrap();
+
+        assert!(backend.application_ids().is_empty());
+    }
+
+    #[test]
+    fn idle_presence_between_matches() {
+        let (handler, backend) = mock_handler();
+
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        let idle = calls[2].as_ref().unwrap();
+        assert_eq!(idle.details.as_deref(), Some("In menus"));
+        assert_eq!(idle.timestamps, None);
+        assert_eq!(idle.assets, Activity::slippi().assets);
+    }
+
+    #[test]
+    fn idle_presence_can_be_disabled() {
+        let config = Config {
+            update_interval: Duration::ZERO,
+            show_idle_presence: false,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, SystemClock, MockBackend::default());
+
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.shutdown().unwrap();
+
+        // Nothing at startup, the match, then a clear once it ends (and another on shutdown).
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 3);
+        assert!(calls[0].is_some());
+        assert_eq!(calls[1], None);
+        assert_eq!(calls[2], None);
+    }
+
+    #[test]
+    fn cleanup_clears_the_activity_and_is_harmless_afterwards() {
+        let (handler, backend) = mock_handler();
+
+        assert!(handler.cleanup.run(Duration::from_secs(1)));
+        assert_eq!(backend.calls().last(), Some(&None));
+
+        // The background thread has exited, so neither of these should do (or wait on) anything.
+        assert!(!handler.cleanup.run(Duration::from_secs(1)));
+        handler.shutdown().unwrap();
+
+        assert_eq!(backend.calls().len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutting_down_closes_the_signal_listener() {
+        let (handler, _backend) = mock_handler();
+        let cleanup = handler.cleanup.clone();
+
+        handler.install_cleanup_hook().unwrap();
+        assert!(cleanup.is_listening());
+
+        handler.shutdown().unwrap();
+        assert!(!cleanup.is_listening());
+        assert!(!cleanup.run(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn sends_fail_once_the_background_thread_has_exited() {
+        let (handler, _backend) = mock_handler();
+
+        handler.cleanup.run(Duration::from_secs(1));
+        wait_until(|| handler.thread.as_ref().is_some_and(|thread| thread.is_finished()));
+
+        assert!(matches!(handler.update_config(Config::default()), Err(ChannelClosed)));
+        assert!(matches!(handler.notify_game_end(GameResult::NoContest), Err(ChannelClosed)));
+    }
+
+    #[test]
+    fn idle_threads_exit_once_disconnected() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::ZERO,
+            idle_timeout: Duration::from_secs(30),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let finished = || handler.thread.as_ref().is_some_and(|thread| thread.is_finished());
+
+        // Being connected counts as activity, however long it's been since the last message.
+        wait_until(|| !backend.calls().is_empty());
+        clock.advance(Duration::from_secs(30));
+        handler.sync();
+        assert!(!finished());
+
+        // Syncing was itself a message, so the timeout starts over from there.
+        backend.set_offline(true);
+        clock.advance(Duration::from_secs(30));
+        wait_until(finished);
+        assert!(matches!(handler.notify_game_end(GameResult::NoContest), Err(ChannelClosed)));
+    }
+
+    #[test]
+    fn dropped_senders_stop_the_thread() {
+        let (tx, rx) = channel();
+        drop(tx);
+
+        let result = DiscordHandler::start(
+            rx,
+            0,
+            Config::default(),
+            MockBackend::default(),
+            SystemClock,
+            Metrics::default(),
+            &SharedStatus::new(),
+            sync_channel(1).0,
+            SharedGameState::default(),
+            SharedMode::default(),
+            &std::env::temp_dir(),
+            &Verbosity::default(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn shutting_down_ends_the_state_streams() {
+        let (handler, _backend) = mock_handler();
+        let status = handler.status.clone();
+        assert!(!status.is_closed());
+
+        handler.shutdown().unwrap();
+        assert!(status.is_closed());
+    }
+
+    #[test]
+    fn the_current_mode_follows_the_match() {
+        let (handler, _backend) = mock_handler();
+        assert_eq!(handler.current_mode(), None);
+
+        let ranked = GameInfo {
+            matchmaking: MatchmakingMode::Ranked,
+            ..game()
+        };
+
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_game_start(ranked).unwrap();
+        wait_until(|| handler.current_mode() == Some(MatchmakingMode::Ranked));
+        assert_eq!(handler.current_mode(), Some(MatchmakingMode::Ranked));
+
+        handler.notify_game_end(GameResult::Win).unwrap();
+        wait_until(|| handler.current_mode().is_none());
+        assert_eq!(handler.current_mode(), None);
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn dropping_the_handler_is_not_an_error() {
+        let (handler, backend) = mock_handler_with(Config::default(), SystemClock, MockBackend::default());
+        let status = handler.status.clone();
+
+        wait_until(|| !backend.calls().is_empty());
+        drop(handler);
+
+        // The thread logs at error level exactly when it records an error.
+        assert_eq!(status.last_error(), None);
+        assert_eq!(backend.calls().last(), Some(&None));
+    }
+
+    #[test]
+    fn flushing_skips_the_update_interval() {
+        let config = Config {
+            update_interval: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, ManualClock::new(), MockBackend::default());
+
+        wait_until(|| !backend.calls().is_empty());
+        handler.notify_game_start(game()).unwrap();
+
+        // Without a flush, this would be held for the rest of the interval (which the clock
+        // never gets to).
+        handler.sync();
+        assert_eq!(backend.calls().len(), 1);
+
+        handler.flush().unwrap();
+        handler.sync();
+
+        assert_eq!(backend.calls().len(), 2);
+        assert!(backend.calls()[1].as_ref().unwrap().state.is_some());
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn dry_runs_log_instead_of_sending() {
+        let config = Config {
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, SystemClock, MockBackend::default());
+
+        handler.notify_game_start(game()).unwrap();
+        handler.flush().unwrap();
+        handler.sync();
+
+        let metrics = handler.metrics();
+        handler.shutdown().unwrap();
+
+        assert_eq!(backend.calls(), Vec::new());
+        assert_eq!(metrics.updates_sent, 0);
+
+        let payload = dry_run_payload(Some(&Activity::slippi()));
+        assert!(payload.contains(crate::activity::SLIPPI_LOGO_ASSET), "{payload}");
+        assert!(payload.lines().count() > 1, "{payload}");
+        assert_eq!(dry_run_payload(None), "(cleared)");
+    }
+
+    #[test]
+    fn playback_waits_for_the_live_match() {
+        let (handler, backend) = mock_handler();
+
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_playback_start(game()).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.notify_playback_end().unwrap();
+        handler.shutdown().unwrap();
+
+        let details: Vec<_> = backend
+            .calls()
+            .iter()
+            .map(|call| call.as_ref().and_then(|activity| activity.details.clone()))
+            .collect();
+
+        assert_eq!(details.len(), 5);
+        assert_eq!(details[2].as_deref(), Some("Watching a replay"));
+        assert_eq!(details[3].as_deref(), Some("In menus"));
+    }
+
+    #[test]
+    fn matches_override_the_lobby_until_they_end() {
+        let (handler, backend) = mock_handler();
+
+        handler.update_lobby(1, 2, LobbyRole::Participant).unwrap();
+        handler.notify_game_start(game()).unwrap();
+        handler.update_lobby(2, 2, LobbyRole::Participant).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        let details: Vec<_> = calls
+            .iter()
+            .map(|call| call.as_ref().and_then(|activity| activity.details.clone()))
+            .collect();
+
+        assert_eq!(details.len(), 5);
+        assert_eq!(details[1].as_deref(), Some("In a lobby"));
+        assert_eq!(details[2].as_deref(), Some("Battling on Final Destination"));
+        assert_eq!(details[3].as_deref(), Some("In a lobby"));
+        assert_eq!(calls[3].as_ref().unwrap().party.as_ref().unwrap().size, Some([2, 2]));
+    }
+
+    #[test]
+    fn highlights_revert_once_expired() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::ZERO,
+            highlight_duration: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let state = || backend.calls().last().cloned().flatten().and_then(|activity| activity.state);
+
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_event(GameEvent::Kill { killer: 0 }).unwrap();
+        wait_until(|| state().as_deref() == Some("💥 Fox took a stock!"));
+
+        clock.advance(Duration::from_secs(4));
+        handler.sync();
+        assert_eq!(state().as_deref(), Some("💥 Fox took a stock!"));
+
+        clock.advance(Duration::from_secs(1));
+        wait_until(|| state().as_deref() == Some("Fox vs Marth"));
+        handler.shutdown().unwrap();
+
+        assert_eq!(backend.calls().len(), 5);
+    }
+
+    #[test]
+    fn stocks_before_a_match_are_held() {
+        let (handler, backend) = mock_handler();
+
+        handler.update_stocks(4, 4).unwrap();
+        handler.notify_game_start(game()).unwrap();
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[1].as_ref().unwrap().state.as_deref(), Some("Fox (4) vs Marth (4)"));
+    }
+
+    #[test]
+    fn set_scores_carry_over_until_the_set_ends() {
+        let (handler, backend) = mock_handler();
+
+        handler.update_set_score(1, 0, 0).unwrap();
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_game_end(GameResult::Win).unwrap();
+        handler.update_set_score(2, 1, 0).unwrap();
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_set_ended().unwrap();
+        handler.shutdown().unwrap();
+
+        let details: Vec<_> = backend
+            .calls()
+            .into_iter()
+            .flatten()
+            .filter_map(|activity| activity.details)
+            .filter(|details| details.starts_with("Battling"))
+            .collect();
+
+        assert_eq!(
+            details,
+            [
+                "Battling on Final Destination · Game 1 — 0-0",
+                "Battling on Final Destination · Game 2 — 1-0",
+                "Battling on Final Destination",
+            ]
+        );
+    }
+
+    #[test]
+    fn pausing_is_debounced() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let last = || backend.calls().last().cloned().flatten();
+
+        handler.notify_game_start(game()).unwrap();
+        wait_until(|| last().is_some_and(|activity| activity.state.as_deref() == Some("Fox vs Marth")));
+        let started_at = last().unwrap().timestamps.unwrap().start.unwrap();
+
+        // Mashing start settles on whatever it ends up at.
+        handler.set_paused(true).unwrap();
+        handler.set_paused(false).unwrap();
+        handler.set_paused(true).unwrap();
+        handler.sync();
+        clock.advance(PAUSE_DEBOUNCE);
+        wait_until(|| last().is_some_and(|activity| activity.timestamps.is_none()));
+        assert_eq!(last().unwrap().state.as_deref(), Some("Fox vs Marth (Paused)"));
+
+        // Timestamps are wall-clock time, which the `ManualClock` doesn't stand in for.
+        let paused_at = unix_millis();
+        wait_until(|| unix_millis() >= paused_at + 50);
+        handler.set_paused(false).unwrap();
+        handler.sync();
+        clock.advance(PAUSE_DEBOUNCE);
+        wait_until(|| last().is_some_and(|activity| activity.timestamps.is_some()));
+
+        let activity = last().unwrap();
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth"));
+        assert!(activity.timestamps.unwrap().start.unwrap() >= started_at + 50);
+        handler.shutdown().unwrap();
+
+        assert_eq!(backend.calls().len(), 5);
+    }
+
+    #[test]
+    fn log_levels_apply_to_every_thread() {
+        let (handler, _backend) = mock_handler();
+        assert!(handler.verbosity.enabled(tracing::Level::DEBUG));
+
+        // The background thread makes the change, and the caller's thread sees it.
+        handler.set_log_level(tracing::Level::WARN).unwrap();
+        handler.sync();
+
+        assert!(handler.verbosity.enabled(tracing::Level::WARN));
+        assert!(!handler.verbosity.enabled(tracing::Level::INFO));
+
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn presences_carry_over_to_the_next_handler() {
+        // This keeps clear of anything saved by a real handler, or another test run.
+        let dir = std::env::temp_dir().join(format!("slippi-discord-rpc-persist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let config = |label: &str| Config {
+            update_interval: Duration::ZERO,
+            persist_presence: true,
+            instance_label: Some(label.to_string()),
+            ..Default::default()
+        };
+
+        let spawn = |config: Config| {
+            let backend = MockBackend::default();
+            let handler = DiscordHandler::spawn(
+                blank_ram_offset(),
+                config,
+                backend.clone(),
+                SharedStatus::new(),
+                mock_sinks(),
+                SystemClock,
+                None,
+                dir.clone(),
+                Verbosity::default(),
+            )
+            .unwrap();
+
+            (handler, backend)
+        };
+
+        let state = |backend: &MockBackend| {
+            let calls = backend.calls();
+            calls.into_iter().flatten().last().and_then(|activity| activity.state)
+        };
+
+        let (handler, backend) = spawn(config("persisted"));
+        handler.notify_game_start(game()).unwrap();
+        wait_until(|| state(&backend).as_deref() == Some("Fox vs Marth"));
+        handler.shutdown().unwrap();
+
+        // Another instance doesn't pick it up, and leaves it for the one that should.
+        let (handler, backend) = spawn(config("other"));
+        wait_until(|| !backend.calls().is_empty());
+        handler.shutdown().unwrap();
+        assert_ne!(state(&backend).as_deref(), Some("Fox vs Marth"));
+
+        let (handler, backend) = spawn(config("persisted"));
+        wait_until(|| state(&backend).is_some());
+        handler.shutdown().unwrap();
+        assert_eq!(state(&backend).as_deref(), Some("Fox vs Marth"));
+
+        // Each of them saved what it was showing on the way out.
+        for label in ["persisted", "other"] {
+            assert!(persist::path_for(&dir, &config(label)).exists());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn updates_are_held_back_while_suspended() {
+        let (handler, backend) = mock_handler();
+        let state = || backend.calls().last().cloned().flatten().and_then(|activity| activity.state);
+
+        handler.notify_game_start(game()).unwrap();
+        wait_until(|| state().as_deref() == Some("Fox vs Marth"));
+        let sent = backend.calls().len();
+
+        handler.suspend().unwrap();
+        handler.update_stocks(4, 4).unwrap();
+        handler.update_stocks(4, 3).unwrap();
+        handler.sync();
+
+        assert_eq!(backend.calls().len(), sent);
+
+        handler.resume().unwrap();
+        handler.sync();
+
+        assert_eq!(backend.calls().len(), sent + 1);
+        assert_eq!(state().as_deref(), Some("Fox (4) vs Marth (3)"));
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn suspending_freezes_what_was_last_built() {
+        let config = Config {
+            update_interval: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, ManualClock::new(), MockBackend::default());
+        let state = || backend.calls().last().cloned().flatten().and_then(|activity| activity.state);
+
+        wait_until(|| !backend.calls().is_empty());
+
+        // The match is held back by the update interval, but it's still what's frozen.
+        handler.notify_game_start(game()).unwrap();
+        handler.suspend().unwrap();
+        handler.update_stocks(4, 3).unwrap();
+        handler.flush().unwrap();
+        handler.sync();
+
+        assert_eq!(backend.calls().len(), 2);
+        assert_eq!(state().as_deref(), Some("Fox vs Marth"));
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn ticks_update_everything_at_once() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::ZERO,
+            show_percents: true,
+            show_ingame_time: true,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let last = || backend.calls().last().cloned().flatten();
+        let state = || last().and_then(|activity| activity.state);
+
+        handler.notify_game_start(game()).unwrap();
+        wait_until(|| state().as_deref() == Some("Fox vs Marth"));
+        let sent = backend.calls().len();
+
+        let tick = TickUpdate {
+            stocks: Some((3, 2)),
+            percents: Some((87.0, 42.0)),
+            frame: Some(155 * 60),
+            paused: Some(false),
+        };
+
+        handler.update_tick(tick).unwrap();
+        let expected = "Fox (3) 87% vs Marth (2) 42% · 2:35";
+        wait_until(|| state().as_deref() == Some(expected));
+        assert_eq!(state().as_deref(), Some(expected));
+        assert_eq!(backend.calls().len(), sent + 1);
+
+        // Pausing on every tick is only the one toggle.
+        for _ in 0..3 {
+            handler
+                .update_tick(TickUpdate {
+                    paused: Some(true),
+                    ..tick
+                })
+                .unwrap();
+        }
+
+        handler.sync();
+        clock.advance(PAUSE_DEBOUNCE);
+        wait_until(|| last().is_some_and(|activity| activity.timestamps.is_none()));
+        assert_eq!(state().as_deref(), Some("Fox (3) 87% vs Marth (2) 42% · 2:35 (Paused)"));
+
+        handler.shutdown().unwrap();
+        assert_eq!(backend.calls().len(), sent + 3);
+    }
+
+    #[test]
+    fn presence_is_condensed_once_it_has_been_up_for_a_while() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::ZERO,
+            verbose_when_recent: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let state = || backend.calls().last().cloned().flatten().and_then(|activity| activity.state);
+
+        handler.update_session_record(7, 3).unwrap();
+        handler.notify_game_start(game()).unwrap();
+        let full = "Fox vs Marth · Session: 7W-3L";
+        wait_until(|| state().as_deref() == Some(full));
+        assert_eq!(state().as_deref(), Some(full));
+
+        handler.sync();
+        clock.advance(Duration::from_secs(10));
+        wait_until(|| state().as_deref() == Some("Fox vs Marth"));
+        assert_eq!(state().as_deref(), Some("Fox vs Marth"));
+
+        // The next match is something new again.
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.notify_game_start(game()).unwrap();
+        wait_until(|| state().as_deref() == Some(full));
+        assert_eq!(state().as_deref(), Some(full));
+
+        handler.shutdown().unwrap();
+    }
+
+    /// Spins up a `mock_handler` that claims `flag`, as `new` does with `RUNNING`.
+    fn claiming_handler(flag: &'static AtomicBool, backend: MockBackend) -> Result<DiscordHandler> {
+        let config = Config {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let instance = InstanceGuard::acquire(flag)?;
+        DiscordHandler::spawn(
+            blank_ram_offset(),
+            config,
+            backend,
+            SharedStatus::new(),
+            mock_sinks(),
+            SystemClock,
+            Some(instance),
+            std::env::temp_dir(),
+            Verbosity::default(),
+        )
+    }
+
+    #[test]
+    fn only_one_handler_runs_at_a_time() {
+        static CLAIMED: AtomicBool = AtomicBool::new(false);
+
+        let spawn = || thread::spawn(|| claiming_handler(&CLAIMED, MockBackend::default()));
+        let results: Vec<_> = [spawn(), spawn()].into_iter().map(|thread| thread.join().unwrap()).collect();
+
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(results.iter().any(|result| matches!(result, Err(AlreadyRunning))));
+        drop(results);
+
+        // Once the first one is gone, its slot is free again.
+        claiming_handler(&CLAIMED, MockBackend::default())
+            .unwrap()
+            .shutdown()
+            .unwrap();
+    }
+
+    #[test]
+    fn stuck_handlers_keep_their_claim_until_they_exit() {
+        static CLAIMED: AtomicBool = AtomicBool::new(false);
+
+        // The first write never returns, so shutting down gives up on the thread.
+        let backend = MockBackend::default();
+        backend.set_stalled(true);
+        claiming_handler(&CLAIMED, backend.clone()).unwrap().shutdown().unwrap();
+
+        assert!(matches!(
+            claiming_handler(&CLAIMED, MockBackend::default()),
+            Err(AlreadyRunning)
+        ));
+
+        // Once it finally exits, its slot is free again.
+        backend.set_stalled(false);
+        wait_until(|| !CLAIMED.load(Ordering::Acquire));
+        claiming_handler(&CLAIMED, MockBackend::default())
+            .unwrap()
+            .shutdown()
+            .unwrap();
+    }
+
+    #[test]
+    fn errors_are_kept_until_reconnecting() {
+        let (handler, backend) = mock_handler();
+        wait_until(|| backend.calls().len() == 1);
+        assert_eq!(handler.last_error(), None);
+
+        backend.set_rejecting(true);
+        handler.notify_game_start(game()).unwrap();
+        wait_until(|| handler.last_error().is_some());
+
+        assert_eq!(
+            handler.last_error(),
+            Some(Command("Activity rejected".to_string()).to_string())
+        );
+        assert_eq!(handler.last_error_category(), Some(ErrorCategory::Rejected));
+
+        backend.set_rejecting(false);
+        backend.drop_connection();
+        handler.flush().unwrap();
+        wait_until(|| handler.last_error().is_none());
+        assert_eq!(handler.last_error_category(), None);
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn results_give_way_to_idle() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let details = || {
+            backend
+                .calls()
+                .last()
+                .cloned()
+                .flatten()
+                .and_then(|activity| activity.details)
+        };
+
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_game_end(GameResult::Win).unwrap();
+        wait_until(|| details().as_deref() == Some("Won as Fox!"));
+
+        clock.advance(RESULT_DURATION - Duration::from_secs(1));
+        handler.sync();
+        assert_eq!(details().as_deref(), Some("Won as Fox!"));
+
+        clock.advance(Duration::from_secs(1));
+        wait_until(|| details().as_deref() == Some("In menus"));
+
+        // Abandoned matches go straight back to idle.
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.shutdown().unwrap();
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 7);
+        assert_eq!(calls[5].as_ref().unwrap().details.as_deref(), Some("In menus"));
+    }
+
+    #[test]
+    fn the_initial_presence_is_sent_on_connect() {
+        // Nothing else can be sent for a while, but the initial presence doesn't wait on that.
+        let config = Config {
+            update_interval: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, SystemClock, MockBackend::default());
+        wait_until(|| !backend.calls().is_empty());
+
+        assert_eq!(backend.calls(), vec![Some(Activity::slippi())]);
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn startup_keeps_trying_if_discord_is_not_ready() {
+        let backend = MockBackend::default();
+        backend.set_unresponsive(true);
+
+        let config = Config {
+            connect_timeout: Duration::from_secs(3),
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, SystemClock, backend);
+
+        wait_until(|| handler.last_error().is_some());
+        assert_eq!(
+            handler.last_error(),
+            Some(HandshakeTimeout(Duration::from_secs(3)).to_string())
+        );
+        assert_eq!(handler.last_error_category(), Some(ErrorCategory::Connection));
+        assert!(backend.calls().is_empty());
+
+        // Once Discord is ready, the retry goes through as any other reconnect would.
+        backend.set_unresponsive(false);
+        wait_until(|| !backend.calls().is_empty());
+        assert_eq!(backend.calls(), vec![Some(Activity::slippi())]);
+        handler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn waiting_for_a_connection() {
+        let connect = |backend: &MockBackend| {
+            let (handler, _backend) = mock_handler_with(Config::default(), SystemClock, backend.clone());
+            handler.wait_until_connected(Duration::from_millis(100))
+        };
+
+        let backend = MockBackend::default();
+        connect(&backend).unwrap().shutdown().unwrap();
+
+        let backend = MockBackend::default();
+        backend.set_offline(true);
+        let started = Instant::now();
+        assert!(matches!(connect(&backend), Err(DiscordUnavailable)));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // Nor if Discord never gets as far as being ready.
+        let backend = MockBackend::default();
+        backend.set_unresponsive(true);
+        assert!(matches!(connect(&backend), Err(DiscordUnavailable)));
+    }
+
+    #[test]
+    fn app_state_gives_way_to_matches() {
+        let (handler, backend) = mock_handler();
+
+        handler.set_app_state(AppState::Updating).unwrap();
+        handler.notify_game_start(game()).unwrap();
+        handler.set_app_state(AppState::Idle).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.shutdown().unwrap();
+
+        let details: Vec<_> = backend
+            .calls()
+            .iter()
+            .map(|call| call.as_ref().and_then(|activity| activity.details.clone()))
+            .collect();
+
+        assert_eq!(details.len(), 5);
+        assert_eq!(details[1].as_deref(), Some("Updating Slippi..."));
+        assert_eq!(details[2].as_deref(), Some("Battling on Final Destination"));
+        assert_eq!(details[3].as_deref(), Some("In menus"));
+    }
+
+    #[test]
+    fn threads_are_named_after_their_label() {
+        let thread_name = |config: Config| {
+            let (handler, _backend) = mock_handler_with(config, SystemClock, MockBackend::default());
+            handler.thread.as_ref().unwrap().thread().name().map(str::to_string)
+        };
+
+        assert_eq!(thread_name(Config::default()).as_deref(), Some("SlippiDiscordRPC"));
+
+        let config = Config {
+            instance_label: Some("p2".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(thread_name(config).as_deref(), Some("SlippiDiscordRPC[p2]"));
+    }
+
+    #[test]
+    fn searching_leads_into_the_match() {
+        let (handler, backend) = mock_handler();
+
+        handler.notify_matchmaking_searching(MatchmakingMode::Ranked).unwrap();
+        handler.notify_matchmaking_found().unwrap();
+        handler.notify_game_start(game()).unwrap();
+        handler.notify_game_end(GameResult::NoContest).unwrap();
+        handler.notify_matchmaking_searching(MatchmakingMode::Unranked).unwrap();
+        handler.notify_matchmaking_cancelled().unwrap();
+        handler.shutdown().unwrap();
+
+        let details: Vec<_> = backend
+            .calls()
+            .iter()
+            .map(|call| call.as_ref().and_then(|activity| activity.details.clone()))
+            .collect();
+
+        assert_eq!(
+            details,
+            vec![
+                None,
+                Some("Searching for a Ranked match...".to_string()),
+                Some("Match found!".to_string()),
+                Some("Battling on Final Destination".to_string()),
+                Some("In menus".to_string()),
+                Some("Searching for an Unranked match...".to_string()),
+                Some("In menus".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn rate_limiting_backs_off() {
+        let clock = ManualClock::new();
+
+        let config = Config {
+            update_interval: Duration::from_secs(4),
+            adaptive_interval: false,
+            ..Default::default()
+        };
+
+        let (handler, backend) = mock_handler_with(config, clock.clone(), MockBackend::default());
+        let metrics = handler.metrics.clone();
+        wait_until(|| backend.calls().len() == 1);
+
+        backend.set_rate_limited(true);
+        handler.notify_game_start(game()).unwrap();
+        clock.advance(Duration::from_secs(4));
+        wait_until(|| metrics.snapshot().rate_limited_count == 1);
+        backend.set_rate_limited(false);
+
+        // The turned away update is retried, but only after twice the usual interval.
+        clock.advance(Duration::from_secs(4));
+        handler.sync();
+        assert_eq!(backend.calls().len(), 1);
+
+        clock.advance(Duration::from_secs(4));
+        wait_until(|| backend.calls().len() == 2);
+        handler.shutdown().unwrap();
+
+        assert_eq!(metrics.snapshot().rate_limited_count, 1);
+        assert_eq!(
+            backend.calls()[1].as_ref().unwrap().details.as_deref(),
+            Some("Battling on Final Destination")
+        );
+    }
+
+    #[test]
+    fn the_current_state_is_shared() {
+        use crate::memory::addresses::*;
+
+        // A stand-in for emulated RAM, mid-match.
+        let mut ram = vec![0u8; MEM1_SIZE as usize];
+        let mut write = |address: u32, bytes: &[u8]| {
+            let start = (address - MEM1_START) as usize;
+            ram[start..start + bytes.len()].copy_from_slice(bytes);
+        };
+
+        write(SCENE_MAJOR, &[SCENE_MAJOR_VS]);
+        write(SCENE_MINOR, &[SCENE_MINOR_IN_GAME]);
+        write(STAGE_ID, &32u16.to_be_bytes());
+
+        for (port, character_id, stocks) in [(0, 1, 4), (1, 18, 3)] {
+            let block = PLAYER_BLOCK_START + PLAYER_BLOCK_SIZE * port;
+            let entity = 0x8100_0000 + port * 0x1000;
+            let fighter = entity + 0x100;
+
+            write(block + PLAYER_ENTITY_OFFSET, &entity.to_be_bytes());
+            write(block + PLAYER_STOCKS_OFFSET, &[stocks]);
+            write(entity + ENTITY_DATA_OFFSET, &fighter.to_be_bytes());
+            write(fighter + FIGHTER_CHARACTER_ID_OFFSET, &[character_id]);
+        }
+
+        let backend = MockBackend::default();
+        let handler = DiscordHandler::with_backend(
+            ram.as_ptr() as usize,
+            Config::default(),
+            backend,
+            SharedStatus::new(),
+            mock_sinks(),
+            ManualClock::new(),
+        )
+        .unwrap();
+
+        wait_until(|| handler.current_state().is_some());
+        assert_eq!(
+            handler.current_state(),
+            Some(GameState {
+                in_game: true,
+                selecting: None,
+                stage_id: 32,
+                p1_character_id: 1,
+                p2_character_id: 18,
+                p1_stocks: 4,
+                p2_stocks: 3,
+                p2_cpu_level: None,
+            })
+        );
+
+        // The thread reads from `ram` until it's stopped, so it has to go first.
+        handler.shutdown().unwrap();
+        drop(ram);
+    }
+
+    #[test]
+    fn unmapped_memory_waits_for_the_game() {
+        let backend = MockBackend::default();
+        let config = Config {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let handler =
+            DiscordHandler::with_backend(0, config, backend.clone(), SharedStatus::new(), mock_sinks(), SystemClock).unwrap();
+        let details = || {
+            backend
+                .calls()
+                .last()
+                .cloned()
+                .flatten()
+                .and_then(|activity| activity.details)
+        };
+
+        // Nothing is read until Dolphin says where memory is, so there's no state to share (or
+        // to fail to read).
+        wait_until(|| details().as_deref() == Some("Waiting for game"));
+        assert_eq!(details().as_deref(), Some("Waiting for game"));
+        assert_eq!(handler.current_state(), None);
+        assert_eq!(handler.metrics().memory_read_failures, 0);
+
+        handler.update_ram_offset(blank_ram_offset()).unwrap();
+        wait_until(|| details().as_deref() == Some("In menus"));
+        assert_eq!(details().as_deref(), Some("In menus"));
+        wait_until(|| handler.current_state().is_some());
+        assert_eq!(handler.current_state(), Some(GameState::default()));
+
+        // Going back to no memory is waiting all over again.
+        handler.update_ram_offset(0).unwrap();
+        wait_until(|| details().as_deref() == Some("Waiting for game"));
+        assert_eq!(details().as_deref(), Some("Waiting for game"));
+
+        handler.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+//! Translations for the character, stage and mode names shown in presence text. The text
+//! around them isn't translated.
+//!
+//! Strings are looked up by their English text, which is what the rest of the crate deals in;
+//! anything without a translation (or in a locale we don't know) stays in English. Locales are
+//! matched on their language alone, so e.g "es-MX" uses the Spanish table.
+
+/// Returns `english` as it should be shown in `locale`.
+pub(crate) fn translate(locale: &str, english: &'static str) -> &'static str {
+    let language = locale.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+
+    let translated = match language.as_str() {
+        "ja" => japanese(english),
+        "es" => spanish(english),
+        _ => None,
+    };
+
+    translated.unwrap_or(english)
+}
+
+fn japanese(english: &str) -> Option<&'static str> {
+    let translated = match english {
+        "Mario" => "マリオ",
+        "Fox" => "フォックス",
+        "Captain Falcon" => "キャプテン・ファルコン",
+        "Donkey Kong" => "ドンキーコング",
+        "Kirby" => "カービィ",
+        "Bowser" => "クッパ",
+        "Link" => "リンク",
+        "Sheik" => "シーク",
+        "Ness" => "ネス",
+        "Peach" => "ピーチ",
+        "Ice Climbers" => "アイスクライマー",
+        "Pikachu" => "ピカチュウ",
+        "Samus" => "サムス",
+        "Yoshi" => "ヨッシー",
+        "Jigglypuff" => "プリン",
+        "Mewtwo" => "ミュウツー",
+        "Luigi" => "ルイージ",
+        "Marth" => "マルス",
+        "Zelda" => "ゼルダ",
+        "Young Link" => "こどもリンク",
+        "Dr. Mario" => "ドクターマリオ",
+        "Falco" => "ファルコ",
+        "Pichu" => "ピチュー",
+        "Mr. Game & Watch" => "Mr.ゲーム&ウォッチ",
+        "Ganondorf" => "ガノンドロフ",
+        "Roy" => "ロイ",
+
+        "Fountain of Dreams" => "夢の泉",
+        "Pokémon Stadium" => "ポケモンスタジアム",
+        "Princess Peach's Castle" => "ピーチ城上空",
+        "Kongo Jungle" => "コンゴジャングル",
+        "Brinstar" => "ブリンスタ",
+        "Corneria" => "惑星コーネリア",
+        "Yoshi's Story" => "ヨースター島",
+        "Onett" => "オネット",
+        "Mute City" => "ミュートシティ",
+        "Rainbow Cruise" => "レインボークルーズ",
+        "Jungle Japes" => "ジャングルガーデン",
+        "Great Bay" => "グレートベイ",
+        "Hyrule Temple" => "神殿",
+        "Green Greens" => "グリーングリーンズ",
+        "Fourside" => "フォーサイド",
+        "Mushroom Kingdom" => "いにしえの王国",
+        "Venom" => "惑星ヴェノム",
+        "Big Blue" => "ビッグブルー",
+        "Icicle Mountain" => "アイシクルマウンテン",
+        "Flat Zone" => "フラットゾーン",
+        "Dream Land" => "プププランド",
+        "Battlefield" => "戦場",
+        "Final Destination" => "終点",
+
+        "Singles" => "シングル",
+        "Doubles" => "ダブルス",
+        "Ranked Match" => "ランクマッチ",
+        "Unranked Match" => "アンランクマッチ",
+        "Playing with friends" => "フレンドと対戦中",
+        "Teams Match" => "チーム戦",
+        "Playing locally" => "ローカルで対戦中",
+
+        "Red Team" => "赤チーム",
+        "Blue Team" => "青チーム",
+        "Green Team" => "緑チーム",
+
+        _ => return None,
+    };
+
+    Some(translated)
+}
+
+/// Most character names are the same in Spanish, so only the exceptions are listed.
+fn spanish(english: &str) -> Option<&'static str> {
+    let translated = match english {
+        "Young Link" => "Link niño",
+
+        "Fountain of Dreams" => "Fuente de los Sueños",
+        "Pokémon Stadium" => "Estadio Pokémon",
+        "Princess Peach's Castle" => "Castillo de la Princesa Peach",
+        "Kongo Jungle" => "Selva Kongo",
+        "Great Bay" => "Gran Bahía",
+        "Hyrule Temple" => "Templo de Hyrule",
+        "Yoshi's Island" => "Isla de Yoshi",
+        "Mushroom Kingdom" => "Reino Champiñón",
+        "Mushroom Kingdom II" => "Reino Champiñón II",
+        "Battlefield" => "Campo de batalla",
+        "Final Destination" => "Destino Final",
+
+        "Singles" => "Individual",
+        "Doubles" => "Dobles",
+        "Ranked Match" => "Partida clasificatoria",
+        "Unranked Match" => "Partida no clasificatoria",
+        "Playing with friends" => "Jugando con amigos",
+        "Teams Match" => "Partida por equipos",
+        "Playing locally" => "Jugando en local",
+
+        "Red Team" => "Equipo rojo",
+        "Blue Team" => "Equipo azul",
+        "Green Team" => "Equipo verde",
+
+        _ => return None,
+    };
+
+    Some(translated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_locales_translate() {
+        assert_eq!(translate("ja", "Fox"), "フォックス");
+        assert_eq!(translate("es-MX", "Final Destination"), "Destino Final");
+        assert_eq!(translate("ES_es", "Doubles"), "Dobles");
+    }
+
+    #[test]
+    fn unknown_locales_and_strings_fall_back_to_english() {
+        assert_eq!(translate("en", "Fox"), "Fox");
+        assert_eq!(translate("xx-YY", "Fox"), "Fox");
+        assert_eq!(translate("", "Battlefield"), "Battlefield");
+        assert_eq!(translate("es", "Fox"), "Fox");
+        assert_eq!(translate("ja", "Something new"), "Something new");
+    }
+}
@@ -0,0 +1,852 @@
+//! Implements the (small) subset of the Discord IPC protocol that we need.
+//!
+//! The Discord client listens on a local socket - a Unix domain socket on macOS/Linux, and a
+//! named pipe on Windows. Everything sent over it is framed as:
+//!
+//! ``` notest
+//! [opcode: u32 (LE)] [length: u32 (LE)] [JSON payload: length bytes]
+//! ```
+//!
+//! A session starts with a `Handshake` frame carrying the application ID, which Discord answers
+//! with a `READY` dispatch. Commands (e.g, `SET_ACTIVITY`) are then sent as `Frame`s, and each
+//! gets a response frame echoing the nonce we sent.
+//!
+//! Discord can also push events at us at any time (e.g, `ACTIVITY_JOIN`), so reads happen on a
+//! dedicated reader thread. Events are dispatched straight to their subscribers from there; all
+//! other frames are forwarded to the `DiscordIPC` that's waiting on a command response.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+#[cfg(windows)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+#[cfg(windows)]
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+
+use dolphin_integrations::Log;
+
+use crate::join::{decode_join_secret, JoinRequest};
+use crate::spectate::{decode_spectate_secret, SpectateRequest};
+use crate::subscribers::Subscribers;
+use crate::verbosity::{self, Verbosity};
+use crate::{Activity, DiscordRPCError, Result};
+use DiscordRPCError::*;
+
+/// The IPC protocol version we speak.
+const IPC_VERSION: u32 = 1;
+
+/// The largest payload we'll read. Discord's are a few KiB at most, so anything past this
+/// means we're out of step (or talking to something that isn't Discord).
+const MAX_PAYLOAD_LENGTH: usize = 64 * 1024;
+
+/// How long we'll wait on Discord to respond to a command.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Frame opcodes as defined by the Discord client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Opcode {
+    Handshake = 0,
+    Frame = 1,
+    Close = 2,
+    Ping = 3,
+    Pong = 4,
+}
+
+impl Opcode {
+    /// Maps a raw opcode read off the wire back to our type.
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Handshake),
+            1 => Some(Self::Frame),
+            2 => Some(Self::Close),
+            3 => Some(Self::Ping),
+            4 => Some(Self::Pong),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+type Stream = std::os::unix::net::UnixStream;
+
+#[cfg(windows)]
+type Stream = std::fs::File;
+
+/// How many sockets/pipes Discord might be listening on. It takes the first free index, so if
+/// e.g another client (or a second Discord install) holds `discord-ipc-0`, it'll be on `-1`.
+const MAX_IPC_INDEX: u8 = 10;
+
+/// Lists the sockets the Discord client might be listening on, in the order to try them.
+///
+/// Discord places these in the first temp-ish directory it finds in the environment, which is
+/// looked up via `env`.
+#[cfg(unix)]
+fn ipc_paths(env: impl Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+    let dir = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .find_map(|key| env(key))
+        .unwrap_or_else(|| "/tmp".to_string());
+
+    (0..MAX_IPC_INDEX)
+        .map(|index| Path::new(&dir).join(format!("discord-ipc-{index}")))
+        .collect()
+}
+
+/// Lists the named pipes the Discord client might be listening on, in the order to try them.
+#[cfg(windows)]
+fn ipc_paths(_env: impl Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+    (0..MAX_IPC_INDEX)
+        .map(|index| PathBuf::from(format!(r"\\?\pipe\discord-ipc-{index}")))
+        .collect()
+}
+
+#[cfg(unix)]
+fn open_stream(path: &Path) -> std::io::Result<Stream> {
+    Stream::connect(path)
+}
+
+#[cfg(windows)]
+fn open_stream(path: &Path) -> std::io::Result<Stream> {
+    std::fs::OpenOptions::new().read(true).write(true).open(path)
+}
+
+/// How often the reader checks a pipe for something to read.
+#[cfg(windows)]
+const PIPE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The reading end of a pipe, which only reads once there's something there to read.
+///
+/// Windows runs synchronous I/O on a file object one call at a time, and a cloned handle
+/// shares its file object. A `ReadFile` left waiting on Discord would hold up every write in
+/// the meantime (the handshake included, which is what Discord's waiting on), so the pipe is
+/// polled with `PeekNamedPipe` instead.
+#[cfg(windows)]
+struct PipeReader {
+    pipe: Stream,
+
+    /// Set once the connection is dropped, which is this side's only way of knowing.
+    closed: Arc<AtomicBool>,
+}
+
+#[cfg(windows)]
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::os::windows::io::AsRawHandle;
+
+        use windows_sys::Win32::Foundation::{ERROR_BROKEN_PIPE, HANDLE};
+        use windows_sys::Win32::System::Pipes::PeekNamedPipe;
+
+        while !self.closed.load(Ordering::Relaxed) {
+            let mut available = 0u32;
+
+            // SAFETY: the handle stays open for as long as `pipe` does, and nothing is read
+            // into a buffer; only the count of available bytes is written out.
+            let peeked = unsafe {
+                PeekNamedPipe(
+                    self.pipe.as_raw_handle() as HANDLE,
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null_mut(),
+                    &mut available,
+                    std::ptr::null_mut(),
+                )
+            };
+
+            if peeked == 0 {
+                let e = std::io::Error::last_os_error();
+
+                // Discord closing its end is the end of the stream, as with a socket.
+                return match e.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) {
+                    true => Ok(0),
+                    false => Err(e),
+                };
+            }
+
+            if available > 0 {
+                let len = buf.len().min(available as usize);
+                return self.pipe.read(&mut buf[..len]);
+            }
+
+            thread::sleep(PIPE_POLL_INTERVAL);
+        }
+
+        Ok(0)
+    }
+}
+
+/// Where events that Discord pushes at us get forwarded to.
+#[derive(Clone, Debug)]
+pub(crate) struct EventSinks {
+    pub join_requests: Subscribers<JoinRequest>,
+    pub spectate_requests: Subscribers<SpectateRequest>,
+}
+
+/// The handshake payload.
+#[derive(Serialize)]
+struct Handshake<'a> {
+    v: u32,
+    client_id: &'a str,
+}
+
+/// A command payload.
+#[derive(Serialize)]
+struct Command<'a, T> {
+    cmd: &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    evt: Option<&'a str>,
+
+    args: T,
+    nonce: String,
+}
+
+/// Arguments for the `SET_ACTIVITY` command. Passing `None` for the activity clears it.
+#[derive(Serialize)]
+struct SetActivityArgs<'a> {
+    pid: u32,
+    activity: Option<&'a Activity>,
+}
+
+/// Arguments for commands that don't need any.
+#[derive(Serialize)]
+struct NoArgs {}
+
+/// The fields we care about from any payload the Discord client sends back.
+///
+/// `Close` frames carry `code` and `message` at the top level, whereas dispatches and command
+/// responses carry `cmd`/`evt`/`data`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Response {
+    cmd: Option<String>,
+    evt: Option<String>,
+    data: Option<serde_json::Value>,
+    nonce: Option<String>,
+    message: Option<String>,
+}
+
+/// Whether an error message from Discord is telling us to slow down. These don't come with a
+/// dedicated error code, so this goes by the wording.
+fn is_rate_limit_message(message: &str) -> bool {
+    message.to_ascii_lowercase().contains("rate limit")
+}
+
+impl Response {
+    /// Extracts the most useful error description from a response.
+    fn error_message(&self) -> String {
+        self.data
+            .as_ref()
+            .and_then(|data| data.get("message"))
+            .and_then(|message| message.as_str())
+            .map(|message| message.to_string())
+            .or_else(|| self.message.clone())
+            .unwrap_or_else(|| format!("cmd={:?}, evt={:?}", self.cmd, self.evt))
+    }
+
+    /// Reads a string field out of `data`.
+    fn data_str(&self, key: &str) -> Option<&str> {
+        self.data.as_ref()?.get(key)?.as_str()
+    }
+}
+
+/// A live, handshaken connection to the Discord client.
+pub(crate) struct DiscordIPC {
+    stream: Stream,
+    frames: Receiver<Result<(Opcode, Vec<u8>)>>,
+    nonce: u64,
+
+    /// Tells the reader to stop polling the pipe. See `PipeReader`.
+    #[cfg(windows)]
+    closed: Arc<AtomicBool>,
+}
+
+impl DiscordIPC {
+    /// Finds the Discord socket, performs the handshake for `application_id`, and subscribes
+    /// to the events we care about. Discord has `timeout` to answer the handshake and the
+    /// subscriptions, across all of the sockets, so that a caller is never held up for longer
+    /// than that.
+    ///
+    /// Each candidate socket is tried in turn, and the first that handshakes wins. Returns
+    /// `DiscordUnavailable` if there's nothing listening on any of them, which almost always
+    /// means the Discord client isn't running (or isn't installed). Otherwise, if none of them
+    /// worked out, the last failure is returned.
+    ///
+    /// Everything logged along the way (including from the reader thread) goes through
+    /// `verbosity`.
+    pub fn connect(application_id: &str, sinks: &EventSinks, timeout: Duration, verbosity: &Verbosity) -> Result<Self> {
+        Self::connect_any(
+            ipc_paths(|key| std::env::var(key).ok()),
+            application_id,
+            sinks,
+            timeout,
+            verbosity,
+        )
+    }
+
+    /// Tries each of `paths` in turn. See `connect`.
+    fn connect_any(
+        paths: Vec<PathBuf>,
+        application_id: &str,
+        sinks: &EventSinks,
+        timeout: Duration,
+        verbosity: &Verbosity,
+    ) -> Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let mut last_error = DiscordUnavailable;
+
+        for path in paths {
+            if Instant::now() >= deadline {
+                break;
+            }
+
+            match Self::connect_at(&path, application_id, sinks, timeout, deadline, verbosity) {
+                Ok(ipc) => return Ok(ipc),
+                Err(DiscordUnavailable) => {},
+
+                Err(e) => {
+                    verbosity::log!(
+                        verbosity,
+                        debug,
+                        target: Log::DiscordRPC,
+                        error = ?e,
+                        path = %path.display(),
+                        "Failed to connect over Discord IPC"
+                    );
+                    last_error = e;
+                },
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Connects over the socket at `path`, giving up on Discord at `deadline`. See `connect`.
+    fn connect_at(
+        path: &Path,
+        application_id: &str,
+        sinks: &EventSinks,
+        timeout: Duration,
+        deadline: Instant,
+        verbosity: &Verbosity,
+    ) -> Result<Self> {
+        let stream = open_stream(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused => DiscordUnavailable,
+            _ => GenericIO(e),
+        })?;
+
+        // Reads are bounded by waiting on the reader thread, but a half-open socket could
+        // still wedge a write. Pipes on Windows have no equivalent.
+        #[cfg(unix)]
+        stream.set_write_timeout(Some(remaining(deadline).max(Duration::from_millis(1))))?;
+
+        #[cfg(unix)]
+        let reader = stream.try_clone()?;
+
+        #[cfg(windows)]
+        let closed = Arc::new(AtomicBool::new(false));
+
+        #[cfg(windows)]
+        let reader = PipeReader {
+            pipe: stream.try_clone()?,
+            closed: closed.clone(),
+        };
+
+        let (frames_tx, frames) = channel();
+        let sinks = sinks.clone();
+        let verbosity = verbosity.clone();
+
+        thread::Builder::new()
+            .name("SlippiDiscordRPCReader".to_string())
+            .spawn(move || read_loop(reader, frames_tx, sinks, verbosity))
+            .map_err(ThreadSpawn)?;
+
+        let mut ipc = Self {
+            stream,
+            frames,
+            nonce: 0,
+            #[cfg(windows)]
+            closed,
+        };
+
+        ipc.handshake(application_id, timeout, deadline)?;
+        ipc.send_command_by("SUBSCRIBE", Some("ACTIVITY_JOIN"), NoArgs {}, deadline)?;
+
+        // Discord only fires this for activities with a spectate secret, so there's no need to
+        // tie the subscription to `Config::enable_spectate`.
+        ipc.send_command_by("SUBSCRIBE", Some("ACTIVITY_SPECTATE"), NoArgs {}, deadline)?;
+
+        Ok(ipc)
+    }
+
+    /// Sends the handshake frame and waits for Discord to say it's `READY`, returning
+    /// `HandshakeTimeout` (for the whole `timeout`) if it hasn't by `deadline`. A client that's
+    /// still starting up can accept connections well before it's ready to answer them.
+    fn handshake(&mut self, application_id: &str, timeout: Duration, deadline: Instant) -> Result<()> {
+        let payload = serde_json::to_vec(&Handshake {
+            v: IPC_VERSION,
+            client_id: application_id,
+        })?;
+
+        write_frame(&mut self.stream, Opcode::Handshake, &payload).map_err(|e| match e {
+            GenericIO(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                HandshakeTimeout(timeout)
+            },
+
+            e => e,
+        })?;
+
+        let (opcode, payload) = match self.frames.recv_timeout(remaining(deadline)) {
+            Ok(frame) => frame?,
+
+            Err(RecvTimeoutError::Timeout) => return Err(HandshakeTimeout(timeout)),
+            Err(RecvTimeoutError::Disconnected) => return Err(ConnectionLost("the Discord IPC reader has exited".to_string())),
+        };
+
+        let response: Response = serde_json::from_slice(&payload).map_err(PayloadParse)?;
+
+        match (opcode, response.evt.as_deref()) {
+            (Opcode::Frame, Some("READY")) => Ok(()),
+            _ => Err(Handshake(response.error_message())),
+        }
+    }
+
+    /// Sets (or, if `activity` is `None`, clears) the activity for this process.
+    pub fn set_activity(&mut self, activity: Option<&Activity>) -> Result<()> {
+        let args = SetActivityArgs {
+            pid: std::process::id(),
+            activity,
+        };
+
+        self.send_command("SET_ACTIVITY", None, args)
+    }
+
+    /// Sends a command and waits up to `RESPONSE_TIMEOUT` on its response, surfacing any
+    /// `ERROR` event.
+    fn send_command<T: Serialize>(&mut self, cmd: &str, evt: Option<&str>, args: T) -> Result<()> {
+        self.send_command_by(cmd, evt, args, Instant::now() + RESPONSE_TIMEOUT)
+    }
+
+    /// Like `send_command`, but gives up on the response at `deadline`.
+    fn send_command_by<T: Serialize>(&mut self, cmd: &str, evt: Option<&str>, args: T, deadline: Instant) -> Result<()> {
+        self.nonce += 1;
+        let nonce = self.nonce.to_string();
+
+        let payload = serde_json::to_vec(&Command {
+            cmd,
+            evt,
+            args,
+            nonce: nonce.clone(),
+        })?;
+
+        write_frame(&mut self.stream, Opcode::Frame, &payload).map_err(|e| ConnectionLost(e.to_string()))?;
+
+        loop {
+            let (opcode, payload) = self.next_frame(deadline)?;
+
+            match opcode {
+                // Discord may ping us at any point; answer and keep waiting on our response.
+                Opcode::Ping => {
+                    write_frame(&mut self.stream, Opcode::Pong, &payload).map_err(|e| ConnectionLost(e.to_string()))?
+                },
+
+                Opcode::Frame => {
+                    let response: Response = serde_json::from_slice(&payload).map_err(PayloadParse)?;
+
+                    // A late response to an earlier command that we gave up on.
+                    if response.nonce.as_deref() != Some(nonce.as_str()) {
+                        continue;
+                    }
+
+                    if response.evt.as_deref() == Some("ERROR") {
+                        let message = response.error_message();
+
+                        return Err(match is_rate_limit_message(&message) {
+                            true => RateLimited(message),
+                            false => Command(message),
+                        });
+                    }
+
+                    return Ok(());
+                },
+
+                Opcode::Close => {
+                    let response: Response = serde_json::from_slice(&payload).unwrap_or_default();
+                    return Err(ConnectionLost(response.error_message()));
+                },
+
+                Opcode::Handshake | Opcode::Pong => {
+                    return Err(InvalidFrame(format!("unexpected opcode {opcode:?}")));
+                },
+            }
+        }
+    }
+
+    /// Waits on the next frame from the reader thread. If the reader has exited (or Discord
+    /// hasn't answered by `deadline`) this returns `ConnectionLost`.
+    fn next_frame(&mut self, deadline: Instant) -> Result<(Opcode, Vec<u8>)> {
+        match self.frames.recv_timeout(remaining(deadline)) {
+            Ok(frame) => frame,
+
+            Err(RecvTimeoutError::Timeout) => Err(ConnectionLost("timed out waiting on a response".to_string())),
+            Err(RecvTimeoutError::Disconnected) => Err(ConnectionLost("the Discord IPC reader has exited".to_string())),
+        }
+    }
+}
+
+/// On Unix, shutting the socket down unblocks the reader thread so that it exits alongside us.
+/// On Windows, the reader is told to stop polling the pipe.
+impl Drop for DiscordIPC {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+
+        #[cfg(windows)]
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Reads frames until the socket closes. Events are dispatched to `sinks`; everything else is
+/// forwarded on to `frames`.
+fn read_loop<R: Read>(mut reader: R, frames: Sender<Result<(Opcode, Vec<u8>)>>, sinks: EventSinks, verbosity: Verbosity) {
+    loop {
+        match read_frame(&mut reader) {
+            Ok((Opcode::Frame, payload)) => {
+                if let Ok(response) = serde_json::from_slice::<Response>(&payload) {
+                    if dispatch_event(&response, &sinks, &verbosity) {
+                        continue;
+                    }
+                }
+
+                if frames.send(Ok((Opcode::Frame, payload))).is_err() {
+                    return;
+                }
+            },
+
+            Ok(frame) => {
+                if frames.send(Ok(frame)).is_err() {
+                    return;
+                }
+            },
+
+            // The frame itself held together, so we're still in step with Discord and can
+            // carry on reading.
+            Err(e @ PayloadParse(_)) => {
+                verbosity::log!(verbosity, debug, target: Log::DiscordRPC, error = ?e, "Discord sent a malformed payload");
+
+                if frames.send(Err(e)).is_err() {
+                    return;
+                }
+            },
+
+            Err(e) => {
+                verbosity::log!(verbosity, debug, target: Log::DiscordRPC, error = ?e, "Discord IPC reader exiting");
+
+                let _ = frames.send(Err(match e {
+                    GenericIO(e) => ConnectionLost(e.to_string()),
+                    e => e,
+                }));
+
+                return;
+            },
+        }
+    }
+}
+
+/// Forwards an event dispatch to whoever's interested. Returns `false` if `response` isn't an
+/// event we handle.
+fn dispatch_event(response: &Response, sinks: &EventSinks, verbosity: &Verbosity) -> bool {
+    if response.cmd.as_deref() != Some("DISPATCH") {
+        return false;
+    }
+
+    match response.evt.as_deref() {
+        Some("ACTIVITY_JOIN") => {
+            match response.data_str("secret").and_then(decode_join_secret) {
+                Some(request) => sinks.join_requests.send(request),
+                None => verbosity::log!(
+                    verbosity,
+                    warn,
+                    target: Log::DiscordRPC,
+                    "Received an unrecognized join secret"
+                ),
+            }
+
+            true
+        },
+
+        Some("ACTIVITY_SPECTATE") => {
+            match response.data_str("secret").and_then(decode_spectate_secret) {
+                Some(request) => sinks.spectate_requests.send(request),
+                None => verbosity::log!(
+                    verbosity,
+                    warn,
+                    target: Log::DiscordRPC,
+                    "Received an unrecognized spectate secret"
+                ),
+            }
+
+            true
+        },
+
+        _ => false,
+    }
+}
+
+/// How long until `deadline`, or zero if it's passed.
+fn remaining(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
+/// Writes a single frame.
+fn write_frame<W: Write>(writer: &mut W, opcode: Opcode, payload: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&(opcode as u32).to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+
+    writer.write_all(&frame)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads a single frame, returning its opcode and payload.
+///
+/// A frame that doesn't hold together is reported as precisely as we can: an opcode we don't
+/// know is `BadOpcode`, one that ends early is `FrameTooShort`, one claiming more than
+/// `MAX_PAYLOAD_LENGTH` is `FrameTooLarge`, and a payload that isn't JSON is `PayloadParse`.
+/// Discord closing the connection between frames is `ConnectionLost`.
+fn read_frame<R: Read>(reader: &mut R) -> Result<(Opcode, Vec<u8>)> {
+    let mut header = [0u8; 8];
+
+    match read_full(reader, &mut header)? {
+        0 => return Err(ConnectionLost("the Discord client closed the connection".to_string())),
+        got if got < header.len() => return Err(FrameTooShort(header.len(), got)),
+        _ => {},
+    }
+
+    let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    let opcode = Opcode::from_u32(opcode).ok_or(BadOpcode(opcode))?;
+
+    // The length is checked before anything is allocated for it.
+    let length = length as usize;
+    if length > MAX_PAYLOAD_LENGTH {
+        return Err(FrameTooLarge(length));
+    }
+
+    let mut payload = vec![0u8; length];
+    let got = read_full(reader, &mut payload)?;
+
+    if got < payload.len() {
+        return Err(FrameTooShort(header.len() + payload.len(), header.len() + got));
+    }
+
+    serde_json::from_slice::<IgnoredAny>(&payload).map_err(PayloadParse)?;
+    Ok((opcode, payload))
+}
+
+/// Reads into `buf` until it's full or the reader runs dry, returning how much was read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame's bytes, as Discord would send them.
+    fn frame(opcode: u32, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = opcode.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn malformed_frames_map_to_specific_errors() {
+        let read = |bytes: &[u8]| read_frame(&mut &bytes[..]);
+
+        assert!(matches!(read(&frame(9, b"{}")), Err(BadOpcode(9))));
+
+        // Cut off in the header, and then in the payload.
+        assert!(matches!(read(&frame(1, b"{}")[..5]), Err(FrameTooShort(8, 5))));
+        assert!(matches!(
+            read(&frame(1, b"{\"evt\":\"READY\"}")[..12]),
+            Err(FrameTooShort(23, 12))
+        ));
+
+        // Only the header is needed to turn these away.
+        let mut oversized = frame(1, b"");
+        oversized[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(read(&oversized), Err(FrameTooLarge(length)) if length == u32::MAX as usize));
+
+        let mut at_the_cap = frame(1, b"");
+        at_the_cap[4..8].copy_from_slice(&(MAX_PAYLOAD_LENGTH as u32).to_le_bytes());
+        assert!(matches!(read(&at_the_cap), Err(FrameTooShort(..))));
+
+        assert!(matches!(read(&frame(1, b"{\"evt\":")), Err(PayloadParse(_))));
+        assert!(matches!(read(&[]), Err(ConnectionLost(_))));
+    }
+
+    #[test]
+    fn rate_limit_errors_are_recognized() {
+        assert!(is_rate_limit_message("You are being rate limited."));
+        assert!(is_rate_limit_message("Rate limit exceeded"));
+        assert!(!is_rate_limit_message("child \"activity\" fails because [...]"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_paths_use_the_first_temp_dir_set() {
+        let paths = ipc_paths(|key| match key {
+            "TMPDIR" => Some("/var/tmp".to_string()),
+            "TEMP" => Some("/ignored".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(paths.len(), usize::from(MAX_IPC_INDEX));
+        assert_eq!(paths[0], Path::new("/var/tmp/discord-ipc-0"));
+        assert_eq!(paths[9], Path::new("/var/tmp/discord-ipc-9"));
+
+        assert_eq!(ipc_paths(|_| None)[1], Path::new("/tmp/discord-ipc-1"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_paths_are_named_pipes() {
+        let paths = ipc_paths(|_| None);
+
+        assert_eq!(paths.len(), usize::from(MAX_IPC_INDEX));
+        assert_eq!(paths[0], Path::new(r"\\?\pipe\discord-ipc-0"));
+        assert_eq!(paths[9], Path::new(r"\\?\pipe\discord-ipc-9"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn handshakes_time_out_if_discord_never_answers() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("slippi-discord-ipc-slow-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // A Discord that accepts the connection and reads the handshake, but never answers.
+        let discord = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = read_frame(&mut stream);
+            thread::sleep(Duration::from_millis(500));
+        });
+
+        let sinks = EventSinks {
+            join_requests: Subscribers::new(),
+            spectate_requests: Subscribers::new(),
+        };
+
+        let timeout = Duration::from_millis(50);
+        let started = Instant::now();
+        let result = DiscordIPC::connect_at(&path, "1234", &sinks, timeout, started + timeout, &Verbosity::default());
+
+        assert!(matches!(result, Err(HandshakeTimeout(t)) if t == timeout));
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        discord.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn subscriptions_share_the_handshake_timeout() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("slippi-discord-ipc-ready-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // A Discord that's ready straight away, but never answers anything after that.
+        let discord = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = read_frame(&mut stream);
+            write_frame(&mut stream, Opcode::Frame, br#"{"evt":"READY"}"#).unwrap();
+            while read_frame(&mut stream).is_ok() {}
+        });
+
+        let sinks = EventSinks {
+            join_requests: Subscribers::new(),
+            spectate_requests: Subscribers::new(),
+        };
+
+        let timeout = Duration::from_millis(100);
+        let started = Instant::now();
+        let result = DiscordIPC::connect_at(&path, "1234", &sinks, timeout, started + timeout, &Verbosity::default());
+
+        assert!(matches!(result, Err(ConnectionLost(_))));
+        assert!(started.elapsed() < timeout * 2, "{:?}", started.elapsed());
+
+        discord.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn every_socket_shares_the_one_timeout() {
+        use std::os::unix::net::UnixListener;
+
+        // Discords that accept the connection and read the handshake, but never answer.
+        let (paths, discords): (Vec<_>, Vec<_>) = (0..3)
+            .map(|index| {
+                let path = std::env::temp_dir().join(format!("slippi-discord-ipc-slow-{}-{index}", std::process::id()));
+                let _ = std::fs::remove_file(&path);
+                let listener = UnixListener::bind(&path).unwrap();
+
+                let discord = thread::spawn(move || {
+                    // This holds on until we hang up.
+                    if let Ok((mut stream, _)) = listener.accept() {
+                        while read_frame(&mut stream).is_ok() {}
+                    }
+                });
+
+                (path, discord)
+            })
+            .unzip();
+
+        let sinks = EventSinks {
+            join_requests: Subscribers::new(),
+            spectate_requests: Subscribers::new(),
+        };
+
+        let timeout = Duration::from_millis(200);
+        let started = Instant::now();
+        let result = DiscordIPC::connect_any(paths.clone(), "1234", &sinks, timeout, &Verbosity::default());
+
+        assert!(matches!(result, Err(HandshakeTimeout(t)) if t == timeout));
+        assert!(started.elapsed() < timeout * 2, "{:?}", started.elapsed());
+
+        // Any that were never tried are still waiting on a connection.
+        for path in &paths {
+            let _ = open_stream(path);
+        }
+
+        for (path, discord) in paths.iter().zip(discords) {
+            discord.join().unwrap();
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Discord "Ask to Join" support.
+//!
+//! When we know the local player's connect code, it's embedded in the activity's join secret,
+//! which makes Discord show a "Join" button. When a friend accepts, Discord hands that secret to
+//! *their* client as an `ACTIVITY_JOIN` event, and we decode it to find who to direct connect to.
+//!
+//! Secrets are encoded as `slippi-direct:v1:<connect code>`, e.g `slippi-direct:v1:ABCD#123`.
+//! The version segment leaves room to change the format without misreading older secrets.
+
+/// The prefix for join secrets we produce.
+const JOIN_SECRET_PREFIX: &str = "slippi-direct:v1:";
+
+/// A request (from Discord) to direct connect to another player.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JoinRequest {
+    pub connect_code: String,
+}
+
+/// Whether `code` looks like a Slippi connect code, e.g `ABCD#123`.
+pub(crate) fn is_valid_connect_code(code: &str) -> bool {
+    match code.split_once('#') {
+        Some((tag, number)) => {
+            (1..=7).contains(&tag.len())
+                && (1..=7).contains(&number.len())
+                && tag.chars().all(|c| c.is_ascii_alphanumeric())
+                && number.chars().all(|c| c.is_ascii_digit())
+        },
+
+        None => false,
+    }
+}
+
+/// Encodes a connect code into a join secret.
+pub(crate) fn encode_join_secret(connect_code: &str) -> String {
+    format!("{JOIN_SECRET_PREFIX}{connect_code}")
+}
+
+/// Decodes a join secret, returning `None` if it isn't one of ours.
+pub(crate) fn decode_join_secret(secret: &str) -> Option<JoinRequest> {
+    let connect_code = secret.strip_prefix(JOIN_SECRET_PREFIX)?;
+
+    match is_valid_connect_code(connect_code) {
+        true => Some(JoinRequest {
+            connect_code: connect_code.to_string(),
+        }),
+
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_secret_roundtrip() {
+        let secret = encode_join_secret("ABCD#123");
+        assert_eq!(secret, "slippi-direct:v1:ABCD#123");
+
+        let request = decode_join_secret(&secret).unwrap();
+        assert_eq!(request.connect_code, "ABCD#123");
+    }
+
+    #[test]
+    fn rejects_foreign_or_malformed_secrets() {
+        assert_eq!(decode_join_secret("someone-elses-secret"), None);
+        assert_eq!(decode_join_secret("slippi-direct:v1:ABCD123"), None);
+        assert_eq!(decode_join_secret("slippi-direct:v1:AB D#1"), None);
+        assert_eq!(decode_join_secret("slippi-direct:v1:ABCD#12a"), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! This module implements native Discord Rich Presence integration for Slippi.
+//!
+//! The core of it runs in a background thread, which owns the connection to the local
+//! Discord client and listens for new events on each pass of its own loop.
+//!
+//! All of that lives behind the (default) `discord` feature. Without it, `DiscordHandler` is a
+//! stub with the same API that does nothing, so callers don't need any feature gating of
+//! their own.
+//!
+//! The `async` feature adds `DiscordHandle`, for awaiting connection changes rather than
+//! polling for them.
+
+// Without the handler, most of the presence machinery has nothing to drive it.
+#![cfg_attr(not(feature = "discord"), allow(dead_code))]
+
+use std::sync::mpsc::Sender;
+
+mod activity;
+pub use activity::{
+    sanitize_presence_field, Activity, ActivityType, Assets, Button, Party, Secrets, Timestamps, MAX_FIELD_CHARS,
+};
+
+#[cfg(feature = "discord")]
+mod backend;
+
+pub mod characters;
+
+#[cfg(feature = "discord")]
+mod cleanup;
+
+#[cfg(feature = "discord")]
+mod clock;
+
+mod config;
+pub use config::{Config, ConfigBuilder, OsdCallback, PrivacyMode, DEFAULT_APPLICATION_ID};
+
+#[cfg(feature = "discord")]
+mod connection;
+
+mod error;
+pub use error::{DiscordRPCError, ErrorCategory};
+
+mod game;
+pub use game::{
+    GameEvent, GameInfo, GameKind, GameReport, GameResult, GameState, MatchmakingMode, Player, SelectScreen, SetScore, TeamColor,
+    TickUpdate,
+};
+
+#[cfg(feature = "async")]
+mod handle;
+#[cfg(feature = "async")]
+pub use handle::{DiscordHandle, Recv, StateStream};
+
+#[cfg(feature = "discord")]
+mod handler;
+#[cfg(feature = "discord")]
+pub use handler::DiscordHandler;
+
+mod i18n;
+
+#[cfg(feature = "discord")]
+mod ipc;
+
+mod join;
+pub use join::JoinRequest;
+
+#[cfg(feature = "discord")]
+mod memory;
+
+mod metrics;
+pub use metrics::DiscordMetrics;
+
+#[cfg(feature = "discord")]
+mod persist;
+
+mod presence;
+pub use presence::{build_activity, AppState, LobbyRole, Matchmaking, PresenceContext};
+
+mod ranked;
+pub use ranked::{Rank, RankTier};
+
+mod spectate;
+pub use spectate::SpectateRequest;
+
+pub mod stages;
+
+mod status;
+pub use status::{ConnectionState, ConnectionStatus};
+
+#[cfg(not(feature = "discord"))]
+mod stub;
+#[cfg(not(feature = "discord"))]
+pub use stub::DiscordHandler;
+
+mod subscribers;
+
+mod template;
+pub use template::{render_template, DEFAULT_DETAILS_TEMPLATE};
+
+#[cfg(feature = "discord")]
+mod throttle;
+
+#[cfg(feature = "discord")]
+mod verbosity;
+
+#[cfg(feature = "discord")]
+mod watcher;
+
+pub(crate) type Result<T> = std::result::Result<T, DiscordRPCError>;
+
+/// Message payloads that the inner thread listens for.
+#[derive(Debug)]
+pub enum Message {
+    Dropping,
+    UpdateConfig(Box<Config>),
+    GameStart(GameInfo),
+    GameEnd {
+        result: GameResult,
+    },
+    GameReport(GameReport),
+    UpdateStocks {
+        p1: u8,
+        p2: u8,
+    },
+    UpdatePercents {
+        p1: f32,
+        p2: f32,
+    },
+    UpdateTick(TickUpdate),
+    UpdateSetScore {
+        game: u8,
+        p1_wins: u8,
+        p2_wins: u8,
+    },
+    SetEnded,
+    AwaitingRematch,
+    UpdateSessionRecord {
+        wins: u32,
+        losses: u32,
+    },
+    ResetSession,
+    SetConnectCode(String),
+    SetOpponent {
+        code: String,
+        display_name: Option<String>,
+    },
+    UpdateRank {
+        tier: RankTier,
+        rating: f32,
+    },
+    UpdateRamOffset(usize),
+    SetSpectateId(Option<String>),
+    UpdateFrame(i32),
+    PlaybackStarted(GameInfo),
+    PlaybackStopped,
+    Event(GameEvent),
+    SetPaused(bool),
+    SetAppState(AppState),
+    MatchmakingSearching(MatchmakingMode),
+    MatchmakingFound,
+    MatchmakingCancelled,
+
+    /// Sends the pending activity as soon as Discord's rate limit allows.
+    Flush,
+
+    /// Drops the connection to Discord (if there is one) and reconnects straight away.
+    Reconnect,
+
+    /// Freezes the presence as it is (`true`), or lifts that and sends the latest (`false`).
+    SetSuspended(bool),
+
+    /// Moves the handler's logging threshold, so that only events at this level or above are
+    /// logged.
+    SetLogLevel(tracing::Level),
+
+    /// Acknowledges on the enclosed channel, which means that everything sent before it has
+    /// been handled (and flushed). Tests use this to step the loop one message at a time.
+    #[cfg(test)]
+    Sync(Sender<()>),
+
+    /// A `max` of zero means the player has left the lobby.
+    UpdateLobby {
+        current: u32,
+        max: u32,
+        role: LobbyRole,
+    },
+
+    /// Clears the activity, acknowledges on the enclosed channel, and exits.
+    ClearAndStop(Sender<()>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls every public `DiscordHandler` method, so that the real handler and the stub can't
+    /// drift apart without one of them failing to compile.
+    #[cfg_attr(feature = "discord", allow(dead_code))]
+    pub(crate) fn exercise_api(handler: DiscordHandler) -> Result<()> {
+        let _: fn(usize, Config, std::time::Duration) -> Result<DiscordHandler> = DiscordHandler::new_connected;
+        let _: bool = handler.is_connected();
+        let _: DiscordMetrics = handler.metrics();
+        let _: ConnectionStatus = handler.status();
+        let _: Option<String> = handler.last_error();
+        let _: Option<ErrorCategory> = handler.last_error_category();
+        let _: Option<GameState> = handler.current_state();
+        let _: Option<MatchmakingMode> = handler.current_mode();
+        let _: std::sync::mpsc::Receiver<ConnectionState> = handler.subscribe_state();
+        #[cfg(feature = "async")]
+        let _: DiscordHandle = handler.handle();
+        let _: std::sync::mpsc::Receiver<JoinRequest> = handler.join_requests();
+        let _: std::sync::mpsc::Receiver<SpectateRequest> = handler.spectate_requests();
+
+        let info = GameInfo {
+            stage_id: 32,
+            p1_character_id: 1,
+            p2_character_id: 18,
+            p1_costume_id: 0,
+            p2_costume_id: 0,
+            kind: GameKind::Singles,
+            timer_seconds: None,
+            matchmaking: MatchmakingMode::Offline,
+            players: [None; 4],
+            local_port: None,
+        };
+
+        handler.update_config(Config::default())?;
+        handler.update_lobby(1, 2, LobbyRole::Spectator)?;
+        handler.notify_game_start(info.clone())?;
+        handler.notify_playback_start(info)?;
+        handler.notify_playback_end()?;
+        handler.notify_event(GameEvent::Kill { killer: 0 })?;
+        handler.set_paused(true)?;
+        handler.set_app_state(AppState::Updating)?;
+        handler.notify_matchmaking_searching(MatchmakingMode::Ranked)?;
+        handler.notify_matchmaking_found()?;
+        handler.notify_matchmaking_cancelled()?;
+        handler.update_stocks(4, 4)?;
+        handler.update_percents(0.0, 12.5)?;
+        handler.update_set_score(2, 1, 0)?;
+        handler.notify_set_ended()?;
+        handler.notify_awaiting_rematch()?;
+        handler.update_session_record(7, 3)?;
+        handler.reset_session()?;
+        handler.update_frame(0)?;
+        handler.update_tick(TickUpdate::default())?;
+        handler.flush()?;
+        handler.reconnect()?;
+        handler.suspend()?;
+        handler.resume()?;
+        handler.set_log_level(tracing::Level::DEBUG)?;
+        handler.set_connect_code("ABCD#123".to_string())?;
+        handler.set_opponent("GRNK#123".to_string(), None)?;
+        handler.update_ram_offset(0)?;
+        handler.update_rank(RankTier::Gold1, 1500.0)?;
+        handler.set_spectate_id(None)?;
+        handler.notify_game_end(GameResult::Win)?;
+        handler.notify_game_report(GameReport {
+            duration_frames: 0,
+            winner_port: None,
+            stocks_remaining: [0; 4],
+            damage_dealt: [0.0; 4],
+        })?;
+        handler.install_cleanup_hook()?;
+        handler.shutdown()
+    }
+}
@@ -0,0 +1,64 @@
+//! Melee (NTSC 1.02) memory addresses that we read game state from.
+//!
+//! These are GameCube virtual addresses, i.e they start at `0x8000_0000`. Values in memory
+//! are big-endian.
+
+/// The start of main memory.
+pub const MEM1_START: u32 = 0x8000_0000;
+
+/// The size of main memory (24MB).
+pub const MEM1_SIZE: u32 = 0x0180_0000;
+
+/// The major scene, e.g VS mode or Slippi Online.
+pub const SCENE_MAJOR: u32 = 0x8047_9D30;
+
+/// The minor scene within the major scene, e.g character select or in-game.
+pub const SCENE_MINOR: u32 = 0x8047_9D33;
+
+/// Major scene values.
+pub const SCENE_MAJOR_VS: u8 = 0x02;
+pub const SCENE_MAJOR_ONLINE: u8 = 0x08;
+
+/// Minor scene values, for both VS mode and Slippi Online: the character select screen, the
+/// stage select screen, and while a match is being played.
+pub const SCENE_MINOR_CSS: u8 = 0x00;
+pub const SCENE_MINOR_SSS: u8 = 0x01;
+pub const SCENE_MINOR_IN_GAME: u8 = 0x02;
+
+/// The (external) stage id of the current match, part of its `StartMelee` struct.
+pub const STAGE_ID: u32 = 0x8045_AC5A;
+
+/// The first of the match's per-port setup entries (also part of `StartMelee`).
+pub const MATCH_PLAYER_START: u32 = 0x8045_ACAC;
+
+/// The distance between consecutive match setup entries.
+pub const MATCH_PLAYER_SIZE: u32 = 0x24;
+
+/// Offset (within a match setup entry) of the player type.
+pub const MATCH_PLAYER_TYPE_OFFSET: u32 = 0x01;
+
+/// Offset (within a match setup entry) of a CPU player's level, from 1 to 9.
+pub const MATCH_PLAYER_CPU_LEVEL_OFFSET: u32 = 0x0F;
+
+/// The player type of a CPU (humans are 0).
+pub const PLAYER_TYPE_CPU: u8 = 0x01;
+
+/// The first of the static player blocks, one per port.
+pub const PLAYER_BLOCK_START: u32 = 0x8045_3080;
+
+/// The distance between consecutive static player blocks.
+pub const PLAYER_BLOCK_SIZE: u32 = 0xE90;
+
+/// Offset (within a player block) of the pointer to the player's entity.
+pub const PLAYER_ENTITY_OFFSET: u32 = 0xB0;
+
+/// Offset (within a player block) of the remaining stock count.
+pub const PLAYER_STOCKS_OFFSET: u32 = 0x8E;
+
+/// Offset (within an entity) of the pointer to its fighter data.
+pub const ENTITY_DATA_OFFSET: u32 = 0x2C;
+
+/// Offset (within fighter data) of the internal character id. Unlike the character picked on
+/// the character select screen, this is whoever is on stage right now, so it follows e.g
+/// Zelda transforming into Sheik.
+pub const FIGHTER_CHARACTER_ID_OFFSET: u32 = 0x04;
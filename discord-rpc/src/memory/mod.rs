@@ -0,0 +1,565 @@
+//! Reads game state directly out of Dolphin's emulated RAM, so that the handler can follow
+//! along with a match without Dolphin having to push every change to us.
+
+use crate::{DiscordRPCError, GameInfo, GameKind, GameState, MatchmakingMode, PresenceContext, Result, SelectScreen};
+
+pub(crate) mod addresses;
+
+/// How many polls in a row can fail to read the game state before it's reported as an error,
+/// rather than put down to e.g memory being remapped across a game swap.
+pub(crate) const MAX_READ_FAILURES: u32 = 20;
+
+/// Reads from emulated memory.
+///
+/// Implementors only need to provide `read`; the typed reads and `game_state` are built on
+/// top of it. Tests can supply a fake that's backed by a plain map of addresses.
+pub(crate) trait MemoryReader {
+    /// Fills `buf` with the bytes starting at `address`. Returns `false` if any part of the
+    /// range falls outside of readable memory.
+    fn read(&self, address: u32, buf: &mut [u8]) -> bool;
+
+    fn read_u8(&self, address: u32) -> Option<u8> {
+        let mut buf = [0; 1];
+        self.read(address, &mut buf).then_some(buf[0])
+    }
+
+    fn read_u16(&self, address: u32) -> Option<u16> {
+        let mut buf = [0; 2];
+        self.read(address, &mut buf).then(|| u16::from_be_bytes(buf))
+    }
+
+    fn read_u32(&self, address: u32) -> Option<u32> {
+        let mut buf = [0; 4];
+        self.read(address, &mut buf).then(|| u32::from_be_bytes(buf))
+    }
+
+    /// Reads the current game state. Returns `None` if anything we need is unreadable, e.g
+    /// because a pointer we followed was null.
+    fn game_state(&self) -> Option<GameState> {
+        use addresses::*;
+
+        let major = self.read_u8(SCENE_MAJOR)?;
+        let minor = self.read_u8(SCENE_MINOR)?;
+
+        let versus = matches!(major, SCENE_MAJOR_VS | SCENE_MAJOR_ONLINE);
+        let in_game = versus && minor == SCENE_MINOR_IN_GAME;
+
+        // Player entities only exist while in-game, so there's nothing further to read.
+        if !in_game {
+            let selecting = match minor {
+                SCENE_MINOR_CSS if versus => Some(SelectScreen::Characters),
+                SCENE_MINOR_SSS if versus => Some(SelectScreen::Stage),
+                _ => None,
+            };
+
+            return Some(GameState {
+                selecting,
+                ..GameState::default()
+            });
+        }
+
+        let (p1_character_id, p1_stocks) = self.read_player(0)?;
+        let (p2_character_id, p2_stocks) = self.read_player(1)?;
+
+        let p2_setup = MATCH_PLAYER_START + MATCH_PLAYER_SIZE;
+        let p2_cpu_level = match self.read_u8(p2_setup + MATCH_PLAYER_TYPE_OFFSET)? {
+            PLAYER_TYPE_CPU => Some(self.read_u8(p2_setup + MATCH_PLAYER_CPU_LEVEL_OFFSET)?),
+            _ => None,
+        };
+
+        Some(GameState {
+            in_game,
+            selecting: None,
+            stage_id: self.read_u16(STAGE_ID)?,
+            p1_character_id,
+            p2_character_id,
+            p1_stocks,
+            p2_stocks,
+            p2_cpu_level,
+        })
+    }
+
+    /// Reads the (character id, stocks) for the player in `port` (0-indexed).
+    fn read_player(&self, port: u32) -> Option<(u8, u8)> {
+        use addresses::*;
+
+        let block = PLAYER_BLOCK_START + PLAYER_BLOCK_SIZE * port;
+
+        // These pointers are only valid mid-match; `checked_add` guards against garbage.
+        let entity = self.read_u32(block + PLAYER_ENTITY_OFFSET)?;
+        let fighter = self.read_u32(entity.checked_add(ENTITY_DATA_OFFSET)?)?;
+        let character_id = self.read_u8(fighter.checked_add(FIGHTER_CHARACTER_ID_OFFSET)?)?;
+        let stocks = self.read_u8(block + PLAYER_STOCKS_OFFSET)?;
+
+        Some((character_id, stocks))
+    }
+}
+
+/// Reads from the RAM of the running Dolphin instance.
+#[derive(Debug)]
+pub(crate) struct DolphinMemory {
+    ram_offset: usize,
+}
+
+impl DolphinMemory {
+    /// `ram_offset` is the host address that emulated main memory is mapped at.
+    pub fn new(ram_offset: usize) -> Self {
+        Self { ram_offset }
+    }
+
+    /// Points the reader at a new base address, e.g after Dolphin has remapped emulated
+    /// memory across a game restart.
+    ///
+    /// This takes `&mut self`, so it can't happen while a `game_state` read (which borrows the
+    /// reader for its whole duration) is in progress: every snapshot reads from a single base.
+    pub fn set_ram_offset(&mut self, ram_offset: usize) {
+        self.ram_offset = ram_offset;
+    }
+
+    /// Whether Dolphin has told us where emulated memory is. Until then, every read fails.
+    pub fn is_mapped(&self) -> bool {
+        self.ram_offset != 0
+    }
+}
+
+/// A zeroed stand-in for emulated RAM (which Melee would read as sitting in menus), for tests
+/// that need the handler to have memory to poll. It's shared and never freed, so handlers can
+/// keep reading it for as long as they like.
+#[cfg(test)]
+pub(crate) fn blank_ram_offset() -> usize {
+    static RAM: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+    *RAM.get_or_init(|| vec![0u8; addresses::MEM1_SIZE as usize].leak().as_ptr() as usize)
+}
+
+impl MemoryReader for DolphinMemory {
+    fn read(&self, address: u32, buf: &mut [u8]) -> bool {
+        use addresses::{MEM1_SIZE, MEM1_START};
+
+        let start = match address.checked_sub(MEM1_START) {
+            Some(start) => start as usize,
+            None => return false,
+        };
+
+        if self.ram_offset == 0 || start + buf.len() > MEM1_SIZE as usize {
+            return false;
+        }
+
+        let ram = (self.ram_offset + start) as *const u8;
+
+        for (i, byte) in buf.iter_mut().enumerate() {
+            // SAFETY: Dolphin maps main memory at `ram_offset` for the lifetime of the emulation
+            // session, and we've bounds checked the range against its size above.
+            //
+            // The emulator thread writes to this memory while we read it, which a plain copy
+            // would make a data race: the compiler is free to assume nothing else changes it,
+            // and so to merge, repeat or tear reads as it likes. Reading it a byte at a time
+            // with `read_volatile` treats it as memory outside of our control (as for MMIO),
+            // so each read happens exactly once, and without any alignment requirement. At
+            // worst a value spanning several bytes comes out torn, and the next poll picks up
+            // the right one.
+            *byte = unsafe { ram.add(i).read_volatile() };
+        }
+
+        true
+    }
+}
+
+/// Counts consecutive failed reads, so that a one-off bad read can be shrugged off while one
+/// that keeps failing still gets noticed.
+#[derive(Debug, Default)]
+pub(crate) struct ReadFailures {
+    consecutive: u32,
+}
+
+impl ReadFailures {
+    /// Records a failed read. Returns `DiscordRPCError::MemoryRead` the first time the failures
+    /// go on past `MAX_READ_FAILURES` in a row.
+    pub fn record(&mut self) -> Result<()> {
+        self.consecutive = self.consecutive.saturating_add(1);
+
+        match self.consecutive == MAX_READ_FAILURES + 1 {
+            true => Err(DiscordRPCError::MemoryRead(self.consecutive)),
+            false => Ok(()),
+        }
+    }
+
+    /// Records a successful read, starting the count over.
+    pub fn reset(&mut self) {
+        self.consecutive = 0;
+    }
+}
+
+/// Folds a freshly read `GameState` into `ctx`, given the previously read one. Returns whether
+/// anything changed that should be reflected in the presence.
+///
+/// A match that Slippi has reported (via `GameStart`) knows far more than memory does, so it's
+/// only ever refined here: it's left to `GameEnd` to end it, too. Only with nothing reported is
+/// the match pieced together from memory alone.
+pub(crate) fn sync_context(ctx: &mut PresenceContext, previous: &GameState, current: &GameState, now_millis: u64) -> bool {
+    let selecting_changed = previous.selecting != current.selecting;
+    ctx.selecting = current.selecting;
+
+    let game_changed = match (previous.in_game, current.in_game) {
+        (false, true) if ctx.game.is_some() => {
+            ctx.stocks = Some((current.p1_stocks, current.p2_stocks));
+            ctx.cpu_level = current.p2_cpu_level;
+            true
+        },
+
+        (false, true) => {
+            ctx.game = Some(GameInfo {
+                stage_id: current.stage_id,
+                p1_character_id: current.p1_character_id,
+                p2_character_id: current.p2_character_id,
+                // Costumes aren't read from RAM (yet), so these show the default.
+                p1_costume_id: 0,
+                p2_costume_id: 0,
+                kind: GameKind::Singles,
+                timer_seconds: None,
+                matchmaking: MatchmakingMode::default(),
+                players: [None; 4],
+                local_port: None,
+            });
+
+            ctx.game_from_memory = true;
+            ctx.started_at = now_millis;
+            ctx.stocks = Some((current.p1_stocks, current.p2_stocks));
+            ctx.cpu_level = current.p2_cpu_level;
+            true
+        },
+
+        (true, false) if ctx.game_from_memory => {
+            ctx.end_game();
+            true
+        },
+
+        (true, false) => false,
+
+        (true, true) => {
+            let mut changed = false;
+
+            if (previous.p1_stocks, previous.p2_stocks) != (current.p1_stocks, current.p2_stocks) {
+                ctx.stocks = Some((current.p1_stocks, current.p2_stocks));
+                changed = true;
+            }
+
+            // Only an actual change mid-match is taken as a transformation, so that whatever
+            // the match was reported to have started with isn't second-guessed.
+            let characters = [
+                (previous.p1_character_id, current.p1_character_id),
+                (previous.p2_character_id, current.p2_character_id),
+            ];
+
+            for (port, (previous, current)) in characters.into_iter().enumerate() {
+                if let Some(game) = ctx.game.as_mut().filter(|_| previous != current) {
+                    game.set_character(port, current);
+                    changed = true;
+                }
+            }
+
+            changed
+        },
+
+        (false, false) => false,
+    };
+
+    selecting_changed || game_changed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::addresses::*;
+    use super::*;
+    use crate::Config;
+
+    /// Memory backed by a map of individual bytes. Anything not set is unreadable.
+    #[derive(Default)]
+    struct FakeMemory {
+        bytes: HashMap<u32, u8>,
+    }
+
+    impl FakeMemory {
+        fn write(&mut self, address: u32, bytes: &[u8]) {
+            for (i, byte) in bytes.iter().enumerate() {
+                self.bytes.insert(address + i as u32, *byte);
+            }
+        }
+
+        fn write_player(&mut self, port: u32, character_id: u8, stocks: u8) {
+            let block = PLAYER_BLOCK_START + PLAYER_BLOCK_SIZE * port;
+            let entity = 0x8100_0000 + port * 0x1000;
+            let fighter = entity + 0x100;
+
+            self.write(block + PLAYER_ENTITY_OFFSET, &entity.to_be_bytes());
+            self.write(block + PLAYER_STOCKS_OFFSET, &[stocks]);
+            self.write(MATCH_PLAYER_START + MATCH_PLAYER_SIZE * port + MATCH_PLAYER_TYPE_OFFSET, &[0]);
+            self.write(entity + ENTITY_DATA_OFFSET, &fighter.to_be_bytes());
+            self.write(fighter + FIGHTER_CHARACTER_ID_OFFSET, &[character_id]);
+        }
+    }
+
+    impl MemoryReader for FakeMemory {
+        fn read(&self, address: u32, buf: &mut [u8]) -> bool {
+            for (i, byte) in buf.iter_mut().enumerate() {
+                match self.bytes.get(&(address + i as u32)) {
+                    Some(value) => *byte = *value,
+                    None => return false,
+                }
+            }
+
+            true
+        }
+    }
+
+    #[test]
+    fn reads_in_game_state() {
+        let mut memory = FakeMemory::default();
+        memory.write(SCENE_MAJOR, &[SCENE_MAJOR_ONLINE]);
+        memory.write(SCENE_MINOR, &[SCENE_MINOR_IN_GAME]);
+        memory.write(STAGE_ID, &32u16.to_be_bytes());
+        memory.write_player(0, 1, 4);
+        memory.write_player(1, 18, 3);
+
+        let state = memory.game_state().unwrap();
+        assert_eq!(
+            state,
+            GameState {
+                in_game: true,
+                selecting: None,
+                stage_id: 32,
+                p1_character_id: 1,
+                p2_character_id: 18,
+                p1_stocks: 4,
+                p2_stocks: 3,
+                p2_cpu_level: None,
+            }
+        );
+    }
+
+    #[test]
+    fn menus_are_not_in_game() {
+        // The main menu.
+        let mut memory = FakeMemory::default();
+        memory.write(SCENE_MAJOR, &[0x01]);
+        memory.write(SCENE_MINOR, &[0x00]);
+
+        assert_eq!(memory.game_state(), Some(GameState::default()));
+    }
+
+    #[test]
+    fn select_screens_lead_into_the_match() {
+        let mut ctx = PresenceContext::default();
+        let mut memory = FakeMemory::default();
+        let details = |ctx: &PresenceContext| crate::build_activity(ctx, &Config::default()).details;
+
+        memory.write(SCENE_MAJOR, &[SCENE_MAJOR_ONLINE]);
+        memory.write(SCENE_MINOR, &[SCENE_MINOR_CSS]);
+
+        let css = memory.game_state().unwrap();
+        assert_eq!(css.selecting, Some(SelectScreen::Characters));
+        assert!(!css.in_game);
+        assert!(sync_context(&mut ctx, &GameState::default(), &css, 0));
+        assert_eq!(details(&ctx).as_deref(), Some("Picking characters"));
+
+        memory.write(SCENE_MINOR, &[SCENE_MINOR_SSS]);
+
+        let sss = memory.game_state().unwrap();
+        assert_eq!(sss.selecting, Some(SelectScreen::Stage));
+        assert!(sync_context(&mut ctx, &css, &sss, 0));
+        assert_eq!(details(&ctx).as_deref(), Some("Picking a stage"));
+
+        // Once the match starts, the select screens are behind us.
+        memory.write(SCENE_MINOR, &[SCENE_MINOR_IN_GAME]);
+        memory.write(STAGE_ID, &32u16.to_be_bytes());
+        memory.write_player(0, 1, 4);
+        memory.write_player(1, 18, 4);
+
+        let playing = memory.game_state().unwrap();
+        assert!(sync_context(&mut ctx, &sss, &playing, 0));
+        assert_eq!(ctx.selecting, None);
+        assert_eq!(details(&ctx).as_deref(), Some("Battling on Final Destination"));
+    }
+
+    #[test]
+    fn cpu_opponents_are_detected() {
+        let mut ctx = PresenceContext::default();
+        let mut memory = FakeMemory::default();
+        memory.write(SCENE_MAJOR, &[SCENE_MAJOR_VS]);
+        memory.write(SCENE_MINOR, &[SCENE_MINOR_IN_GAME]);
+        memory.write(STAGE_ID, &32u16.to_be_bytes());
+        memory.write_player(0, 1, 4);
+        memory.write_player(1, 18, 4);
+
+        let p2_setup = MATCH_PLAYER_START + MATCH_PLAYER_SIZE;
+        memory.write(p2_setup + MATCH_PLAYER_TYPE_OFFSET, &[PLAYER_TYPE_CPU]);
+        memory.write(p2_setup + MATCH_PLAYER_CPU_LEVEL_OFFSET, &[9]);
+
+        let state = memory.game_state().unwrap();
+        assert_eq!(state.p2_cpu_level, Some(9));
+
+        assert!(sync_context(&mut ctx, &GameState::default(), &state, 0));
+        assert_eq!(ctx.cpu_level, Some(9));
+
+        sync_context(&mut ctx, &state, &GameState::default(), 0);
+        assert_eq!(ctx.cpu_level, None);
+    }
+
+    #[test]
+    fn unreadable_memory_yields_nothing() {
+        let mut memory = FakeMemory::default();
+        memory.write(SCENE_MAJOR, &[SCENE_MAJOR_VS]);
+        memory.write(SCENE_MINOR, &[SCENE_MINOR_IN_GAME]);
+
+        assert_eq!(memory.game_state(), None);
+        assert!(!DolphinMemory::new(0).read(SCENE_MAJOR, &mut [0]));
+    }
+
+    #[test]
+    fn ram_offset_can_be_swapped() {
+        // Only the first few bytes are ever read here, so these don't need to be a full MEM1.
+        let first = [0x12u8, 0x34, 0, 0];
+        let second = [0x56u8, 0x78, 0, 0];
+
+        let mut memory = DolphinMemory::new(first.as_ptr() as usize);
+        assert_eq!(memory.read_u16(MEM1_START), Some(0x1234));
+
+        memory.set_ram_offset(second.as_ptr() as usize);
+        assert_eq!(memory.read_u16(MEM1_START), Some(0x5678));
+    }
+
+    #[test]
+    fn one_off_read_failures_are_tolerated() {
+        let mut failures = ReadFailures::default();
+
+        for _ in 0..MAX_READ_FAILURES {
+            assert!(failures.record().is_ok());
+            failures.reset();
+        }
+
+        assert_eq!(failures.consecutive, 0);
+    }
+
+    #[test]
+    fn persistent_read_failures_surface_once() {
+        let mut failures = ReadFailures::default();
+
+        for _ in 0..MAX_READ_FAILURES {
+            assert!(failures.record().is_ok());
+        }
+
+        assert!(matches!(failures.record(), Err(DiscordRPCError::MemoryRead(count)) if count == MAX_READ_FAILURES + 1));
+        assert!(failures.record().is_ok());
+
+        failures.reset();
+        for _ in 0..MAX_READ_FAILURES {
+            assert!(failures.record().is_ok());
+        }
+        assert!(failures.record().is_err());
+    }
+
+    #[test]
+    fn sync_follows_the_match_lifecycle() {
+        let mut ctx = PresenceContext::default();
+        let menus = GameState::default();
+        let playing = GameState {
+            in_game: true,
+            selecting: None,
+            stage_id: 31,
+            p1_character_id: 1,
+            p2_character_id: 18,
+            p1_stocks: 4,
+            p2_stocks: 4,
+            p2_cpu_level: None,
+        };
+
+        assert!(!sync_context(&mut ctx, &menus, &menus, 0));
+
+        assert!(sync_context(&mut ctx, &menus, &playing, 500));
+        assert_eq!(ctx.started_at, 500);
+        assert_eq!(ctx.game.as_ref().unwrap().stage_id, 31);
+
+        let lost_a_stock = GameState {
+            p2_stocks: 3,
+            ..playing.clone()
+        };
+        assert!(!sync_context(&mut ctx, &playing, &playing, 600));
+        assert!(sync_context(&mut ctx, &playing, &lost_a_stock, 700));
+        assert_eq!(ctx.stocks, Some((4, 3)));
+
+        assert!(sync_context(&mut ctx, &lost_a_stock, &menus, 800));
+        assert_eq!(ctx.game, None);
+    }
+
+    #[test]
+    fn reported_matches_are_only_refined() {
+        let mut ctx = PresenceContext::default();
+        let menus = GameState::default();
+        let playing = GameState {
+            in_game: true,
+            selecting: None,
+            stage_id: 31,
+            p1_character_id: 1,
+            p2_character_id: 18,
+            p1_stocks: 4,
+            p2_stocks: 4,
+            p2_cpu_level: None,
+        };
+
+        let reported = GameInfo {
+            stage_id: 32,
+            p1_character_id: 1,
+            p2_character_id: 18,
+            p1_costume_id: 2,
+            p2_costume_id: 0,
+            kind: GameKind::Singles,
+            timer_seconds: None,
+            matchmaking: MatchmakingMode::Ranked,
+            players: [None; 4],
+            local_port: Some(1),
+        };
+        ctx.game = Some(reported.clone());
+        ctx.started_at = 100;
+
+        assert!(sync_context(&mut ctx, &menus, &playing, 500));
+        assert_eq!(ctx.game.as_ref(), Some(&reported));
+        assert_eq!(ctx.started_at, 100);
+        assert_eq!(ctx.stocks, Some((4, 4)));
+
+        // It's up to `GameEnd` to end it (with its result).
+        assert!(!sync_context(&mut ctx, &playing, &menus, 800));
+        assert_eq!(ctx.game.as_ref(), Some(&reported));
+    }
+
+    #[test]
+    fn transformations_swap_the_character() {
+        let mut ctx = PresenceContext::default();
+        let zelda = GameState {
+            in_game: true,
+            selecting: None,
+            stage_id: 31,
+            p1_character_id: 19,
+            p2_character_id: 18,
+            p1_stocks: 4,
+            p2_stocks: 4,
+            p2_cpu_level: None,
+        };
+        let sheik = GameState {
+            p1_character_id: 7,
+            ..zelda.clone()
+        };
+
+        let large_image = |ctx: &PresenceContext| crate::build_activity(ctx, &Config::default()).assets?.large_image;
+
+        sync_context(&mut ctx, &GameState::default(), &zelda, 0);
+        assert_eq!(large_image(&ctx).as_deref(), Some("zelda"));
+
+        assert!(sync_context(&mut ctx, &zelda, &sheik, 100));
+        assert_eq!(ctx.game.as_ref().unwrap().p1_character_id, 7);
+        assert_eq!(large_image(&ctx).as_deref(), Some("sheik"));
+
+        assert!(sync_context(&mut ctx, &sheik, &zelda, 200));
+        assert_eq!(large_image(&ctx).as_deref(), Some("zelda"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Counters for keeping an eye on how the background thread is doing, e.g when debugging
+//! presence that seems to lag behind or go missing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A point-in-time snapshot of the handler's counters. All counts are since the handler was
+/// created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiscordMetrics {
+    /// Activities (including clears) successfully written to Discord.
+    pub updates_sent: u64,
+
+    /// Activities that were replaced by a newer one before the rate limiter let them through,
+    /// and so were never sent.
+    pub updates_coalesced: u64,
+
+    /// Activities that were dropped because they were identical to the last one sent.
+    pub updates_skipped_identical: u64,
+
+    /// Successful connections made after the initial attempt, i.e recoveries from Discord
+    /// being unavailable or the connection dropping.
+    pub reconnects: u64,
+
+    /// Polls where the game state couldn't be read out of emulated memory, and the last one
+    /// read was kept instead.
+    pub memory_read_failures: u64,
+
+    /// Activities that Discord turned away for being sent too quickly.
+    pub rate_limited_count: u64,
+}
+
+/// The live counters behind `DiscordMetrics`, shared between the handler and its thread.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Metrics(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    updates_sent: AtomicU64,
+    updates_coalesced: AtomicU64,
+    updates_skipped_identical: AtomicU64,
+    reconnects: AtomicU64,
+    memory_read_failures: AtomicU64,
+    rate_limited_count: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_sent(&self) {
+        self.0.updates_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_coalesced(&self) {
+        self.0.updates_coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_skipped_identical(&self) {
+        self.0.updates_skipped_identical.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.0.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_memory_read_failure(&self) {
+        self.0.memory_read_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.0.rate_limited_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> DiscordMetrics {
+        DiscordMetrics {
+            updates_sent: self.0.updates_sent.load(Ordering::Relaxed),
+            updates_coalesced: self.0.updates_coalesced.load(Ordering::Relaxed),
+            updates_skipped_identical: self.0.updates_skipped_identical.load(Ordering::Relaxed),
+            reconnects: self.0.reconnects.load(Ordering::Relaxed),
+            memory_read_failures: self.0.memory_read_failures.load(Ordering::Relaxed),
+            rate_limited_count: self.0.rate_limited_count.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Saves the `PresenceContext` when the handler goes away, so that a handler started shortly
+//! afterwards (e.g, when Dolphin is restarted mid-session) can pick up where it left off
+//! rather than starting the match timer over.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use dolphin_integrations::Log;
+
+use crate::verbosity::{self, Verbosity};
+use crate::{Config, PresenceContext, Result};
+
+/// How old a saved context can be and still be restored. Anything older is from a previous
+/// session, and more likely to be wrong than helpful.
+const MAX_AGE: Duration = Duration::from_secs(30);
+
+/// A `PresenceContext`, as written to disk.
+#[derive(Debug, Serialize, Deserialize)]
+struct Saved {
+    /// When this was saved, as a Unix timestamp in milliseconds.
+    saved_at: u64,
+    ctx: PresenceContext,
+}
+
+/// Where the context is saved to under `dir` (normally the temp directory) for a handler
+/// configured like `config`. The temp directory can be shared between users, so the file is
+/// named for the user, as well as for the application ID and instance label: only a handler
+/// that's taking over from this one should pick it up.
+pub(crate) fn path_for(dir: &Path, config: &Config) -> PathBuf {
+    let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok();
+    path_in(dir, user.as_deref(), config)
+}
+
+/// Where the context for `user` and `config` is saved to under `dir`. Each part of the name is
+/// stripped down to what's safe in a file name, and separated by a `-` that none of them can
+/// contain.
+fn path_in(dir: &Path, user: Option<&str>, config: &Config) -> PathBuf {
+    let mut name = "slippi-discord-presence".to_string();
+
+    for part in [user, Some(&config.application_id), config.instance_label.as_deref()]
+        .into_iter()
+        .flatten()
+    {
+        name.push('-');
+        name.extend(part.chars().map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        }));
+    }
+
+    dir.join(format!("{name}.json"))
+}
+
+/// Writes `ctx` to `path`, stamped with `now` (a Unix timestamp in milliseconds).
+///
+/// It's written to a file beside `path` and then moved over it, so that `load` never sees
+/// (and deletes) a partial write. Only the current user can read it, as it names who they're
+/// playing.
+pub(crate) fn save(path: &Path, ctx: &PresenceContext, now: u64) -> Result<()> {
+    // Nothing would be left to expire these once restored, so they're left behind.
+    let saved = Saved {
+        saved_at: now,
+        ctx: PresenceContext {
+            highlight: None,
+            last_result: None,
+            awaiting_rematch_since: None,
+            // This is read back out of memory, which the next handler starts over on.
+            selecting: None,
+            condensed: false,
+            ..ctx.clone()
+        },
+    };
+
+    let contents = serde_json::to_vec(&saved)?;
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    // Whatever's there is ours, or was left in the way; `create_new` won't follow it.
+    let _ = std::fs::remove_file(&temp);
+
+    let written = create_private(&temp)
+        .and_then(|mut file| file.write_all(&contents))
+        .and_then(|()| std::fs::rename(&temp, path));
+
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    Ok(written?)
+}
+
+/// Creates a new file at `path` that only the current user can read or write.
+fn create_private(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+}
+
+/// Reads back a context saved to `path`, if there is one and it was saved within `MAX_AGE` of
+/// `now`. The file is removed either way, so a context is only ever restored once.
+pub(crate) fn load(path: &Path, now: u64, verbosity: &Verbosity) -> Option<PresenceContext> {
+    let contents = std::fs::read(path).ok()?;
+    let _ = std::fs::remove_file(path);
+
+    let saved: Saved = match serde_json::from_slice(&contents) {
+        Ok(saved) => saved,
+
+        Err(e) => {
+            verbosity::log!(verbosity, warn, target: Log::DiscordRPC, error = ?e, "Ignoring unreadable saved presence");
+            return None;
+        },
+    };
+
+    let age = Duration::from_millis(now.saturating_sub(saved.saved_at));
+
+    match age <= MAX_AGE {
+        true => Some(saved.ctx),
+
+        false => {
+            verbosity::log!(
+                verbosity,
+                debug,
+                target: Log::DiscordRPC,
+                ?age,
+                "Ignoring stale saved presence"
+            );
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameInfo, GameKind, MatchmakingMode};
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("slippi-discord-presence-{name}-{}.json", std::process::id()))
+    }
+
+    fn ctx() -> PresenceContext {
+        PresenceContext {
+            game: Some(GameInfo {
+                stage_id: 32,
+                p1_character_id: 1,
+                p2_character_id: 18,
+                p1_costume_id: 0,
+                p2_costume_id: 0,
+                kind: GameKind::Singles,
+                timer_seconds: Some(480),
+                matchmaking: MatchmakingMode::Ranked,
+                players: [None; 4],
+                local_port: None,
+            }),
+            started_at: 1_000,
+            stocks: Some((4, 3)),
+            connect_code: Some("ABCD#123".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn contexts_roundtrip_once() {
+        let path = path("roundtrip");
+        save(&path, &ctx(), 10_000).unwrap();
+
+        assert_eq!(load(&path, 15_000, &Verbosity::default()), Some(ctx()));
+        assert_eq!(load(&path, 15_000, &Verbosity::default()), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saved_contexts_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = path("private");
+        save(&path, &ctx(), 10_000).unwrap();
+        // Saving again replaces the file rather than writing into it.
+        save(&path, &ctx(), 10_000).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!path.with_extension(format!("{}.tmp", std::process::id())).exists());
+
+        assert_eq!(load(&path, 15_000, &Verbosity::default()), Some(ctx()));
+    }
+
+    #[test]
+    fn paths_are_per_user_and_instance() {
+        let dir = Path::new("saved");
+        let config = Config::default();
+
+        let labelled = Config {
+            instance_label: Some("p2/left".to_string()),
+            ..Default::default()
+        };
+
+        let other_app = Config {
+            application_id: "1234".to_string(),
+            ..Default::default()
+        };
+
+        let path = path_in(dir, Some("fox"), &config);
+        assert_eq!(
+            path,
+            dir.join(format!("slippi-discord-presence-fox-{}.json", config.application_id))
+        );
+        assert_eq!(path_in(dir, Some("fox"), &config), path);
+
+        assert_ne!(path_in(dir, Some("marth"), &config), path);
+        assert_ne!(path_in(dir, None, &config), path);
+        assert_ne!(path_in(dir, Some("fox"), &other_app), path);
+
+        assert_eq!(
+            path_in(dir, Some("fox"), &labelled),
+            dir.join(format!("slippi-discord-presence-fox-{}-p2_left.json", config.application_id))
+        );
+    }
+
+    #[test]
+    fn stale_contexts_are_dropped() {
+        let path = path("stale");
+        let saved_at = 10_000;
+        let cutoff = saved_at + MAX_AGE.as_millis() as u64;
+
+        save(&path, &ctx(), saved_at).unwrap();
+        assert_eq!(load(&path, cutoff, &Verbosity::default()), Some(ctx()));
+
+        save(&path, &ctx(), saved_at).unwrap();
+        assert_eq!(load(&path, cutoff + 1, &Verbosity::default()), None);
+        assert!(!path.exists());
+    }
+}
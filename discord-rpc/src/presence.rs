@@ -0,0 +1,2347 @@
+//! Builds the activities we show for each point in the match lifecycle.
+
+use serde::{Deserialize, Serialize};
+
+use crate::activity::SLIPPI_LOGO_ASSET;
+use crate::characters::{character_asset_key, character_asset_key_with_costume, character_name};
+use crate::i18n::translate;
+use crate::join::encode_join_secret;
+use crate::spectate::encode_spectate_secret;
+use crate::stages::{stage_asset_key, stage_name};
+use crate::template::try_render_template;
+use crate::{
+    Activity, ActivityType, Assets, Button, Config, GameEvent, GameInfo, GameKind, GameReport, GameResult, MatchmakingMode,
+    Party, PrivacyMode, Rank, Secrets, SelectScreen, SetScore, TeamColor, Timestamps, MAX_FIELD_CHARS,
+};
+
+/// What the opponent is referred to as when `PrivacyMode::HideOpponent` is set.
+const REDACTED_OPPONENT: &str = "an opponent";
+
+/// What opposing teams are referred to as when `PrivacyMode::HideOpponent` is set.
+const REDACTED_OPPONENTS: &str = "opponents";
+
+/// The small image shown while watching a replay.
+const REPLAY_ASSET: &str = "replay";
+
+/// The small image shown while searching for a match.
+const SEARCHING_ASSET: &str = "searching";
+
+/// The small image shown while spectating a lobby.
+const SPECTATING_ASSET: &str = "spectating";
+
+/// Melee runs at a fixed 60 frames per second.
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// What Slippi itself is up to, as reported by whatever's embedding us (e.g, the launcher).
+/// Anything we know from the game itself takes priority over this.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AppState {
+    /// Nothing worth showing.
+    #[default]
+    Idle,
+
+    /// Slippi is downloading or applying an update.
+    Updating,
+
+    /// A match is being played, though we may not know anything else about it.
+    InMatch,
+}
+
+/// Where the local player is in finding an online match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Matchmaking {
+    /// Searching for an opponent in `mode`, since `since` (a Unix timestamp in milliseconds).
+    Searching { mode: MatchmakingMode, since: u64 },
+
+    /// An opponent was found, and the match is about to start.
+    Found(MatchmakingMode),
+}
+
+/// Whether the local player is in a lobby to play, or just to watch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LobbyRole {
+    #[default]
+    Participant,
+    Spectator,
+}
+
+/// Everything the background thread knows about what the player is currently doing. Activities
+/// are rebuilt from this whenever any of it changes.
+///
+/// This can be saved and restored with `Config::persist_presence`. Anything missing from a
+/// saved copy is left at its default.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresenceContext {
+    /// The match in progress, if any.
+    pub game: Option<GameInfo>,
+
+    /// Whether `game` was pieced together from memory, rather than reported by Slippi. Only
+    /// then does memory get to decide when it ends; a reported match waits on `GameEnd`.
+    pub game_from_memory: bool,
+
+    /// When the match in progress started, as a Unix timestamp in milliseconds.
+    pub started_at: u64,
+
+    /// Stock counts for (p1, p2). These can arrive before the match has started, in which
+    /// case they're held until it does.
+    pub stocks: Option<(u8, u8)>,
+
+    /// Damage percents for (p1, p2), held the same way as `stocks`.
+    pub percents: Option<(f32, f32)>,
+
+    /// What to call the current opponent (their display name, or failing that their connect
+    /// code), if known.
+    pub opponent: Option<String>,
+
+    /// The opponent's level, if they're a CPU, as read from memory.
+    pub cpu_level: Option<u8>,
+
+    /// The local player's connect code, if known. This outlives any single match.
+    pub connect_code: Option<String>,
+
+    /// The local player's ranked tier and rating, if known.
+    pub rank: Option<Rank>,
+
+    /// An id that others can use to spectate the local player's matches, if they can be.
+    pub spectate_id: Option<String>,
+
+    /// The current match's frame counter. Melee counts up from a negative value before GO!,
+    /// which is clamped to zero here.
+    pub frame: Option<u32>,
+
+    /// Where the best-of set being played stands, if it's part of one. Unlike the match
+    /// itself, this carries over from one game of the set to the next.
+    pub set_score: Option<SetScore>,
+
+    /// The (current, max) number of players in the lobby the local player is waiting in, if
+    /// any. This is cleared once the match starts, but one that arrives mid-match is held
+    /// until it ends.
+    pub lobby: Option<(u32, u32)>,
+
+    /// Whether the local player is in `lobby` to play or to spectate.
+    pub lobby_role: LobbyRole,
+
+    /// Where the local player is in finding an online match, if they're looking for one. This
+    /// is cleared once the match starts (or the search is cancelled).
+    pub matchmaking: Option<Matchmaking>,
+
+    /// The replay being played back, if any. A live match takes priority over this.
+    pub playback: Option<GameInfo>,
+
+    /// A big moment in the match in progress, shown in place of the usual state line until
+    /// it expires.
+    pub highlight: Option<GameEvent>,
+
+    /// How the last match went, and what it was, while that's still being shown. This is
+    /// cleared once the next one starts.
+    pub last_result: Option<(GameResult, GameInfo)>,
+
+    /// Slippi's report on the last match, if it's sent one. This fills in the result while
+    /// it's shown, and is cleared alongside it.
+    pub last_report: Option<GameReport>,
+
+    /// When the local player started waiting on a rematch (i.e, their set ended and they've
+    /// stuck around), as a Unix timestamp in milliseconds, if they are. This is cleared once
+    /// the next match starts.
+    pub awaiting_rematch_since: Option<u64>,
+
+    /// What Slippi itself is up to.
+    pub app_state: AppState,
+
+    /// When the match in progress was paused, as a Unix timestamp in milliseconds, if it is.
+    pub paused_at: Option<u64>,
+
+    /// The local player's (wins, losses) so far this session, if we've been told. Like the
+    /// connect code, this outlives any single match.
+    pub session_record: Option<(u32, u32)>,
+
+    /// Whether Dolphin has yet to tell us where emulated memory is (i.e, there's no game
+    /// running for us to read), in which case we wait for it rather than claiming to be in
+    /// menus.
+    pub waiting_for_emulator: bool,
+
+    /// Which select screen the game is on, if it's on one, as read from memory.
+    pub selecting: Option<SelectScreen>,
+
+    /// Whether the presence has been showing the same thing for longer than
+    /// `Config::verbose_when_recent`, and so is condensed.
+    pub condensed: bool,
+}
+
+impl PresenceContext {
+    /// Clears everything specific to the match that just ended.
+    pub(crate) fn end_game(&mut self) {
+        self.game = None;
+        self.game_from_memory = false;
+        self.started_at = 0;
+        self.stocks = None;
+        self.percents = None;
+        self.frame = None;
+        self.highlight = None;
+        self.paused_at = None;
+        self.opponent = None;
+        self.cpu_level = None;
+    }
+
+    /// Records who the local player is up against, preferring `display_name` (if it isn't
+    /// blank) over `code`.
+    pub(crate) fn set_opponent(&mut self, code: String, display_name: Option<String>) {
+        self.opponent = display_name.filter(|name| !name.trim().is_empty()).or(Some(code));
+    }
+
+    /// Pauses or unpauses the match in progress at `now` (a Unix timestamp in milliseconds).
+    /// Time spent paused is left out of the elapsed timer, which picks up where it left off.
+    /// Returns whether anything changed.
+    pub(crate) fn set_paused(&mut self, paused: bool, now: u64) -> bool {
+        match (paused, self.paused_at) {
+            (true, None) if self.game.is_some() => {
+                self.paused_at = Some(now);
+                true
+            },
+
+            (false, Some(paused_at)) => {
+                self.paused_at = None;
+                self.started_at += now.saturating_sub(paused_at);
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Returns a copy of this context as seen from `port`: that port's player takes p1's
+    /// place (and the first other player p2's), with stocks and teams following along.
+    pub(crate) fn seen_from(&self, port: usize) -> PresenceContext {
+        let mut ctx = self.clone();
+
+        let info = match ctx.game.as_mut() {
+            Some(info) if port != 0 && port < info.players.len() => info,
+            _ => return ctx,
+        };
+
+        let you = match info.player(port) {
+            Some(you) => you,
+            None => return ctx,
+        };
+
+        let opponent = (0..info.players.len())
+            .filter(|other| *other != port)
+            .find_map(|other| info.player(other).map(|player| (other, player)));
+
+        info.p1_character_id = you.character_id;
+        info.p1_costume_id = you.costume_id;
+
+        if let Some((_, opponent)) = opponent {
+            info.p2_character_id = opponent.character_id;
+            info.p2_costume_id = opponent.costume_id;
+        }
+
+        info.players.swap(0, port);
+
+        // Stocks, percents and set scores are only tracked for the first two ports.
+        (ctx.stocks, ctx.percents, ctx.set_score) = match (port, opponent) {
+            (1, Some((0, _))) => (
+                ctx.stocks.map(|(p1, p2)| (p2, p1)),
+                ctx.percents.map(|(p1, p2)| (p2, p1)),
+                ctx.set_score.map(SetScore::swapped),
+            ),
+            (1, _) => (ctx.stocks, ctx.percents, ctx.set_score),
+            _ => (None, None, None),
+        };
+
+        ctx
+    }
+
+    /// Records the current frame counter. Returns whether the in-game time shown in the
+    /// presence would change as a result, i.e whether a whole second has ticked over.
+    pub(crate) fn set_frame(&mut self, frame: i32) -> bool {
+        let frame = u32::try_from(frame).unwrap_or(0);
+        let previous = self.frame.replace(frame);
+
+        previous.map(|previous| previous / FRAMES_PER_SECOND) != Some(frame / FRAMES_PER_SECOND)
+    }
+}
+
+/// Something the presence can be built from. Several can be active at once (e.g, a replay left
+/// playing while waiting in a lobby), in which case the highest priority one is shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PresenceSource<'a> {
+    /// A match in progress.
+    Match(&'a GameInfo),
+
+    /// Looking for an online match.
+    Matchmaking(Matchmaking),
+
+    /// The match that just ended, and how it went.
+    Result(GameResult, &'a GameInfo),
+
+    /// Waiting on a rematch after a set, since the enclosed Unix timestamp.
+    Rematch { since: u64 },
+
+    /// Waiting in a lobby with `current` of `max` players, as a `role`.
+    Lobby { current: u32, max: u32, role: LobbyRole },
+
+    /// A replay being played back.
+    Playback(&'a GameInfo),
+
+    /// An `AppState` other than `Idle`.
+    App(AppState),
+
+    /// On the character or stage select screen, getting ready for the next match.
+    Selecting(SelectScreen),
+
+    /// None of the above, while Dolphin has yet to map emulated memory. This takes the place of
+    /// `Menus` until it has.
+    WaitingForGame,
+
+    /// None of the above. This is always active (or `WaitingForGame` is), so there's always
+    /// something to show.
+    Menus,
+}
+
+impl PresenceSource<'_> {
+    /// Sources with a higher priority are shown over those with a lower one.
+    pub(crate) fn priority(&self) -> u8 {
+        match self {
+            PresenceSource::Match(_) => 8,
+            PresenceSource::Matchmaking(_) => 7,
+            PresenceSource::Result(..) => 6,
+            PresenceSource::Rematch { .. } => 5,
+            PresenceSource::Lobby { .. } => 4,
+            PresenceSource::Playback(_) => 3,
+            PresenceSource::App(_) => 2,
+            PresenceSource::Selecting(_) => 1,
+            PresenceSource::WaitingForGame | PresenceSource::Menus => 0,
+        }
+    }
+}
+
+impl PresenceContext {
+    /// Returns every source that's active in this context, highest priority first. Each lasts
+    /// for as long as the state behind it, so e.g once a match ends, whatever it was shown over
+    /// takes its place again.
+    pub(crate) fn sources(&self) -> Vec<PresenceSource<'_>> {
+        let mut sources: Vec<PresenceSource<'_>> = [
+            self.game.as_ref().map(PresenceSource::Match),
+            self.matchmaking.map(PresenceSource::Matchmaking),
+            self.last_result
+                .as_ref()
+                .map(|(result, info)| PresenceSource::Result(*result, info)),
+            self.awaiting_rematch_since.map(|since| PresenceSource::Rematch { since }),
+            self.lobby.map(|(current, max)| PresenceSource::Lobby {
+                current,
+                max,
+                role: self.lobby_role,
+            }),
+            self.playback.as_ref().map(PresenceSource::Playback),
+            (self.app_state != AppState::Idle).then_some(PresenceSource::App(self.app_state)),
+            self.selecting.map(PresenceSource::Selecting),
+            Some(match self.waiting_for_emulator {
+                true => PresenceSource::WaitingForGame,
+                false => PresenceSource::Menus,
+            }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        sources.sort_by_key(|source| std::cmp::Reverse(source.priority()));
+        sources
+    }
+
+    /// Returns the highest priority active source, which is the one the presence shows.
+    pub(crate) fn source(&self) -> PresenceSource<'_> {
+        self.sources().first().copied().unwrap_or(PresenceSource::Menus)
+    }
+}
+
+/// Decides what to show for the current context: `None` means the activity should be cleared,
+/// which is what happens between matches if `Config::show_idle_presence` is off, and during
+/// matches against a CPU unless `Config::show_cpu_matches` is on.
+pub(crate) fn update(ctx: &PresenceContext, config: &Config) -> Option<Activity> {
+    match (ctx.source(), config.show_idle_presence) {
+        (PresenceSource::WaitingForGame | PresenceSource::Menus, false) => None,
+        (PresenceSource::Match(_), _) if ctx.cpu_level.is_some() && !config.show_cpu_matches => None,
+        _ => Some(render(ctx, config)),
+    }
+}
+
+/// Renders the activity for `ctx`, exactly as the handler would send it. This doesn't need a
+/// handler (or Discord) at all, so it can be used to e.g preview settings.
+///
+/// The one thing it doesn't decide is whether to send anything: with
+/// `Config::show_idle_presence` off, the handler clears the activity in menus instead.
+pub fn build_activity(ctx: &PresenceContext, config: &Config) -> Activity {
+    let mut activity = render(ctx, config);
+    activity.sanitize();
+    activity
+}
+
+/// `build_activity`, short of sanitizing the text. The handler leaves that until it's sending
+/// the activity, so that anything cut off is only logged for what actually goes out.
+fn render(ctx: &PresenceContext, config: &Config) -> Activity {
+    let source = ctx.source();
+
+    let mut activity = match (source, config.privacy_mode) {
+        _ if config.stream_safe => stream_safe(source, ctx, config),
+        (_, PrivacyMode::Minimal) => minimal(),
+        (PresenceSource::Match(info), _) => {
+            let port = config.primary_port.or(info.local_port).unwrap_or(0);
+            let seen = ctx.seen_from(usize::from(port));
+            let mut activity = in_match(seen.game.as_ref().unwrap_or(info), &seen, config);
+
+            // The matchup (with the CPU's character) is left on the state line.
+            if let Some(level) = ctx.cpu_level {
+                activity.details = Some(match level {
+                    1..=9 => format!("Practicing vs CPU (Level {level})"),
+                    _ => "Practicing vs CPU".to_string(),
+                });
+            }
+
+            // Events name their ports as-is, so this works from the unswapped match.
+            if let Some(text) = ctx.highlight.and_then(|event| highlight(event, info, port, config)) {
+                activity.state = Some(text);
+            }
+
+            // Discord has no way to stop a running timer, so it's hidden until the match resumes.
+            if ctx.paused_at.is_some() {
+                activity.timestamps = None;
+                activity.state = Some(match activity.state.take() {
+                    Some(state) => format!("{state} (Paused)"),
+                    None => "Paused".to_string(),
+                });
+            }
+
+            activity
+        },
+        (PresenceSource::Matchmaking(matchmaking), _) => searching(matchmaking, config),
+        (PresenceSource::Result(result, info), _) => finished(result, info, ctx.last_report.as_ref(), config),
+        (PresenceSource::Rematch { since }, _) => awaiting_rematch(since, config),
+        (PresenceSource::Lobby { role, .. }, _) => lobby(role),
+        (PresenceSource::Playback(info), _) => playback(info, config),
+        (PresenceSource::App(state), _) => app(state),
+        (PresenceSource::Selecting(screen), _) => selecting(screen),
+        (PresenceSource::WaitingForGame, _) => waiting_for_game(),
+        (PresenceSource::Menus, _) => menus(),
+    };
+
+    // e.g "Session: 7W-3L", wherever the player is.
+    if let Some(record) = ctx.session_record.filter(|_| config.privacy_mode != PrivacyMode::Minimal) {
+        let record = session_record(record);
+
+        activity.state = Some(match activity.state.take() {
+            Some(state) => format!("{state} · {record}"),
+            None => record,
+        });
+    }
+
+    // While waiting in a lobby, Discord can show how full it is (e.g, "1 of 2").
+    let lobby_size = match source {
+        PresenceSource::Lobby { current, max, .. } => Some([current, max]),
+        _ => None,
+    };
+
+    // Stream-safe presence can't point back at the local player, even indirectly.
+    let connect_code = ctx.connect_code.as_deref().filter(|_| !config.stream_safe);
+
+    // With a connect code, Discord can offer friends a "Join" button that direct connects.
+    let party_id = connect_code.map(|connect_code| format!("slippi-{connect_code}"));
+
+    if party_id.is_some() || lobby_size.is_some() {
+        activity.party = Some(Party {
+            size: lobby_size.or(party_id.as_ref().map(|_| [1, 2])),
+            id: party_id,
+        });
+    }
+
+    // Spectating only makes sense mid-match, and is opt-in.
+    let spectate_id = match (source, config.enable_spectate && !config.stream_safe) {
+        (PresenceSource::Match(_), true) => ctx.spectate_id.as_deref(),
+        _ => None,
+    };
+
+    let secrets = Secrets {
+        join: connect_code.map(encode_join_secret),
+        spectate: spectate_id.map(encode_spectate_secret),
+    };
+
+    if secrets != Secrets::default() {
+        activity.secrets = Some(secrets);
+    }
+
+    // Discord rejects buttons alongside secrets, and joining is the more useful of the two. Any
+    // button could link back to the player, so stream-safe mode drops them all.
+    let buttons: Vec<Button> = config
+        .profile_url
+        .iter()
+        .map(|url| Button::new("View Profile", url))
+        .chain(config.extra_button.iter().map(|(label, url)| Button::new(label, url)))
+        .filter(|_| !config.stream_safe)
+        .collect();
+
+    if activity.secrets.is_none() && !buttons.is_empty() {
+        activity.buttons = Some(buttons);
+    }
+
+    // Ranked is the only mode that's really a competition.
+    activity.kind = Some(match (config.force_activity_type, source) {
+        (Some(kind), _) => kind,
+        (None, PresenceSource::Match(info)) if info.matchmaking == MatchmakingMode::Ranked => ActivityType::Competing,
+        (None, _) => ActivityType::Playing,
+    });
+
+    // Discord shows a broken image for an empty key, rather than none at all.
+    if let Some(assets) = activity.assets.as_mut() {
+        if assets.large_image.as_deref() == Some("") {
+            assets.large_image = fallback_assets(config).large_image;
+        }
+
+        if assets.small_image.as_deref() == Some("") {
+            assets.small_image = None;
+        }
+    }
+
+    // e.g "Slippi 3.4.0", wherever the small image is free. Discord only shows small text on
+    // hovering the small image, so the logo goes there to carry it.
+    if let Some(version) = config
+        .version_string
+        .as_deref()
+        .filter(|_| config.privacy_mode != PrivacyMode::Minimal)
+    {
+        let assets = activity.assets.get_or_insert_with(Assets::default);
+
+        if assets.small_image.is_none() && assets.small_text.is_none() {
+            assets.small_image = Some(SLIPPI_LOGO_ASSET.to_string());
+            assets.small_text = Some(version.to_string());
+        }
+    }
+
+    if ctx.condensed {
+        condense(&mut activity.details);
+        condense(&mut activity.state);
+    }
+
+    activity
+}
+
+/// Cuts `text` down to its main part, i.e whatever comes before the first " · ", e.g
+/// "Battling on Final Destination · Game 2 — 1-0" down to "Battling on Final Destination".
+fn condense(text: &mut Option<String>) {
+    if let Some(main) = text.as_mut() {
+        if let Some(end) = main.find(" · ") {
+            main.truncate(end);
+        }
+    }
+}
+
+/// The activity shown while a match is in progress.
+///
+/// Characters or stages that we can't resolve fall back to `Config::fallback_large_image` (or no
+/// image at all) and are left out of the text. If the details template can't be fully rendered,
+/// we fall back to a generic "In a match".
+///
+/// With `PrivacyMode::HideOpponent`, the opponent's character is replaced with a generic
+/// description everywhere it would otherwise appear.
+///
+/// Anything turned off with the `Config::show_*` flags is left out the same way as something
+/// we can't resolve. If they're all off, this is the same as `PrivacyMode::Minimal`.
+fn in_match(info: &GameInfo, ctx: &PresenceContext, config: &Config) -> Activity {
+    let shown = [
+        config.show_characters,
+        config.show_stage,
+        config.show_stocks,
+        config.show_percents,
+        config.show_timer,
+        config.show_mode,
+    ];
+
+    if !shown.contains(&true) {
+        return minimal();
+    }
+
+    let hide_opponent = config.privacy_mode == PrivacyMode::HideOpponent;
+    let locale = config.locale.as_str();
+    let name_of = |id| {
+        character_name(id)
+            .filter(|_| config.show_characters)
+            .map(|name| translate(locale, name))
+    };
+
+    let opponent = match hide_opponent {
+        true => config.show_characters.then_some(REDACTED_OPPONENT),
+        false => name_of(info.p2_character_id),
+    };
+
+    let names = name_of(info.p1_character_id).zip(opponent);
+    let stocks = ctx.stocks.filter(|_| config.show_stocks);
+    let percents = ctx.percents.filter(|_| config.show_percents);
+    let state = matchup_state(names, stocks, percents);
+
+    let template = match hide_opponent {
+        true => config.details_template.replace("{p2_char}", REDACTED_OPPONENT),
+        false => config.details_template.clone(),
+    };
+
+    // Hidden placeholders make the template unrenderable, just like unresolvable ones do.
+    let hidden = [
+        ("{p1_char}", config.show_characters),
+        ("{p2_char}", config.show_characters),
+        ("{stage}", config.show_stage),
+        ("{mode}", config.show_mode),
+    ];
+
+    let details = match hidden
+        .iter()
+        .any(|(placeholder, shown)| !shown && template.contains(placeholder))
+    {
+        true => None,
+        false => try_render_template(&template, ctx, locale),
+    };
+
+    let mut details = details
+        .filter(|details| !details.trim().is_empty())
+        .unwrap_or_else(|| "In a match".to_string());
+
+    // Teams matchups are a mouthful, so they take over the details line (with the rendered
+    // template dropping down to the state line instead of the singles matchup).
+    let is_teams = info.matchmaking == MatchmakingMode::Teams || info.kind == GameKind::Doubles;
+
+    let matchup = (is_teams && config.show_characters).then(|| team_matchup(info, hide_opponent, locale));
+
+    let state = match matchup.flatten() {
+        Some(matchup) => Some(std::mem::replace(&mut details, matchup)),
+        None => state,
+    };
+
+    // e.g "Game 2 — 1-0". The opponent's side of the score is theirs to keep private.
+    if let Some(score) = ctx.set_score {
+        details = match hide_opponent {
+            true => format!("{details} · Game {}", score.game),
+            false => format!("{details} · Game {} — {}-{}", score.game, score.p1_wins, score.p2_wins),
+        };
+    }
+
+    // Who the opponent is online, e.g "GRNK#123". A teams match has more than one of them.
+    let state = match (&state, &ctx.opponent) {
+        (Some(state), Some(opponent)) if !is_teams && !hide_opponent => Some(format!("{state} · vs {opponent}")),
+        _ => state,
+    };
+
+    // Unlike the timestamps, which Discord counts from wall-clock time, this stays accurate
+    // through pauses.
+    let state = match (config.show_ingame_time, ctx.frame) {
+        (true, Some(frame)) => {
+            let seconds = frame / FRAMES_PER_SECOND;
+            let time = format!("{}:{:02}", seconds / 60, seconds % 60);
+
+            match state {
+                Some(state) => Some(format!("{state} · {time}")),
+                None => Some(time),
+            }
+        },
+
+        _ => state,
+    };
+
+    // Only the local player's costume is shown; the opponent's would just be noise.
+    let mut assets = match character_asset_key(info.p1_character_id).filter(|_| config.show_characters) {
+        Some(_) => Assets {
+            large_image: Some(character_image(info.p1_character_id, info.p1_costume_id, config)),
+            large_text: name_of(info.p1_character_id).map(|name| name.to_string()),
+            ..Default::default()
+        },
+
+        None => fallback_assets(config),
+    };
+
+    // Ranked matches show the player's rank in the small image (or nothing, until we know it),
+    // other online matches show the matchmaking mode, and offline ones the stage. An overridden
+    // mode icon is shown in place of the stage (or in ranked, until the rank is known).
+    let mode_override = config.asset_override(&format!("mode:{}", info.matchmaking.name()));
+    let mode_key = mode_override.clone().or(info.matchmaking.asset_key().map(str::to_string));
+
+    match (info.matchmaking, mode_key.filter(|_| config.show_mode)) {
+        (MatchmakingMode::Ranked, _) => match (&ctx.rank, mode_override.filter(|_| config.show_mode)) {
+            (Some(rank), _) => {
+                assets.small_image = Some(rank.tier.asset_key().to_string());
+                assets.small_text = Some(rank.to_string());
+            },
+
+            (None, Some(key)) => {
+                assets.small_image = Some(key);
+                assets.small_text = Some(translate(locale, matchmaking_text(MatchmakingMode::Ranked)).to_string());
+            },
+
+            (None, None) => {},
+        },
+
+        (mode, Some(key)) => {
+            let text = translate(locale, matchmaking_text(mode));
+
+            // In teams, the local player's (i.e, the first player's, as in `team_matchup`) team
+            // color stands in for the neutral mode icon.
+            let local_team = info.players.iter().flatten().next().and_then(|player| player.team);
+
+            match local_team.filter(|_| is_teams) {
+                Some(team) => {
+                    assets.small_image = Some(team.asset_key().to_string());
+                    assets.small_text = Some(format!("{text} · {}", translate(locale, team.name())));
+                },
+
+                None => {
+                    assets.small_image = Some(key);
+                    assets.small_text = Some(text.to_string());
+                },
+            }
+        },
+
+        (_, None) => {
+            if let Some(default) = stage_asset_key(info.stage_id).filter(|_| config.show_stage) {
+                let name = stage_name(info.stage_id);
+                let key = name.and_then(|name| config.asset_override(&format!("stage:{name}")));
+
+                assets.small_image = Some(key.unwrap_or_else(|| default.to_string()));
+                assets.small_text = name.map(|name| translate(locale, name).to_string());
+            }
+        },
+    }
+
+    let timestamps = match config.show_timer {
+        true => Some(Timestamps {
+            start: Some(ctx.started_at),
+            end: info.timer_seconds.map(|seconds| ctx.started_at + u64::from(seconds) * 1000),
+        }),
+
+        false => None,
+    };
+
+    Activity {
+        details: Some(details),
+        state,
+        timestamps,
+        assets: Some(assets),
+        ..Default::default()
+    }
+}
+
+/// Describes a `GameEvent`, e.g "🔥 Fox landed a 62% combo!". `local_port` is whoever the
+/// presence is shown for, who's the only player named with `PrivacyMode::HideOpponent`.
+fn highlight(event: GameEvent, info: &GameInfo, local_port: u8, config: &Config) -> Option<String> {
+    let port = match event {
+        GameEvent::Kill { killer } => killer,
+        GameEvent::BigCombo { port, .. } => port,
+    };
+
+    let name = match config.privacy_mode == PrivacyMode::HideOpponent && port != local_port {
+        true => REDACTED_OPPONENT,
+        false => {
+            let player = info.player(usize::from(port)).filter(|_| config.show_characters)?;
+            translate(&config.locale, character_name(player.character_id)?)
+        },
+    };
+
+    let text = match event {
+        GameEvent::Kill { .. } => format!("💥 {name} took a stock!"),
+        GameEvent::BigCombo { damage, .. } => format!("🔥 {name} landed a {damage:.0}% combo!"),
+    };
+
+    Some(text)
+}
+
+/// Formats a teams matchup, grouped by team color, e.g "Fox & Falco vs Marth & Peach".
+///
+/// Returns `None` unless there are at least two teams with (recognized) characters in them. If
+/// `hide_opponent` is set, every team other than the first player's (i.e, "you") is redacted.
+fn team_matchup(info: &GameInfo, hide_opponent: bool, locale: &str) -> Option<String> {
+    let local_team = info.players.iter().flatten().next()?.team;
+    let mut teams: Vec<(TeamColor, Vec<&str>)> = Vec::new();
+
+    for player in info.players.iter().flatten() {
+        let (team, name) = match (player.team, character_name(player.character_id)) {
+            (Some(team), Some(name)) => (team, translate(locale, name)),
+            _ => continue,
+        };
+
+        match teams.iter_mut().find(|(color, _)| *color == team) {
+            Some((_, names)) => names.push(name),
+            None => teams.push((team, vec![name])),
+        }
+    }
+
+    if teams.len() < 2 {
+        return None;
+    }
+
+    teams.sort_by_key(|(color, _)| *color);
+
+    let formatted: Vec<String> = teams
+        .into_iter()
+        .map(|(color, names)| match hide_opponent && Some(color) != local_team {
+            true => REDACTED_OPPONENTS.to_string(),
+            false => names.join(" & "),
+        })
+        .collect();
+
+    Some(formatted.join(" vs "))
+}
+
+/// The large image for a character in a costume, unless `Config::asset_overrides` replaces it
+/// (for every costume).
+fn character_image(character_id: u8, costume_id: u8, config: &Config) -> String {
+    character_name(character_id)
+        .and_then(|name| config.asset_override(&format!("char:{name}")))
+        .unwrap_or_else(|| character_asset_key_with_costume(character_id, costume_id).to_string())
+}
+
+/// The Slippi logo's assets, with `Config::fallback_large_image` in its place (if there is one), for
+/// when there's no specific art to show.
+fn fallback_assets(config: &Config) -> Assets {
+    let fallback = Some(config.fallback_large_image.clone()).filter(|key| !key.is_empty());
+
+    Assets {
+        large_image: fallback,
+        ..Activity::slippi().assets.unwrap_or_default()
+    }
+}
+
+/// Describes how the match came about, e.g "Playing with friends".
+fn matchmaking_text(mode: MatchmakingMode) -> &'static str {
+    match mode {
+        MatchmakingMode::Ranked => "Ranked Match",
+        MatchmakingMode::Unranked => "Unranked Match",
+        MatchmakingMode::Direct => "Playing with friends",
+        MatchmakingMode::Teams => "Teams Match",
+        MatchmakingMode::Offline => "Playing locally",
+    }
+}
+
+/// The activity shown while looking for an online match, with how long the search has taken
+/// so far.
+fn searching(matchmaking: Matchmaking, config: &Config) -> Activity {
+    let base = Activity::slippi();
+    let locale = config.locale.as_str();
+
+    let (details, mode, since) = match matchmaking {
+        Matchmaking::Searching { mode, since } => {
+            let details = match mode {
+                MatchmakingMode::Ranked => "Searching for a Ranked match...",
+                MatchmakingMode::Unranked => "Searching for an Unranked match...",
+                MatchmakingMode::Direct => "Connecting to a friend...",
+                MatchmakingMode::Teams => "Searching for a Teams match...",
+                MatchmakingMode::Offline => "Searching for a match...",
+            };
+
+            (details, mode, Some(since))
+        },
+
+        Matchmaking::Found(mode) => ("Match found!", mode, None),
+    };
+
+    Activity {
+        details: Some(details.to_string()),
+        timestamps: since.filter(|_| config.show_timer).map(|since| Timestamps {
+            start: Some(since),
+            end: None,
+        }),
+        assets: Some(Assets {
+            small_image: Some(SEARCHING_ASSET.to_string()),
+            small_text: Some(translate(locale, matchmaking_text(mode)).to_string()),
+            ..base.assets.unwrap_or_default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The activity shown while watching a replay. The matchup is only shown with
+/// `PrivacyMode::Full`, as the replay may well be of one of the player's own matches.
+fn playback(info: &GameInfo, config: &Config) -> Activity {
+    let base = Activity::slippi();
+    let locale = config.locale.as_str();
+    let name_of = |id| character_name(id).map(|name| translate(locale, name));
+
+    let state = match (
+        config.privacy_mode,
+        name_of(info.p1_character_id),
+        name_of(info.p2_character_id),
+    ) {
+        (PrivacyMode::Full, Some(p1), Some(p2)) => Some(format!("{p1} vs {p2}")),
+        _ => None,
+    };
+
+    Activity {
+        details: Some("Watching a replay".to_string()),
+        state,
+        assets: Some(Assets {
+            small_image: Some(REPLAY_ASSET.to_string()),
+            small_text: Some("Spectating".to_string()),
+            ..base.assets.unwrap_or_default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The activity shown for `PrivacyMode::Minimal`, regardless of what the player is doing.
+fn minimal() -> Activity {
+    Activity {
+        details: Some("Playing Slippi".to_string()),
+        ..Activity::slippi()
+    }
+}
+
+/// The activity shown for a few seconds after a match, e.g "Won as Fox!". Only the local
+/// player's character is named, so this is the same with `PrivacyMode::HideOpponent`.
+///
+/// With a `GameReport`, its winner takes precedence over `result`, and a win says how many
+/// stocks were left (e.g "Won as Fox — 2 stocks left"). The state line then shows how long the
+/// match lasted and how much damage the local player dealt.
+fn finished(result: GameResult, info: &GameInfo, report: Option<&GameReport>, config: &Config) -> Activity {
+    let port = config.primary_port.or(info.local_port).unwrap_or(0);
+    let player = info.player(usize::from(port)).filter(|_| config.show_characters);
+    let name = player.and_then(|player| character_name(player.character_id));
+    let name = name.map(|name| translate(&config.locale, name));
+
+    let result = match report.and_then(|report| report.winner_port) {
+        Some(winner) if winner == port => GameResult::Win,
+        Some(_) => GameResult::Loss,
+        None => result,
+    };
+
+    let stocks_left = report
+        .and_then(|report| report.stocks_remaining.get(usize::from(port)).copied())
+        .filter(|_| config.show_stocks)
+        .map(|stocks| match stocks {
+            1 => "1 stock left".to_string(),
+            stocks => format!("{stocks} stocks left"),
+        });
+
+    let details = match (result, name, stocks_left) {
+        (GameResult::Win, Some(name), Some(stocks_left)) => format!("Won as {name} — {stocks_left}"),
+        (GameResult::Win, None, Some(stocks_left)) => format!("Won a match — {stocks_left}"),
+        (GameResult::Win, Some(name), None) => format!("Won as {name}!"),
+        (GameResult::Loss, Some(name), _) => format!("Lost as {name}"),
+        (GameResult::Win, None, None) => "Won a match!".to_string(),
+        (GameResult::Loss, None, _) | (GameResult::NoContest, ..) => "Finished a match".to_string(),
+    };
+
+    // e.g "3:42 · 187% dealt"
+    let state = report.map(|report| {
+        let seconds = report.duration_frames / FRAMES_PER_SECOND;
+        let damage = report.damage_dealt.get(usize::from(port)).copied().unwrap_or_default();
+        format!("{}:{:02} · {damage:.0}% dealt", seconds / 60, seconds % 60)
+    });
+
+    let assets = match player.filter(|player| character_asset_key(player.character_id).is_some()) {
+        Some(player) => Assets {
+            large_image: Some(character_image(player.character_id, player.costume_id, config)),
+            large_text: name.map(|name| name.to_string()),
+            ..Default::default()
+        },
+
+        None => fallback_assets(config),
+    };
+
+    Activity {
+        details: Some(details),
+        state,
+        assets: Some(assets),
+        ..Default::default()
+    }
+}
+
+/// The activity shown for an `AppState`, when there's nothing more specific to show.
+fn app(state: AppState) -> Activity {
+    let details = match state {
+        AppState::Updating => "Updating Slippi...",
+        AppState::InMatch => "In a match",
+        AppState::Idle => return menus(),
+    };
+
+    Activity {
+        details: Some(details.to_string()),
+        ..Activity::slippi()
+    }
+}
+
+/// The activity shown with `Config::stream_safe`, which describes what's going on in terms of
+/// modes alone, e.g "Playing Singles".
+fn stream_safe(source: PresenceSource<'_>, ctx: &PresenceContext, config: &Config) -> Activity {
+    let locale = config.locale.as_str();
+
+    match source {
+        PresenceSource::Match(info) => {
+            let kind = match info.kind {
+                GameKind::Singles => "Singles",
+                GameKind::Doubles => "Doubles",
+            };
+
+            Activity {
+                details: Some(format!("Playing {}", translate(locale, kind))),
+                state: Some(translate(locale, matchmaking_text(info.matchmaking)).to_string()),
+                timestamps: (config.show_timer && ctx.paused_at.is_none()).then_some(Timestamps {
+                    start: Some(ctx.started_at),
+                    end: None,
+                }),
+                ..Activity::slippi()
+            }
+        },
+
+        PresenceSource::Result(..) => Activity {
+            details: Some("Finished a match".to_string()),
+            ..Activity::slippi()
+        },
+
+        PresenceSource::Playback(_) => Activity {
+            details: Some("Watching a replay".to_string()),
+            ..Activity::slippi()
+        },
+
+        PresenceSource::Matchmaking(matchmaking) => searching(matchmaking, config),
+        PresenceSource::Rematch { since } => awaiting_rematch(since, config),
+        PresenceSource::Lobby { role, .. } => lobby(role),
+        PresenceSource::App(state) => app(state),
+        PresenceSource::Selecting(screen) => selecting(screen),
+        PresenceSource::WaitingForGame => waiting_for_game(),
+        PresenceSource::Menus => menus(),
+    }
+}
+
+/// The state line for a match, e.g "Fox (3) 87% vs Marth (2) 42%", with whichever of the
+/// stock counts and damage percents are given. Percents are rounded to whole numbers, and
+/// dropped again if they'd make the line too long for Discord.
+fn matchup_state(names: Option<(&str, &str)>, stocks: Option<(u8, u8)>, percents: Option<(f32, f32)>) -> Option<String> {
+    let side = |name: &str, stocks: Option<u8>, percent: Option<u32>| {
+        let mut side = name.to_string();
+
+        if let Some(stocks) = stocks {
+            side.push_str(&format!(" ({stocks})"));
+        }
+
+        if let Some(percent) = percent {
+            side.push_str(&format!(" {percent}%"));
+        }
+
+        side
+    };
+
+    let render = |percents: Option<(u32, u32)>| match (names, stocks, percents) {
+        (Some((p1, p2)), ..) => Some(format!(
+            "{} vs {}",
+            side(p1, stocks.map(|(p1, _)| p1), percents.map(|(p1, _)| p1)),
+            side(p2, stocks.map(|(_, p2)| p2), percents.map(|(_, p2)| p2)),
+        )),
+
+        (None, Some((p1_stocks, p2_stocks)), Some((p1_percent, p2_percent))) => {
+            Some(format!("Stocks: {p1_stocks}-{p2_stocks} · {p1_percent}%-{p2_percent}%"))
+        },
+
+        (None, Some((p1_stocks, p2_stocks)), None) => Some(format!("Stocks: {p1_stocks}-{p2_stocks}")),
+        (None, None, Some((p1_percent, p2_percent))) => Some(format!("Damage: {p1_percent}%-{p2_percent}%")),
+        (None, None, None) => None,
+    };
+
+    // Negative (or NaN) percents can only be garbage, so they're shown as zero.
+    let rounded = |percent: f32| percent.round().max(0.0) as u32;
+    let percents = percents.map(|(p1, p2)| (rounded(p1), rounded(p2)));
+
+    render(percents)
+        .filter(|state| state.chars().count() <= MAX_FIELD_CHARS)
+        .or_else(|| render(None))
+}
+
+/// How a session's (wins, losses) read in the state line, e.g "Session: 7W-3L".
+fn session_record((wins, losses): (u32, u32)) -> String {
+    format!("Session: {wins}W-{losses}L")
+}
+
+/// The activity shown while waiting on a rematch once a set is over, with how long it's been.
+fn awaiting_rematch(since: u64, config: &Config) -> Activity {
+    Activity {
+        details: Some("Waiting for rematch".to_string()),
+        timestamps: config.show_timer.then_some(Timestamps {
+            start: Some(since),
+            end: None,
+        }),
+        ..Activity::slippi()
+    }
+}
+
+/// The activity shown while waiting in a lobby, either for opponents or (as a spectator) for
+/// the match to start.
+fn lobby(role: LobbyRole) -> Activity {
+    match role {
+        LobbyRole::Participant => Activity {
+            details: Some("In a lobby".to_string()),
+            ..Activity::slippi()
+        },
+
+        LobbyRole::Spectator => {
+            let mut activity = Activity {
+                details: Some("Spectating a lobby".to_string()),
+                ..Activity::slippi()
+            };
+
+            if let Some(assets) = activity.assets.as_mut() {
+                assets.small_image = Some(SPECTATING_ASSET.to_string());
+                assets.small_text = Some("Spectating".to_string());
+            }
+
+            activity
+        },
+    }
+}
+
+/// The activity shown on the select screens, between menus and the match.
+fn selecting(screen: SelectScreen) -> Activity {
+    let details = match screen {
+        SelectScreen::Characters => "Picking characters",
+        SelectScreen::Stage => "Picking a stage",
+    };
+
+    Activity {
+        details: Some(details.to_string()),
+        ..Activity::slippi()
+    }
+}
+
+/// The activity shown while Dolphin is open, but has yet to boot the game.
+fn waiting_for_game() -> Activity {
+    Activity {
+        details: Some("Waiting for game".to_string()),
+        ..Activity::slippi()
+    }
+}
+
+/// The activity shown while sitting in menus, between matches.
+fn menus() -> Activity {
+    Activity {
+        details: Some("In menus".to_string()),
+        ..Activity::slippi()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Player, RankTier};
+
+    fn in_game(timer_seconds: Option<u32>) -> PresenceContext {
+        PresenceContext {
+            game: Some(GameInfo {
+                stage_id: 32,
+                p1_character_id: 1,
+                p2_character_id: 18,
+                p1_costume_id: 0,
+                p2_costume_id: 0,
+                kind: GameKind::Singles,
+                timer_seconds,
+                matchmaking: MatchmakingMode::Offline,
+                players: [None; 4],
+                local_port: None,
+            }),
+            started_at: 1_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn representative_contexts_render_fully() {
+        let mut ctx = in_game(Some(480));
+        ctx.game.as_mut().unwrap().matchmaking = MatchmakingMode::Ranked;
+        ctx.stocks = Some((3, 2));
+        ctx.opponent = Some("GRNK#123".to_string());
+        ctx.rank = Some(Rank {
+            tier: RankTier::Diamond2,
+            rating: 1850.0,
+        });
+
+        assert_eq!(
+            build_activity(&ctx, &Config::default()),
+            Activity {
+                kind: Some(ActivityType::Competing),
+                details: Some("Battling on Final Destination".to_string()),
+                state: Some("Fox (3) vs Marth (2) · vs GRNK#123".to_string()),
+                timestamps: Some(Timestamps {
+                    start: Some(1_000),
+                    end: Some(481_000),
+                }),
+                assets: Some(Assets {
+                    large_image: Some("fox".to_string()),
+                    large_text: Some("Fox".to_string()),
+                    small_image: Some("rank_diamond_2".to_string()),
+                    small_text: Some("Diamond 2 — 1850".to_string()),
+                }),
+                ..Default::default()
+            }
+        );
+
+        ctx.end_game();
+        assert_eq!(
+            build_activity(&ctx, &Config::default()),
+            Activity {
+                kind: Some(ActivityType::Playing),
+                details: Some("In menus".to_string()),
+                ..Activity::slippi()
+            }
+        );
+    }
+
+    #[test]
+    fn timed_matches_count_down() {
+        let activity = build_activity(&in_game(Some(480)), &Config::default());
+
+        let timestamps = activity.timestamps.unwrap();
+        assert_eq!(timestamps.start, Some(1_000));
+        assert_eq!(timestamps.end, Some(481_000));
+    }
+
+    #[test]
+    fn timer_can_be_disabled() {
+        let config = Config {
+            show_timer: false,
+            ..Default::default()
+        };
+
+        assert_eq!(build_activity(&in_game(Some(480)), &config).timestamps, None);
+        assert_eq!(build_activity(&PresenceContext::default(), &config).timestamps, None);
+    }
+
+    #[test]
+    fn details_fall_back_when_template_cannot_render() {
+        let mut ctx = in_game(None);
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).details.as_deref(),
+            Some("Battling on Final Destination")
+        );
+
+        ctx.game.as_mut().unwrap().stage_id = 0;
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).details.as_deref(),
+            Some("In a match")
+        );
+    }
+
+    #[test]
+    fn connect_code_enables_joining() {
+        let mut ctx = PresenceContext::default();
+        assert_eq!(build_activity(&ctx, &Config::default()).secrets, None);
+
+        ctx.connect_code = Some("ABCD#123".to_string());
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.secrets.unwrap().join.as_deref(), Some("slippi-direct:v1:ABCD#123"));
+        assert_eq!(activity.party.unwrap().size, Some([1, 2]));
+    }
+
+    #[test]
+    fn spectating_is_opt_in_and_mid_match_only() {
+        let mut ctx = in_game(None);
+        ctx.spectate_id = Some("ABCD#123-7f3a".to_string());
+        assert_eq!(build_activity(&ctx, &Config::default()).secrets, None);
+
+        let config = Config {
+            enable_spectate: true,
+            ..Default::default()
+        };
+
+        let secrets = build_activity(&ctx, &config).secrets.unwrap();
+        assert_eq!(secrets.spectate.as_deref(), Some("slippi-spectate:v1:ABCD#123-7f3a"));
+        assert_eq!(secrets.join, None);
+
+        ctx.end_game();
+        assert_eq!(build_activity(&ctx, &config).secrets, None);
+    }
+
+    #[test]
+    fn cpu_matches_are_hidden_unless_enabled() {
+        let mut ctx = in_game(None);
+        ctx.stocks = Some((3, 2));
+        ctx.cpu_level = Some(9);
+        assert_eq!(update(&ctx, &Config::default()), None);
+
+        let config = Config {
+            show_cpu_matches: true,
+            ..Default::default()
+        };
+
+        let activity = update(&ctx, &config).unwrap();
+        assert_eq!(activity.details.as_deref(), Some("Practicing vs CPU (Level 9)"));
+        assert_eq!(activity.state.as_deref(), Some("Fox (3) vs Marth (2)"));
+
+        ctx.cpu_level = Some(0);
+        assert_eq!(update(&ctx, &config).unwrap().details.as_deref(), Some("Practicing vs CPU"));
+
+        // Humans are shown as usual either way.
+        ctx.cpu_level = None;
+        assert_eq!(
+            update(&ctx, &Config::default()).unwrap().details.as_deref(),
+            Some("Battling on Final Destination")
+        );
+    }
+
+    #[test]
+    fn hide_opponent_redacts_the_opponent() {
+        let mut ctx = in_game(None);
+        ctx.stocks = Some((3, 2));
+
+        let config = Config {
+            privacy_mode: PrivacyMode::HideOpponent,
+            details_template: "{p1_char} vs {p2_char}".to_string(),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &config);
+        assert_eq!(activity.details.as_deref(), Some("Fox vs an opponent"));
+        assert_eq!(activity.state.as_deref(), Some("Fox (3) vs an opponent (2)"));
+        assert_eq!(activity.assets.unwrap().large_image.as_deref(), Some("fox"));
+    }
+
+    #[test]
+    fn opponents_are_named_unless_hidden() {
+        let mut ctx = in_game(None);
+        ctx.set_opponent("GRNK#123".to_string(), None);
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Fox vs Marth · vs GRNK#123")
+        );
+
+        ctx.set_opponent("GRNK#123".to_string(), Some("Grunk".to_string()));
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Fox vs Marth · vs Grunk")
+        );
+
+        ctx.set_opponent("GRNK#123".to_string(), Some(" ".to_string()));
+        assert_eq!(ctx.opponent.as_deref(), Some("GRNK#123"));
+
+        let config = Config {
+            privacy_mode: PrivacyMode::HideOpponent,
+            ..Default::default()
+        };
+
+        assert_eq!(build_activity(&ctx, &config).state.as_deref(), Some("Fox vs an opponent"));
+
+        ctx.end_game();
+        assert_eq!(ctx.opponent, None);
+    }
+
+    #[test]
+    fn fields_can_be_hidden_individually() {
+        let mut ctx = in_game(Some(480));
+        ctx.stocks = Some((3, 2));
+
+        // Characters, but not the stage or stocks.
+        let config = Config {
+            show_stage: false,
+            show_stocks: false,
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &config);
+        assert_eq!(activity.details.as_deref(), Some("In a match"));
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth"));
+        assert_eq!(activity.assets.unwrap().small_image, None);
+
+        // The stage and the timer, but not who's playing.
+        let config = Config {
+            show_characters: false,
+            show_stocks: false,
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &config);
+        assert_eq!(activity.details.as_deref(), Some("Battling on Final Destination"));
+        assert_eq!(activity.state, None);
+        assert!(activity.timestamps.is_some());
+
+        let assets = activity.assets.unwrap();
+        assert_eq!(assets.large_image, Activity::slippi().assets.unwrap().large_image);
+        assert_eq!(assets.small_text.as_deref(), Some("Final Destination"));
+
+        // Stocks alone still make for a state line.
+        let config = Config {
+            show_stocks: true,
+            ..config
+        };
+
+        assert_eq!(build_activity(&ctx, &config).state.as_deref(), Some("Stocks: 3-2"));
+    }
+
+    #[test]
+    fn hiding_everything_is_minimal() {
+        let config = Config {
+            show_characters: false,
+            show_stage: false,
+            show_stocks: false,
+            show_timer: false,
+            show_mode: false,
+            ..Default::default()
+        };
+
+        let activity = build_activity(&in_game(Some(480)), &config);
+        assert_eq!(activity.details.as_deref(), Some("Playing Slippi"));
+        assert_eq!(activity.state, None);
+        assert_eq!(activity.timestamps, None);
+    }
+
+    #[test]
+    fn minimal_hides_all_match_details() {
+        let config = Config {
+            privacy_mode: PrivacyMode::Minimal,
+            ..Default::default()
+        };
+
+        let activity = build_activity(&in_game(Some(480)), &config);
+        assert_eq!(activity.details.as_deref(), Some("Playing Slippi"));
+        assert_eq!(activity.state, None);
+        assert_eq!(activity.timestamps, None);
+        assert_eq!(activity.assets, Activity::slippi().assets);
+    }
+
+    #[test]
+    fn full_privacy_mode_shows_everything() {
+        let activity = build_activity(&in_game(None), &Config::default());
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth"));
+        assert_eq!(activity.details.as_deref(), Some("Battling on Final Destination"));
+    }
+
+    #[test]
+    fn matchmaking_mode_wording() {
+        let cases = [
+            (MatchmakingMode::Unranked, Some("unranked"), Some("Unranked Match")),
+            (MatchmakingMode::Direct, Some("direct"), Some("Playing with friends")),
+            (MatchmakingMode::Teams, Some("teams"), Some("Teams Match")),
+            (MatchmakingMode::Offline, Some("final_destination"), Some("Final Destination")),
+        ];
+
+        for (mode, image, text) in cases {
+            let mut ctx = in_game(None);
+            ctx.game.as_mut().unwrap().matchmaking = mode;
+
+            let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+            assert_eq!(assets.small_image.as_deref(), image, "{mode:?}");
+            assert_eq!(assets.small_text.as_deref(), text, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn asset_overrides_take_precedence() {
+        let mut ctx = in_game(None);
+        ctx.game.as_mut().unwrap().p1_costume_id = 2;
+
+        let config = Config::builder()
+            .application_id("1234")
+            .asset_override("char:Fox", "my_fox")
+            .asset_override("stage:Final Destination", "my_fd")
+            .asset_override("mode:Ranked", "my_ranked")
+            .build()
+            .unwrap();
+
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.large_image.as_deref(), Some("my_fox"));
+        assert_eq!(assets.small_image.as_deref(), Some("my_fd"));
+        assert_eq!(assets.small_text.as_deref(), Some("Final Destination"));
+
+        // In ranked, the mode icon only stands in until the rank is known.
+        ctx.game.as_mut().unwrap().matchmaking = MatchmakingMode::Ranked;
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("my_ranked"));
+        assert_eq!(assets.small_text.as_deref(), Some("Ranked Match"));
+
+        ctx.rank = Some(Rank {
+            tier: RankTier::Diamond2,
+            rating: 1850.0,
+        });
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("rank_diamond_2"));
+
+        // Everything else keeps its built-in asset.
+        ctx.game.as_mut().unwrap().p1_character_id = 18;
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.large_image.as_deref(), Some("marth_2"));
+    }
+
+    #[test]
+    fn missing_art_falls_back() {
+        let mut ctx = in_game(None);
+        ctx.game.as_mut().unwrap().p1_character_id = 200;
+
+        let config = Config {
+            fallback_large_image: "melee_logo".to_string(),
+            ..Default::default()
+        };
+
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.large_image.as_deref(), Some("melee_logo"));
+        assert_eq!(assets.small_image.as_deref(), Some("final_destination"));
+
+        // The result screen falls back the same way.
+        let result = PresenceContext {
+            last_result: Some((GameResult::Win, ctx.game.clone().unwrap())),
+            ..Default::default()
+        };
+
+        let assets = build_activity(&result, &config).assets.unwrap();
+        assert_eq!(assets.large_image.as_deref(), Some("melee_logo"));
+
+        // Without a fallback (or with an empty override), there's no image rather than an
+        // empty key.
+        let config = Config::builder()
+            .application_id("1234")
+            .fallback_large_image("")
+            .asset_override("stage:Final Destination", "")
+            .build()
+            .unwrap();
+
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.large_image, None);
+        assert_eq!(assets.small_image, None);
+        assert_eq!(assets.small_text.as_deref(), Some("Final Destination"));
+
+        // The Slippi logo is the default fallback.
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.large_image, Activity::slippi().assets.unwrap().large_image);
+    }
+
+    #[test]
+    fn ranked_matches_show_the_rank() {
+        let mut ctx = in_game(None);
+        ctx.game.as_mut().unwrap().matchmaking = MatchmakingMode::Ranked;
+
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.small_image, None);
+        assert_eq!(assets.small_text, None);
+
+        ctx.rank = Some(Rank {
+            tier: RankTier::Diamond2,
+            rating: 1850.0,
+        });
+
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("rank_diamond_2"));
+        assert_eq!(assets.small_text.as_deref(), Some("Diamond 2 — 1850"));
+    }
+
+    #[test]
+    fn condensing_keeps_the_main_part_of_each_line() {
+        let mut ctx = in_game(None);
+        ctx.set_score = Some(SetScore {
+            game: 2,
+            p1_wins: 1,
+            p2_wins: 0,
+        });
+        ctx.session_record = Some((7, 3));
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(
+            activity.details.as_deref(),
+            Some("Battling on Final Destination · Game 2 — 1-0")
+        );
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth · Session: 7W-3L"));
+
+        ctx.condensed = true;
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Battling on Final Destination"));
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth"));
+    }
+
+    #[test]
+    fn the_version_yields_to_the_rank() {
+        let config = Config {
+            version_string: Some("Slippi 3.4.0".to_string()),
+            ..Default::default()
+        };
+
+        // Until the rank is known, the small image is free for the version.
+        let mut ctx = in_game(None);
+        ctx.game.as_mut().unwrap().matchmaking = MatchmakingMode::Ranked;
+
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some(SLIPPI_LOGO_ASSET));
+        assert_eq!(assets.small_text.as_deref(), Some("Slippi 3.4.0"));
+
+        ctx.rank = Some(Rank {
+            tier: RankTier::Diamond2,
+            rating: 1850.0,
+        });
+
+        let assets = build_activity(&ctx, &config).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("rank_diamond_2"));
+        assert_eq!(assets.small_text.as_deref(), Some("Diamond 2 — 1850"));
+
+        // As does the stage, offline.
+        let assets = build_activity(&in_game(None), &config).assets.unwrap();
+        assert_eq!(assets.small_text.as_deref(), Some("Final Destination"));
+
+        let assets = build_activity(&PresenceContext::default(), &config).assets.unwrap();
+        assert_eq!(assets.small_text.as_deref(), Some("Slippi 3.4.0"));
+    }
+
+    fn teams(players: &[(u8, TeamColor)]) -> PresenceContext {
+        let mut ctx = in_game(None);
+        let info = ctx.game.as_mut().unwrap();
+        info.kind = GameKind::Doubles;
+        info.matchmaking = MatchmakingMode::Teams;
+
+        for (port, (character_id, team)) in players.iter().enumerate() {
+            info.players[port] = Some(Player {
+                character_id: *character_id,
+                costume_id: 0,
+                team: Some(*team),
+            });
+        }
+
+        ctx
+    }
+
+    #[test]
+    fn teams_matchups_group_by_color() {
+        // Fox, Marth, Falco, Peach; ports 1 and 3 are on blue, which sorts after red.
+        let ctx = teams(&[
+            (1, TeamColor::Blue),
+            (18, TeamColor::Red),
+            (22, TeamColor::Blue),
+            (9, TeamColor::Red),
+        ]);
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Marth & Peach vs Fox & Falco"));
+        assert_eq!(activity.state.as_deref(), Some("Battling on Final Destination"));
+    }
+
+    #[test]
+    fn teams_matchups_handle_empty_ports() {
+        let ctx = teams(&[(1, TeamColor::Red), (22, TeamColor::Red), (18, TeamColor::Green)]);
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).details.as_deref(),
+            Some("Fox & Falco vs Marth")
+        );
+
+        // With only one team, there's no matchup to show.
+        let ctx = teams(&[(1, TeamColor::Red), (22, TeamColor::Red)]);
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).details.as_deref(),
+            Some("Battling on Final Destination")
+        );
+    }
+
+    #[test]
+    fn teams_matchups_respect_hide_opponent() {
+        let ctx = teams(&[
+            (1, TeamColor::Blue),
+            (18, TeamColor::Red),
+            (22, TeamColor::Blue),
+            (9, TeamColor::Red),
+        ]);
+
+        let config = Config {
+            privacy_mode: PrivacyMode::HideOpponent,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            build_activity(&ctx, &config).details.as_deref(),
+            Some("opponents vs Fox & Falco")
+        );
+    }
+
+    #[test]
+    fn teams_show_the_local_players_color() {
+        let mut ctx = teams(&[
+            (1, TeamColor::Blue),
+            (18, TeamColor::Red),
+            (22, TeamColor::Blue),
+            (9, TeamColor::Red),
+        ]);
+
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("team_blue"));
+        assert_eq!(assets.small_text.as_deref(), Some("Teams Match · Blue Team"));
+
+        ctx.game.as_mut().unwrap().local_port = Some(1);
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("team_red"));
+        assert_eq!(assets.small_text.as_deref(), Some("Teams Match · Red Team"));
+
+        // Without a known team, it's the plain teams icon.
+        ctx.game.as_mut().unwrap().local_port = None;
+        ctx.game.as_mut().unwrap().players[0].as_mut().unwrap().team = None;
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("teams"));
+        assert_eq!(assets.small_text.as_deref(), Some("Teams Match"));
+    }
+
+    #[test]
+    fn stocks_are_shown_in_state() {
+        let mut ctx = in_game(None);
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Fox vs Marth")
+        );
+
+        ctx.stocks = Some((3, 2));
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Fox (3) vs Marth (2)")
+        );
+    }
+
+    #[test]
+    fn percents_are_rounded_into_the_state() {
+        let mut ctx = in_game(None);
+        ctx.percents = Some((86.6, 42.4));
+
+        let with_percents = Config {
+            show_percents: true,
+            ..Default::default()
+        };
+
+        let state = |ctx: &PresenceContext, config: &Config| build_activity(ctx, config).state;
+        assert_eq!(state(&ctx, &Config::default()).as_deref(), Some("Fox vs Marth"));
+        assert_eq!(state(&ctx, &with_percents).as_deref(), Some("Fox 87% vs Marth 42%"));
+
+        ctx.stocks = Some((1, 1));
+        assert_eq!(state(&ctx, &with_percents).as_deref(), Some("Fox (1) 87% vs Marth (1) 42%"));
+
+        let only_percents = Config {
+            show_characters: false,
+            show_stocks: false,
+            ..with_percents
+        };
+        assert_eq!(state(&ctx, &only_percents).as_deref(), Some("Damage: 87%-42%"));
+
+        ctx.percents = Some((-1.0, 999.5));
+        assert_eq!(state(&ctx, &only_percents).as_deref(), Some("Damage: 0%-1000%"));
+    }
+
+    #[test]
+    fn percents_make_way_for_stocks_when_space_is_short() {
+        let long = "x".repeat(MAX_FIELD_CHARS / 2 - 6);
+
+        let state = matchup_state(Some((&long, &long)), Some((4, 3)), Some((100.0, 20.0))).unwrap();
+        assert_eq!(state, format!("{long} (4) vs {long} (3)"));
+        assert!(state.chars().count() <= MAX_FIELD_CHARS);
+
+        // Both fit with a little less to say.
+        let short = &long[..long.len() - 6];
+        let state = matchup_state(Some((short, short)), Some((4, 3)), Some((100.0, 20.0))).unwrap();
+        assert_eq!(state, format!("{short} (4) 100% vs {short} (3) 20%"));
+        assert!(state.chars().count() <= MAX_FIELD_CHARS);
+    }
+
+    #[test]
+    fn set_scores_are_shown_in_details() {
+        let mut ctx = in_game(None);
+        ctx.set_score = Some(SetScore {
+            game: 2,
+            p1_wins: 1,
+            p2_wins: 0,
+        });
+
+        let details = |ctx: &PresenceContext, config: &Config| build_activity(ctx, config).details;
+        assert_eq!(
+            details(&ctx, &Config::default()).as_deref(),
+            Some("Battling on Final Destination · Game 2 — 1-0")
+        );
+
+        let config = Config {
+            privacy_mode: PrivacyMode::HideOpponent,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            details(&ctx, &config).as_deref(),
+            Some("Battling on Final Destination · Game 2")
+        );
+
+        // The score is p1's, so it flips along with everything else.
+        ctx.game.as_mut().unwrap().local_port = Some(1);
+        assert_eq!(
+            details(&ctx, &Config::default()).as_deref(),
+            Some("Battling on Final Destination · Game 2 — 0-1")
+        );
+    }
+
+    #[test]
+    fn ingame_time_is_shown_when_enabled() {
+        let mut ctx = in_game(None);
+        let config = Config {
+            show_ingame_time: true,
+            ..Default::default()
+        };
+
+        assert_eq!(build_activity(&ctx, &config).state.as_deref(), Some("Fox vs Marth"));
+
+        ctx.set_frame(155 * 60 + 30);
+        assert_eq!(build_activity(&ctx, &config).state.as_deref(), Some("Fox vs Marth · 2:35"));
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Fox vs Marth")
+        );
+    }
+
+    #[test]
+    fn frames_before_go_are_clamped() {
+        let mut ctx = in_game(None);
+
+        assert!(ctx.set_frame(-123));
+        assert_eq!(ctx.frame, Some(0));
+        assert!(!ctx.set_frame(59));
+        assert!(ctx.set_frame(60));
+
+        ctx.end_game();
+        assert_eq!(ctx.frame, None);
+    }
+
+    #[test]
+    fn sources_are_ordered_by_priority() {
+        let mut ctx = in_game(None);
+        ctx.lobby = Some((1, 2));
+        ctx.matchmaking = Some(Matchmaking::Found(MatchmakingMode::Ranked));
+        ctx.playback = ctx.game.clone();
+        ctx.app_state = AppState::Updating;
+
+        let info = ctx.game.as_ref().unwrap();
+        assert_eq!(
+            ctx.sources(),
+            vec![
+                PresenceSource::Match(info),
+                PresenceSource::Matchmaking(Matchmaking::Found(MatchmakingMode::Ranked)),
+                PresenceSource::Lobby {
+                    current: 1,
+                    max: 2,
+                    role: LobbyRole::Participant
+                },
+                PresenceSource::Playback(info),
+                PresenceSource::App(AppState::Updating),
+                PresenceSource::Menus,
+            ]
+        );
+
+        ctx.end_game();
+        ctx.matchmaking = None;
+        assert_eq!(
+            ctx.source(),
+            PresenceSource::Lobby {
+                current: 1,
+                max: 2,
+                role: LobbyRole::Participant
+            }
+        );
+        assert_eq!(PresenceContext::default().source(), PresenceSource::Menus);
+    }
+
+    #[test]
+    fn session_records_follow_the_state() {
+        let mut ctx = PresenceContext {
+            session_record: Some((7, 3)),
+            ..Default::default()
+        };
+
+        assert_eq!(session_record((1, 0)), "Session: 1W-0L");
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Session: 7W-3L")
+        );
+
+        ctx.game = in_game(None).game;
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Fox vs Marth · Session: 7W-3L")
+        );
+
+        let minimal = Config {
+            privacy_mode: PrivacyMode::Minimal,
+            ..Default::default()
+        };
+        assert_eq!(build_activity(&ctx, &minimal).state, None);
+    }
+
+    #[test]
+    fn rematches_are_awaited_after_the_result() {
+        let mut ctx = PresenceContext {
+            awaiting_rematch_since: Some(1000),
+            lobby: Some((2, 2)),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Waiting for rematch"));
+        assert_eq!(activity.timestamps.unwrap().start, Some(1000));
+
+        // The result of the last game of the set is shown first.
+        ctx.last_result = Some((GameResult::Win, in_game(None).game.unwrap()));
+        assert!(matches!(ctx.source(), PresenceSource::Result(..)));
+
+        ctx.last_result = None;
+        let config = Config {
+            show_timer: false,
+            ..Default::default()
+        };
+        assert_eq!(build_activity(&ctx, &config).timestamps, None);
+    }
+
+    #[test]
+    fn searching_shows_the_queue_and_elapsed_time() {
+        let mut ctx = PresenceContext {
+            matchmaking: Some(Matchmaking::Searching {
+                mode: MatchmakingMode::Unranked,
+                since: 1000,
+            }),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Searching for an Unranked match..."));
+        assert_eq!(activity.timestamps.unwrap().start, Some(1000));
+
+        let assets = activity.assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some("searching"));
+        assert_eq!(assets.small_text.as_deref(), Some("Unranked Match"));
+
+        ctx.matchmaking = Some(Matchmaking::Found(MatchmakingMode::Unranked));
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Match found!"));
+        assert_eq!(activity.timestamps, None);
+    }
+
+    #[test]
+    fn results_name_the_local_character() {
+        let ctx = PresenceContext {
+            last_result: Some((GameResult::Win, in_game(None).game.unwrap())),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Won as Fox!"));
+        assert_eq!(activity.assets.unwrap().large_image.as_deref(), Some("fox"));
+
+        let config = Config {
+            primary_port: Some(1),
+            ..Default::default()
+        };
+
+        let mut ctx = ctx;
+        ctx.last_result.as_mut().unwrap().0 = GameResult::Loss;
+        assert_eq!(build_activity(&ctx, &config).details.as_deref(), Some("Lost as Marth"));
+
+        let config = Config {
+            privacy_mode: PrivacyMode::Minimal,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            build_activity(&ctx, &config),
+            build_activity(&PresenceContext::default(), &config)
+        );
+    }
+
+    #[test]
+    fn reports_fill_in_the_result() {
+        let mut ctx = PresenceContext {
+            last_result: Some((GameResult::Win, in_game(None).game.unwrap())),
+            last_report: Some(GameReport {
+                duration_frames: 222 * 60,
+                winner_port: Some(0),
+                stocks_remaining: [2, 0, 0, 0],
+                damage_dealt: [187.4, 96.0, 0.0, 0.0],
+            }),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Won as Fox — 2 stocks left"));
+        assert_eq!(activity.state.as_deref(), Some("3:42 · 187% dealt"));
+
+        ctx.last_report.as_mut().unwrap().stocks_remaining[0] = 1;
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Won as Fox — 1 stock left"));
+
+        // The report's winner is the one that counts, and everything's from the local player's
+        // side.
+        let config = Config {
+            primary_port: Some(1),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &config);
+        assert_eq!(activity.details.as_deref(), Some("Lost as Marth"));
+        assert_eq!(activity.state.as_deref(), Some("3:42 · 96% dealt"));
+
+        let config = Config {
+            show_stocks: false,
+            ..Default::default()
+        };
+
+        assert_eq!(build_activity(&ctx, &config).details.as_deref(), Some("Won as Fox!"));
+    }
+
+    #[test]
+    fn app_state_only_shows_when_idle() {
+        let mut ctx = PresenceContext {
+            app_state: AppState::Updating,
+            ..Default::default()
+        };
+
+        let config = Config {
+            show_idle_presence: false,
+            ..Default::default()
+        };
+
+        let activity = update(&ctx, &config).unwrap();
+        assert_eq!(activity.details.as_deref(), Some("Updating Slippi..."));
+
+        ctx.game = in_game(None).game;
+        assert_eq!(
+            build_activity(&ctx, &config).details.as_deref(),
+            Some("Battling on Final Destination")
+        );
+
+        ctx.end_game();
+        ctx.app_state = AppState::Idle;
+        assert_eq!(update(&ctx, &config), None);
+    }
+
+    #[test]
+    fn lobby_size_is_shown_until_the_match_starts() {
+        let mut ctx = PresenceContext {
+            lobby: Some((1, 2)),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("In a lobby"));
+        assert_eq!(
+            activity.party,
+            Some(Party {
+                id: None,
+                size: Some([1, 2])
+            })
+        );
+
+        ctx.lobby = None;
+        ctx.game = in_game(None).game;
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Battling on Final Destination"));
+        assert_eq!(activity.party, None);
+    }
+
+    #[test]
+    fn spectators_are_shown_differently_in_lobbies() {
+        let mut ctx = PresenceContext {
+            lobby: Some((3, 4)),
+            ..Default::default()
+        };
+
+        let participant = build_activity(&ctx, &Config::default());
+        assert_eq!(participant.details.as_deref(), Some("In a lobby"));
+        assert_eq!(participant.assets, Activity::slippi().assets);
+
+        ctx.lobby_role = LobbyRole::Spectator;
+        let spectator = build_activity(&ctx, &Config::default());
+        assert_eq!(spectator.details.as_deref(), Some("Spectating a lobby"));
+
+        let assets = spectator.assets.unwrap();
+        assert_eq!(assets.large_image, Activity::slippi().assets.unwrap().large_image);
+        assert_eq!(assets.small_image.as_deref(), Some(SPECTATING_ASSET));
+
+        // Either way, the party is the lobby.
+        assert_eq!(participant.party, spectator.party);
+        assert_eq!(spectator.party.unwrap().size, Some([3, 4]));
+    }
+
+    #[test]
+    fn buttons_are_shown_without_secrets() {
+        let mut ctx = PresenceContext::default();
+        let mut config = Config::default();
+        assert_eq!(build_activity(&ctx, &config).buttons, None);
+
+        config.profile_url = Some("https://slippi.gg/user/abcd-123".to_string());
+        assert_eq!(
+            build_activity(&ctx, &config).buttons,
+            Some(vec![Button::new("View Profile", "https://slippi.gg/user/abcd-123")])
+        );
+
+        config.extra_button = Some(("Stream".to_string(), "https://twitch.tv/slippi".to_string()));
+        assert_eq!(build_activity(&ctx, &config).buttons.unwrap().len(), 2);
+
+        ctx.connect_code = Some("ABCD#123".to_string());
+        assert_eq!(build_activity(&ctx, &config).buttons, None);
+    }
+
+    #[test]
+    fn ranked_matches_are_competitive() {
+        let mut ctx = in_game(None);
+        let modes = [
+            (MatchmakingMode::Ranked, ActivityType::Competing),
+            (MatchmakingMode::Unranked, ActivityType::Playing),
+            (MatchmakingMode::Direct, ActivityType::Playing),
+            (MatchmakingMode::Teams, ActivityType::Playing),
+            (MatchmakingMode::Offline, ActivityType::Playing),
+        ];
+
+        for (mode, kind) in modes {
+            ctx.game.as_mut().unwrap().matchmaking = mode;
+            assert_eq!(build_activity(&ctx, &Config::default()).kind, Some(kind), "{mode:?}");
+        }
+
+        assert_eq!(
+            build_activity(&PresenceContext::default(), &Config::default()).kind,
+            Some(ActivityType::Playing)
+        );
+
+        let config = Config {
+            force_activity_type: Some(ActivityType::Playing),
+            ..Default::default()
+        };
+
+        assert_eq!(build_activity(&ctx, &config).kind, Some(ActivityType::Playing));
+        ctx.game.as_mut().unwrap().matchmaking = MatchmakingMode::Unranked;
+
+        let config = Config {
+            force_activity_type: Some(ActivityType::Competing),
+            ..Default::default()
+        };
+
+        assert_eq!(build_activity(&ctx, &config).kind, Some(ActivityType::Competing));
+    }
+
+    #[test]
+    fn stream_safe_removes_all_identifiers() {
+        let mut ctx = in_game(Some(480));
+        ctx.game.as_mut().unwrap().matchmaking = MatchmakingMode::Ranked;
+        ctx.connect_code = Some("ABCD#123".to_string());
+        ctx.spectate_id = Some("ABCD#123-7f3a".to_string());
+        ctx.set_opponent("GRNK#123".to_string(), Some("Grunk".to_string()));
+        ctx.rank = Some(Rank {
+            tier: RankTier::Gold2,
+            rating: 1650.0,
+        });
+
+        let config = Config {
+            stream_safe: true,
+            privacy_mode: PrivacyMode::Full,
+            enable_spectate: true,
+            profile_url: Some("https://slippi.gg/user/abcd-123".to_string()),
+            extra_button: Some(("Stream".to_string(), "https://twitch.tv/abcd".to_string())),
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &config);
+        assert_eq!(activity.details.as_deref(), Some("Playing Singles"));
+        assert_eq!(activity.state.as_deref(), Some("Ranked Match"));
+        assert_eq!(activity.assets, Activity::slippi().assets);
+        assert_eq!(activity.party, None);
+        assert_eq!(activity.secrets, None);
+        assert_eq!(activity.buttons, None);
+        assert!(activity.timestamps.is_some());
+
+        ctx.end_game();
+        ctx.last_result = Some((GameResult::Win, in_game(None).game.unwrap()));
+        let activity = build_activity(&ctx, &config);
+        assert_eq!(activity.details.as_deref(), Some("Finished a match"));
+        assert_eq!(activity.assets, Activity::slippi().assets);
+
+        // Nothing's held back in favour of secrets here, so any button would have shown.
+        ctx.connect_code = None;
+        assert_eq!(build_activity(&ctx, &config).buttons, None);
+    }
+
+    #[test]
+    fn large_image_follows_the_local_players_costume() {
+        let mut ctx = in_game(None);
+        let info = ctx.game.as_mut().unwrap();
+        info.p1_costume_id = 2;
+        info.p2_costume_id = 3;
+
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.large_image.as_deref(), Some("fox_2"));
+
+        ctx.game.as_mut().unwrap().p1_costume_id = 9;
+        let assets = build_activity(&ctx, &Config::default()).assets.unwrap();
+        assert_eq!(assets.large_image.as_deref(), Some("fox"));
+    }
+
+    #[test]
+    fn perspective_follows_the_primary_port() {
+        let mut ctx = teams(&[
+            (1, TeamColor::Red),
+            (18, TeamColor::Blue),
+            (22, TeamColor::Red),
+            (9, TeamColor::Blue),
+        ]);
+
+        let info = ctx.game.as_mut().unwrap();
+        info.kind = GameKind::Singles;
+        info.matchmaking = MatchmakingMode::Offline;
+
+        let mut config = Config::default();
+        let large_image = |ctx: &PresenceContext, config: &Config| build_activity(ctx, config).assets.unwrap().large_image;
+        assert_eq!(large_image(&ctx, &config).as_deref(), Some("fox"));
+
+        // The online user's port is used if nothing's configured...
+        ctx.game.as_mut().unwrap().local_port = Some(1);
+        assert_eq!(large_image(&ctx, &config).as_deref(), Some("marth"));
+        assert_eq!(build_activity(&ctx, &config).state.as_deref(), Some("Marth vs Fox"));
+
+        // ...but the configured port wins.
+        config.primary_port = Some(2);
+        assert_eq!(large_image(&ctx, &config).as_deref(), Some("falco"));
+        assert_eq!(build_activity(&ctx, &config).state.as_deref(), Some("Falco vs Fox"));
+
+        config.primary_port = Some(3);
+        ctx.game.as_mut().unwrap().matchmaking = MatchmakingMode::Teams;
+        config.privacy_mode = PrivacyMode::HideOpponent;
+        assert_eq!(large_image(&ctx, &config).as_deref(), Some("peach"));
+        assert_eq!(
+            build_activity(&ctx, &config).details.as_deref(),
+            Some("opponents vs Peach & Marth")
+        );
+    }
+
+    #[test]
+    fn stocks_follow_the_perspective() {
+        let mut ctx = in_game(None);
+        ctx.stocks = Some((4, 2));
+
+        let swapped = ctx.seen_from(1);
+        assert_eq!(swapped.stocks, Some((2, 4)));
+        assert_eq!(swapped.game.as_ref().unwrap().p1_character_id, 18);
+        assert_eq!(ctx.seen_from(0), ctx);
+    }
+
+    #[test]
+    fn names_follow_the_locale() {
+        let ctx = in_game(None);
+        let config = Config {
+            locale: "ja".to_string(),
+            ..Default::default()
+        };
+
+        // The template itself is the user's to translate.
+        let activity = build_activity(&ctx, &config);
+        assert_eq!(activity.state.as_deref(), Some("フォックス vs マルス"));
+        assert_eq!(activity.details.as_deref(), Some("Battling on 終点"));
+        assert_eq!(activity.assets.unwrap().large_text.as_deref(), Some("フォックス"));
+
+        let config = Config {
+            locale: "tlh".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(build_activity(&ctx, &config).state.as_deref(), Some("Fox vs Marth"));
+    }
+
+    #[test]
+    fn replays_show_the_matchup() {
+        let ctx = PresenceContext {
+            playback: in_game(None).game,
+            ..Default::default()
+        };
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Watching a replay"));
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth"));
+        assert_eq!(activity.timestamps, None);
+
+        let assets = activity.assets.unwrap();
+        assert_eq!(assets.small_image.as_deref(), Some(REPLAY_ASSET));
+        assert_eq!(assets.small_text.as_deref(), Some("Spectating"));
+
+        // Replays count as something to show, even without an idle presence.
+        let config = Config {
+            show_idle_presence: false,
+            privacy_mode: PrivacyMode::HideOpponent,
+            ..Default::default()
+        };
+
+        let activity = update(&ctx, &config).unwrap();
+        assert_eq!(activity.details.as_deref(), Some("Watching a replay"));
+        assert_eq!(activity.state, None);
+    }
+
+    #[test]
+    fn live_matches_take_priority_over_replays() {
+        let mut ctx = in_game(None);
+        ctx.playback = Some(GameInfo {
+            p1_character_id: 22,
+            p2_character_id: 9,
+            ..ctx.game.clone().unwrap()
+        });
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.details.as_deref(), Some("Battling on Final Destination"));
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth"));
+    }
+
+    #[test]
+    fn highlights_replace_the_state() {
+        let mut ctx = in_game(None);
+        ctx.highlight = Some(GameEvent::BigCombo { port: 0, damage: 61.6 });
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("🔥 Fox landed a 62% combo!")
+        );
+
+        let config = Config {
+            privacy_mode: PrivacyMode::HideOpponent,
+            ..Default::default()
+        };
+
+        ctx.highlight = Some(GameEvent::Kill { killer: 1 });
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("💥 Marth took a stock!")
+        );
+        assert_eq!(
+            build_activity(&ctx, &config).state.as_deref(),
+            Some("💥 an opponent took a stock!")
+        );
+
+        // Nobody is in port 4, so there's nothing to call out.
+        ctx.highlight = Some(GameEvent::Kill { killer: 3 });
+        assert_eq!(
+            build_activity(&ctx, &Config::default()).state.as_deref(),
+            Some("Fox vs Marth")
+        );
+    }
+
+    #[test]
+    fn pausing_freezes_the_timer() {
+        let mut ctx = in_game(Some(480));
+
+        assert!(ctx.set_paused(true, 5_000));
+        assert!(!ctx.set_paused(true, 6_000));
+
+        let activity = build_activity(&ctx, &Config::default());
+        assert_eq!(activity.state.as_deref(), Some("Fox vs Marth (Paused)"));
+        assert_eq!(activity.timestamps, None);
+
+        // Three seconds were spent paused, so the timer resumes three seconds later.
+        assert!(ctx.set_paused(false, 8_000));
+        assert!(!ctx.set_paused(false, 9_000));
+
+        let timestamps = build_activity(&ctx, &Config::default()).timestamps.unwrap();
+        assert_eq!(timestamps.start, Some(4_000));
+        assert_eq!(timestamps.end, Some(484_000));
+
+        // There's nothing to pause outside of a match.
+        assert!(!PresenceContext::default().set_paused(true, 5_000));
+    }
+
+    #[test]
+    fn paused_time_adds_up() {
+        let mut ctx = in_game(None);
+        let start = |ctx: &PresenceContext| build_activity(ctx, &Config::default()).timestamps.unwrap().start.unwrap();
+
+        ctx.set_paused(true, 30_000);
+        ctx.set_paused(false, 40_000);
+        assert_eq!(start(&ctx), 11_000);
+
+        // Each pause pushes the start back by however long it lasted, on top of the last.
+        ctx.set_paused(true, 90_000);
+        ctx.set_paused(false, 92_500);
+        assert_eq!(start(&ctx), 13_500);
+    }
+}
@@ -0,0 +1,131 @@
+//! Slippi ranked tiers, and how they map to Discord assets.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A Slippi ranked tier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RankTier {
+    Bronze1,
+    Bronze2,
+    Bronze3,
+    Silver1,
+    Silver2,
+    Silver3,
+    Gold1,
+    Gold2,
+    Gold3,
+    Platinum1,
+    Platinum2,
+    Platinum3,
+    Diamond1,
+    Diamond2,
+    Diamond3,
+    Master1,
+    Master2,
+    Master3,
+    Grandmaster,
+}
+
+impl RankTier {
+    /// Every tier, from lowest to highest.
+    pub const ALL: [RankTier; 19] = [
+        Self::Bronze1,
+        Self::Bronze2,
+        Self::Bronze3,
+        Self::Silver1,
+        Self::Silver2,
+        Self::Silver3,
+        Self::Gold1,
+        Self::Gold2,
+        Self::Gold3,
+        Self::Platinum1,
+        Self::Platinum2,
+        Self::Platinum3,
+        Self::Diamond1,
+        Self::Diamond2,
+        Self::Diamond3,
+        Self::Master1,
+        Self::Master2,
+        Self::Master3,
+        Self::Grandmaster,
+    ];
+
+    /// The display name, e.g "Diamond 2".
+    pub fn name(self) -> &'static str {
+        self.names().0
+    }
+
+    /// The Discord asset key for this tier's icon.
+    pub fn asset_key(self) -> &'static str {
+        self.names().1
+    }
+
+    /// The inverse of `asset_key`.
+    pub fn from_asset_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|tier| tier.asset_key() == key)
+    }
+
+    fn names(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Bronze1 => ("Bronze 1", "rank_bronze_1"),
+            Self::Bronze2 => ("Bronze 2", "rank_bronze_2"),
+            Self::Bronze3 => ("Bronze 3", "rank_bronze_3"),
+            Self::Silver1 => ("Silver 1", "rank_silver_1"),
+            Self::Silver2 => ("Silver 2", "rank_silver_2"),
+            Self::Silver3 => ("Silver 3", "rank_silver_3"),
+            Self::Gold1 => ("Gold 1", "rank_gold_1"),
+            Self::Gold2 => ("Gold 2", "rank_gold_2"),
+            Self::Gold3 => ("Gold 3", "rank_gold_3"),
+            Self::Platinum1 => ("Platinum 1", "rank_platinum_1"),
+            Self::Platinum2 => ("Platinum 2", "rank_platinum_2"),
+            Self::Platinum3 => ("Platinum 3", "rank_platinum_3"),
+            Self::Diamond1 => ("Diamond 1", "rank_diamond_1"),
+            Self::Diamond2 => ("Diamond 2", "rank_diamond_2"),
+            Self::Diamond3 => ("Diamond 3", "rank_diamond_3"),
+            Self::Master1 => ("Master 1", "rank_master_1"),
+            Self::Master2 => ("Master 2", "rank_master_2"),
+            Self::Master3 => ("Master 3", "rank_master_3"),
+            Self::Grandmaster => ("Grandmaster", "rank_grandmaster"),
+        }
+    }
+}
+
+/// The local player's current rank.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rank {
+    pub tier: RankTier,
+    pub rating: f32,
+}
+
+impl fmt::Display for Rank {
+    /// Formats as e.g "Diamond 2 — 1850".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} — {:.0}", self.tier.name(), self.rating)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_keys_roundtrip() {
+        for tier in RankTier::ALL {
+            assert_eq!(RankTier::from_asset_key(tier.asset_key()), Some(tier));
+        }
+
+        assert_eq!(RankTier::from_asset_key("rank_wood"), None);
+    }
+
+    #[test]
+    fn rank_display() {
+        let rank = Rank {
+            tier: RankTier::Diamond2,
+            rating: 1850.4,
+        };
+
+        assert_eq!(rank.to_string(), "Diamond 2 — 1850");
+    }
+}
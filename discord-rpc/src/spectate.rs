@@ -0,0 +1,67 @@
+//! Discord "Spectate" support.
+//!
+//! This mirrors joining (see `join`): while the local player is in a match that can be
+//! spectated, its spectate id is embedded in the activity's spectate secret, which makes
+//! Discord show a "Spectate" button. When a friend clicks it, their client receives the secret
+//! as an `ACTIVITY_SPECTATE` event, and we decode it to find what to spectate.
+//!
+//! Secrets are encoded as `slippi-spectate:v1:<spectate id>`.
+
+/// The prefix for spectate secrets we produce.
+const SPECTATE_SECRET_PREFIX: &str = "slippi-spectate:v1:";
+
+/// The longest spectate id we'll accept. Discord caps secrets at 128 bytes.
+const MAX_SPECTATE_ID_LEN: usize = 128 - SPECTATE_SECRET_PREFIX.len();
+
+/// A request (from Discord) to spectate another player's match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpectateRequest {
+    pub spectate_id: String,
+}
+
+/// Whether `id` is something we're willing to put in (or take out of) a spectate secret.
+pub(crate) fn is_valid_spectate_id(id: &str) -> bool {
+    (1..=MAX_SPECTATE_ID_LEN).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '#'))
+}
+
+/// Encodes a spectate id into a spectate secret.
+pub(crate) fn encode_spectate_secret(spectate_id: &str) -> String {
+    format!("{SPECTATE_SECRET_PREFIX}{spectate_id}")
+}
+
+/// Decodes a spectate secret, returning `None` if it isn't one of ours.
+pub(crate) fn decode_spectate_secret(secret: &str) -> Option<SpectateRequest> {
+    let spectate_id = secret.strip_prefix(SPECTATE_SECRET_PREFIX)?;
+
+    match is_valid_spectate_id(spectate_id) {
+        true => Some(SpectateRequest {
+            spectate_id: spectate_id.to_string(),
+        }),
+
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spectate_secret_roundtrip() {
+        let secret = encode_spectate_secret("ABCD#123-7f3a");
+        assert_eq!(secret, "slippi-spectate:v1:ABCD#123-7f3a");
+
+        let request = decode_spectate_secret(&secret).unwrap();
+        assert_eq!(request.spectate_id, "ABCD#123-7f3a");
+    }
+
+    #[test]
+    fn rejects_foreign_or_malformed_secrets() {
+        assert_eq!(decode_spectate_secret("slippi-direct:v1:ABCD#123"), None);
+        assert_eq!(decode_spectate_secret("slippi-spectate:v1:"), None);
+        assert_eq!(decode_spectate_secret("slippi-spectate:v1:has spaces"), None);
+
+        let too_long = encode_spectate_secret(&"a".repeat(MAX_SPECTATE_ID_LEN + 1));
+        assert_eq!(decode_spectate_secret(&too_long), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! Maps Melee stage ids to display names and Discord asset keys.
+//!
+//! Only stages that can actually be played on in versus mode are covered; anything else
+//! (e.g, single player stages) resolves to `None`.
+
+/// Returns the display name for a stage id, if it's a known versus stage.
+pub fn stage_name(id: u16) -> Option<&'static str> {
+    let name = match id {
+        2 => "Fountain of Dreams",
+        3 => "Pokémon Stadium",
+        4 => "Princess Peach's Castle",
+        5 => "Kongo Jungle",
+        6 => "Brinstar",
+        7 => "Corneria",
+        8 => "Yoshi's Story",
+        9 => "Onett",
+        10 => "Mute City",
+        11 => "Rainbow Cruise",
+        12 => "Jungle Japes",
+        13 => "Great Bay",
+        14 => "Hyrule Temple",
+        15 => "Brinstar Depths",
+        16 => "Yoshi's Island",
+        17 => "Green Greens",
+        18 => "Fourside",
+        19 => "Mushroom Kingdom",
+        20 => "Mushroom Kingdom II",
+        22 => "Venom",
+        23 => "Poké Floats",
+        24 => "Big Blue",
+        25 => "Icicle Mountain",
+        27 => "Flat Zone",
+        28 => "Dream Land",
+        29 => "Yoshi's Island (N64)",
+        30 => "Kongo Jungle (N64)",
+        31 => "Battlefield",
+        32 => "Final Destination",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Returns the Discord asset key for a stage id, if it's a known versus stage.
+pub fn stage_asset_key(id: u16) -> Option<&'static str> {
+    let key = match id {
+        2 => "fountain_of_dreams",
+        3 => "pokemon_stadium",
+        4 => "princess_peachs_castle",
+        5 => "kongo_jungle",
+        6 => "brinstar",
+        7 => "corneria",
+        8 => "yoshis_story",
+        9 => "onett",
+        10 => "mute_city",
+        11 => "rainbow_cruise",
+        12 => "jungle_japes",
+        13 => "great_bay",
+        14 => "hyrule_temple",
+        15 => "brinstar_depths",
+        16 => "yoshis_island",
+        17 => "green_greens",
+        18 => "fourside",
+        19 => "mushroom_kingdom",
+        20 => "mushroom_kingdom_ii",
+        22 => "venom",
+        23 => "poke_floats",
+        24 => "big_blue",
+        25 => "icicle_mountain",
+        27 => "flat_zone",
+        28 => "dream_land",
+        29 => "yoshis_island_n64",
+        30 => "kongo_jungle_n64",
+        31 => "battlefield",
+        32 => "final_destination",
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Returns the abbreviation players use for a stage id, if it's a tournament legal stage.
+pub fn stage_abbreviation(id: u16) -> Option<&'static str> {
+    let abbreviation = match id {
+        2 => "FoD",
+        3 => "PS",
+        8 => "YS",
+        28 => "DL",
+        31 => "BF",
+        32 => "FD",
+        _ => return None,
+    };
+
+    Some(abbreviation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legal_stages_resolve() {
+        let legal = [
+            (2, "Fountain of Dreams", "fountain_of_dreams", "FoD"),
+            (3, "Pokémon Stadium", "pokemon_stadium", "PS"),
+            (8, "Yoshi's Story", "yoshis_story", "YS"),
+            (28, "Dream Land", "dream_land", "DL"),
+            (31, "Battlefield", "battlefield", "BF"),
+            (32, "Final Destination", "final_destination", "FD"),
+        ];
+
+        for (id, name, key, abbreviation) in legal {
+            assert_eq!(stage_name(id), Some(name));
+            assert_eq!(stage_asset_key(id), Some(key));
+            assert_eq!(stage_abbreviation(id), Some(abbreviation));
+        }
+    }
+
+    #[test]
+    fn unknown_stages_are_none() {
+        assert_eq!(stage_name(0), None);
+        assert_eq!(stage_asset_key(400), None);
+        assert_eq!(stage_abbreviation(7), None);
+    }
+}
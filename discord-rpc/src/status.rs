@@ -0,0 +1,255 @@
+//! Connection state that's shared between the background thread and the handler.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "async")]
+use std::sync::mpsc::TryRecvError;
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+use crate::subscribers::Subscribers;
+use crate::{DiscordRPCError, ErrorCategory};
+
+/// The state of the connection to the Discord client, as seen from outside the background
+/// thread. Callers can use this to e.g show a hint when Discord isn't running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// We haven't finished the first connection attempt yet.
+    Connecting,
+
+    /// We hold a live connection.
+    Connected,
+
+    /// Discord doesn't appear to be running. We'll keep trying in the background.
+    Unavailable,
+
+    /// We were connected, but lost the connection mid-session. We'll keep trying to reconnect.
+    Disconnected,
+}
+
+/// A connection transition, as broadcast to `DiscordHandler::subscribe_state` subscribers.
+///
+/// This is coarser than `ConnectionStatus` in that Discord not running is just `Disconnected`,
+/// but also reports when a reconnect is being attempted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+    Reconnecting,
+
+    /// Reconnecting failed `Config::max_reconnect_attempts` times in a row, so we've stopped
+    /// trying (until a new config is applied, or we're asked to reconnect).
+    GaveUp,
+
+    /// Discord support was compiled out (see the `discord` feature), so there's nothing to
+    /// connect to. The stub handler reports this, and nothing else.
+    Unavailable,
+}
+
+impl From<ConnectionStatus> for ConnectionState {
+    fn from(status: ConnectionStatus) -> Self {
+        match status {
+            ConnectionStatus::Connecting => Self::Connecting,
+            ConnectionStatus::Connected => Self::Connected,
+            ConnectionStatus::Unavailable | ConnectionStatus::Disconnected => Self::Disconnected,
+        }
+    }
+}
+
+/// A `DiscordRPCError` the background thread ran into, kept around for display. The error
+/// itself can't be cloned out to callers, so this holds onto its message instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RecordedError {
+    pub category: ErrorCategory,
+    pub message: String,
+}
+
+/// The latest `ConnectionState`, along with the tasks waiting on the next one.
+#[derive(Debug)]
+struct Transitions {
+    current: ConnectionState,
+    wakers: Vec<Waker>,
+    /// Set once the handler is gone, after which there won't be any more transitions.
+    closed: bool,
+}
+
+impl Transitions {
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// A `ConnectionStatus` that can be shared across threads, which also broadcasts each
+/// `ConnectionState` transition to subscribers.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedStatus {
+    status: Arc<AtomicU8>,
+    state: Arc<Mutex<Transitions>>,
+    subscribers: Subscribers<ConnectionState>,
+    last_error: Arc<Mutex<Option<RecordedError>>>,
+}
+
+impl SharedStatus {
+    pub fn new() -> Self {
+        Self {
+            status: Arc::new(AtomicU8::new(ConnectionStatus::Connecting as u8)),
+            state: Arc::new(Mutex::new(Transitions {
+                current: ConnectionState::Connecting,
+                wakers: Vec::new(),
+                closed: false,
+            })),
+            subscribers: Subscribers::new(),
+            last_error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the most recently recorded error, unless it's since been cleared.
+    pub fn last_error(&self) -> Option<RecordedError> {
+        self.last_error.lock().expect("Unable to acquire last error lock").clone()
+    }
+
+    /// Records `error` as the most recent one.
+    pub fn record_error(&self, error: &DiscordRPCError) {
+        *self.last_error.lock().expect("Unable to acquire last error lock") = Some(RecordedError {
+            category: error.category(),
+            message: error.to_string(),
+        });
+    }
+
+    /// Forgets the most recent error, e.g once whatever caused it has been recovered from.
+    pub fn clear_error(&self) {
+        *self.last_error.lock().expect("Unable to acquire last error lock") = None;
+    }
+
+    pub fn get(&self) -> ConnectionStatus {
+        match self.status.load(Ordering::Relaxed) {
+            1 => ConnectionStatus::Connected,
+            2 => ConnectionStatus::Unavailable,
+            3 => ConnectionStatus::Disconnected,
+            _ => ConnectionStatus::Connecting,
+        }
+    }
+
+    /// Returns a receiver for state transitions, starting with the current state.
+    pub fn subscribe(&self) -> Receiver<ConnectionState> {
+        // Holding the lock keeps a concurrent transition from slipping in ahead of the
+        // current state.
+        let state = self.state.lock().expect("Unable to acquire connection state lock");
+        self.subscribers.subscribe_with(state.current)
+    }
+
+    /// Polls `rx` (from `subscribe`) for the next transition, registering `cx` to be woken when
+    /// there is one. This is `Ready(None)` once the handler is gone and `rx` has been drained.
+    #[cfg(feature = "async")]
+    pub fn poll_transition(&self, rx: &Receiver<ConnectionState>, cx: &mut Context<'_>) -> Poll<Option<ConnectionState>> {
+        // Transitions are sent under this lock, so one can't slip in between checking `rx` and
+        // registering the waker.
+        let mut state = self.state.lock().expect("Unable to acquire connection state lock");
+
+        match rx.try_recv() {
+            Ok(next) => Poll::Ready(Some(next)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) if state.closed => Poll::Ready(None),
+
+            Err(TryRecvError::Empty) => {
+                if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
+                }
+
+                Poll::Pending
+            },
+        }
+    }
+
+    /// Marks the handler as gone, ending any `poll_transition`s once they've caught up.
+    pub fn close(&self) {
+        let mut state = self.state.lock().expect("Unable to acquire connection state lock");
+        state.closed = true;
+        state.wake_all();
+    }
+
+    #[cfg(test)]
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().expect("Unable to acquire connection state lock").closed
+    }
+
+    pub fn set(&self, status: ConnectionStatus) {
+        self.status.store(status as u8, Ordering::Relaxed);
+        self.transition(status.into());
+    }
+
+    /// Broadcasts `state` if it differs from the last one.
+    pub fn transition(&self, state: ConnectionState) {
+        let mut transitions = self.state.lock().expect("Unable to acquire connection state lock");
+
+        if transitions.current != state {
+            transitions.current = state;
+            self.subscribers.send(state);
+            transitions.wake_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_status_roundtrips() {
+        let status = SharedStatus::new();
+        assert_eq!(status.get(), ConnectionStatus::Connecting);
+
+        for expected in [
+            ConnectionStatus::Connected,
+            ConnectionStatus::Unavailable,
+            ConnectionStatus::Disconnected,
+            ConnectionStatus::Connecting,
+        ] {
+            status.clone().set(expected);
+            assert_eq!(status.get(), expected);
+        }
+    }
+
+    #[test]
+    fn state_transitions_are_broadcast() {
+        let status = SharedStatus::new();
+        let early = status.subscribe();
+
+        // Simulates connecting, dropping, and reconnecting (after one failed attempt).
+        status.set(ConnectionStatus::Connected);
+        status.set(ConnectionStatus::Disconnected);
+        status.transition(ConnectionState::Reconnecting);
+        status.set(ConnectionStatus::Unavailable);
+        status.set(ConnectionStatus::Unavailable);
+        status.transition(ConnectionState::Reconnecting);
+        status.set(ConnectionStatus::Connected);
+
+        let late = status.subscribe();
+        drop(status.subscribe());
+        status.set(ConnectionStatus::Disconnected);
+
+        assert_eq!(
+            early.try_iter().collect::<Vec<_>>(),
+            vec![
+                ConnectionState::Connecting,
+                ConnectionState::Connected,
+                ConnectionState::Disconnected,
+                ConnectionState::Reconnecting,
+                ConnectionState::Disconnected,
+                ConnectionState::Reconnecting,
+                ConnectionState::Connected,
+                ConnectionState::Disconnected,
+            ]
+        );
+
+        assert_eq!(
+            late.try_iter().collect::<Vec<_>>(),
+            vec![ConnectionState::Connected, ConnectionState::Disconnected]
+        );
+    }
+}
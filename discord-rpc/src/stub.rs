@@ -0,0 +1,247 @@
+//! A stand-in for `DiscordHandler` when the `discord` feature is off. It has the same API as
+//! the real handler, but never spawns a thread or touches IPC; every method is a no-op.
+
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use crate::metrics::Metrics;
+use crate::status::SharedStatus;
+use crate::DiscordRPCError::DiscordUnavailable;
+use crate::{
+    AppState, Config, ConnectionState, ConnectionStatus, DiscordMetrics, ErrorCategory, GameEvent, GameInfo, GameReport,
+    GameResult, GameState, JoinRequest, LobbyRole, MatchmakingMode, RankTier, Result, SpectateRequest, TickUpdate,
+};
+
+/// A `DiscordHandler` that does nothing, as Discord support was compiled out.
+#[derive(Debug)]
+pub struct DiscordHandler {
+    status: SharedStatus,
+    metrics: Metrics,
+}
+
+impl DiscordHandler {
+    /// Returns a handler that reports Discord as unavailable, and ignores everything else.
+    pub fn new(_ram_offset: usize, _config: Config) -> Result<Self> {
+        let status = SharedStatus::new();
+        status.set(ConnectionStatus::Unavailable);
+        status.transition(ConnectionState::Unavailable);
+
+        Ok(Self {
+            status,
+            metrics: Metrics::default(),
+        })
+    }
+
+    /// Fails with `DiscordUnavailable` straight away, as the stub never connects.
+    pub fn new_connected(_ram_offset: usize, _config: Config, _timeout: Duration) -> Result<Self> {
+        Err(DiscordUnavailable)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        false
+    }
+
+    pub fn metrics(&self) -> DiscordMetrics {
+        self.metrics.snapshot()
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.status.get()
+    }
+
+    pub fn current_state(&self) -> Option<GameState> {
+        None
+    }
+
+    pub fn current_mode(&self) -> Option<MatchmakingMode> {
+        None
+    }
+
+    pub fn subscribe_state(&self) -> Receiver<ConnectionState> {
+        self.status.subscribe()
+    }
+
+    #[cfg(feature = "async")]
+    pub fn handle(&self) -> crate::DiscordHandle {
+        crate::DiscordHandle::new(self.status.clone())
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        None
+    }
+
+    pub fn last_error_category(&self) -> Option<ErrorCategory> {
+        None
+    }
+
+    pub fn update_config(&self, _config: Config) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn reconnect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn suspend(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_log_level(&self, _level: tracing::Level) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn watch_config_file(&self, _path: PathBuf) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_lobby(&self, _current: u32, _max: u32, _role: LobbyRole) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_game_start(&self, _info: GameInfo) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_playback_start(&self, _info: GameInfo) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_playback_end(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_event(&self, _event: GameEvent) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_paused(&self, _paused: bool) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_app_state(&self, _state: AppState) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_matchmaking_searching(&self, _mode: MatchmakingMode) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_matchmaking_found(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_matchmaking_cancelled(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_stocks(&self, _p1: u8, _p2: u8) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_percents(&self, _p1: f32, _p2: f32) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_set_score(&self, _game: u8, _p1_wins: u8, _p2_wins: u8) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_set_ended(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_awaiting_rematch(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_session_record(&self, _wins: u32, _losses: u32) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn reset_session(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_frame(&self, _frame: i32) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_tick(&self, _tick: TickUpdate) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_connect_code(&self, _connect_code: String) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_opponent(&self, _code: String, _display_name: Option<String>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_ram_offset(&self, _ram_offset: usize) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn update_rank(&self, _tier: RankTier, _rating: f32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns a receiver that never receives anything.
+    pub fn join_requests(&self) -> Receiver<JoinRequest> {
+        channel().1
+    }
+
+    pub fn set_spectate_id(&self, _spectate_id: Option<String>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns a receiver that never receives anything.
+    pub fn spectate_requests(&self) -> Receiver<SpectateRequest> {
+        channel().1
+    }
+
+    pub fn notify_game_end(&self, _result: GameResult) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn notify_game_report(&self, _report: GameReport) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn install_cleanup_hook(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn shutdown(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stub_is_inert() {
+        let handler = DiscordHandler::new(0, Config::default()).unwrap();
+
+        assert!(!handler.is_connected());
+        assert_eq!(handler.status(), ConnectionStatus::Unavailable);
+        assert_eq!(
+            handler.subscribe_state().try_iter().collect::<Vec<_>>(),
+            vec![ConnectionState::Unavailable]
+        );
+        assert!(handler.join_requests().try_recv().is_err());
+
+        crate::tests::exercise_api(handler).unwrap();
+    }
+}
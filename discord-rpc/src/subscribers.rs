@@ -0,0 +1,54 @@
+//! A tiny broadcast helper: any number of consumers can subscribe to receive values sent
+//! from the background thread(s). Subscribers that have gone away are pruned on send.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// A thread-safe list of subscriber channels.
+#[derive(Debug)]
+pub(crate) struct Subscribers<T> {
+    senders: Arc<Mutex<Vec<Sender<T>>>>,
+}
+
+impl<T> Clone for Subscribers<T> {
+    fn clone(&self) -> Self {
+        Self {
+            senders: self.senders.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Subscribers<T> {
+    pub fn new() -> Self {
+        Self {
+            senders: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Registers a new subscriber, returning the receiving end.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let (tx, rx) = channel();
+
+        let mut senders = self.senders.lock().expect("Unable to acquire subscribers lock");
+        senders.push(tx);
+
+        rx
+    }
+
+    /// Registers a new subscriber that receives `initial` before anything else.
+    pub fn subscribe_with(&self, initial: T) -> Receiver<T> {
+        let (tx, rx) = channel();
+        let _ = tx.send(initial);
+
+        let mut senders = self.senders.lock().expect("Unable to acquire subscribers lock");
+        senders.push(tx);
+
+        rx
+    }
+
+    /// Sends `value` to every live subscriber.
+    pub fn send(&self, value: T) {
+        let mut senders = self.senders.lock().expect("Unable to acquire subscribers lock");
+        senders.retain(|tx| tx.send(value.clone()).is_ok());
+    }
+}
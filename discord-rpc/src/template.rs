@@ -0,0 +1,156 @@
+//! Placeholder substitution for user-configurable presence text.
+//!
+//! Templates are plain strings with `{name}` placeholders, e.g `"Battling on {stage}"`. The
+//! supported placeholders are:
+//!
+//! - `{p1_char}` / `{p2_char}`: character names for ports 1 and 2 (in presence text, for
+//!   `Config::primary_port` and their opponent)
+//! - `{stage}`: the stage name
+//! - `{mode}`: the kind of match (e.g, "Singles")
+//!
+//! In presence text, names are shown in `Config::locale`. Anything else between braces is left
+//! untouched, as are unbalanced braces.
+
+use crate::characters::character_name;
+use crate::i18n::translate;
+use crate::stages::stage_name;
+use crate::{GameKind, PresenceContext};
+
+/// The default template for the activity details line.
+pub const DEFAULT_DETAILS_TEMPLATE: &str = "Battling on {stage}";
+
+/// Renders `template` against `ctx`. Placeholders that are unknown, or that can't be resolved
+/// from `ctx`, are left untouched.
+pub fn render_template(template: &str, ctx: &PresenceContext) -> String {
+    substitute(template, ctx, "en", false).unwrap_or_else(|| template.to_string())
+}
+
+/// Renders `template` against `ctx` with names in `locale`, returning `None` if any known
+/// placeholder can't be resolved. Unknown placeholders are still left untouched.
+pub(crate) fn try_render_template(template: &str, ctx: &PresenceContext, locale: &str) -> Option<String> {
+    substitute(template, ctx, locale, true)
+}
+
+/// Resolves a placeholder name. The outer `Option` indicates whether the placeholder is known
+/// at all; the inner one whether it has a value in this context.
+fn resolve(name: &str, ctx: &PresenceContext, locale: &str) -> Option<Option<String>> {
+    let game = ctx.game.as_ref();
+
+    let value = match name {
+        "p1_char" => game.and_then(|info| character_name(info.p1_character_id)),
+        "p2_char" => game.and_then(|info| character_name(info.p2_character_id)),
+        "stage" => game.and_then(|info| stage_name(info.stage_id)),
+
+        "mode" => game.map(|info| match info.kind {
+            GameKind::Singles => "Singles",
+            GameKind::Doubles => "Doubles",
+        }),
+
+        _ => return None,
+    };
+
+    Some(value.map(|value| translate(locale, value).to_string()))
+}
+
+fn substitute(template: &str, ctx: &PresenceContext, locale: &str, strict: bool) -> Option<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        // Only `{identifier}` counts as a placeholder; anything else is copied through
+        // one character at a time so that e.g `{{stage}` still picks up `{stage}`.
+        let placeholder = rest[1..]
+            .find('}')
+            .map(|end| &rest[1..end + 1])
+            .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+
+        match placeholder {
+            Some(name) => {
+                match resolve(name, ctx, locale) {
+                    Some(Some(value)) => output.push_str(&value),
+                    Some(None) if strict => return None,
+                    _ => output.push_str(&rest[..name.len() + 2]),
+                }
+
+                rest = &rest[name.len() + 2..];
+            },
+
+            None => {
+                output.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    output.push_str(rest);
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameInfo, MatchmakingMode};
+
+    fn ctx() -> PresenceContext {
+        PresenceContext {
+            game: Some(GameInfo {
+                stage_id: 31,
+                p1_character_id: 1,
+                p2_character_id: 22,
+                p1_costume_id: 0,
+                p2_costume_id: 0,
+                kind: GameKind::Singles,
+                timer_seconds: None,
+                matchmaking: MatchmakingMode::Offline,
+                players: [None; 4],
+                local_port: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn substitutes_known_placeholders() {
+        assert_eq!(render_template(DEFAULT_DETAILS_TEMPLATE, &ctx()), "Battling on Battlefield");
+        assert_eq!(
+            render_template("{p1_char} vs {p2_char} ({mode})", &ctx()),
+            "Fox vs Falco (Singles)"
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_placeholders_untouched() {
+        assert_eq!(render_template("{stage} {nope}", &ctx()), "Battlefield {nope}");
+    }
+
+    #[test]
+    fn handles_missing_and_extra_braces() {
+        assert_eq!(render_template("on {stage", &ctx()), "on {stage");
+        assert_eq!(render_template("on stage}", &ctx()), "on stage}");
+        assert_eq!(render_template("{{stage}}", &ctx()), "{Battlefield}");
+        assert_eq!(render_template("{}", &ctx()), "{}");
+        assert_eq!(render_template("{ stage }", &ctx()), "{ stage }");
+    }
+
+    #[test]
+    fn unresolvable_placeholders() {
+        let empty = PresenceContext::default();
+        assert_eq!(render_template("on {stage}", &empty), "on {stage}");
+        assert_eq!(try_render_template("on {stage}", &empty, "en"), None);
+        assert_eq!(
+            try_render_template("on {other}", &empty, "en"),
+            Some("on {other}".to_string())
+        );
+    }
+
+    #[test]
+    fn placeholders_are_localized() {
+        assert_eq!(
+            try_render_template("{p1_char} vs {p2_char} on {stage} ({mode})", &ctx(), "ja"),
+            Some("フォックス vs ファルコ on 戦場 (シングル)".to_string())
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Discord will throttle (or outright drop) activity updates that are sent faster than
+//! roughly five every twenty seconds. Rather than pushing every change straight through,
+//! the handler queues desired activities here and flushes at most once per interval, with
+//! the most recently queued activity winning.
+//!
+//! An activity can also be expedited past the interval, but never past Discord's own limit,
+//! which is tracked separately.
+//!
+//! The interval itself can adapt to how busy things are, via `AdaptiveThrottle`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::Config;
+
+/// How many activity updates Discord accepts per `DISCORD_RATE_WINDOW`.
+pub(crate) const DISCORD_RATE_LIMIT: usize = 5;
+
+/// The rolling window that `DISCORD_RATE_LIMIT` applies over.
+pub(crate) const DISCORD_RATE_WINDOW: Duration = Duration::from_secs(20);
+
+/// The interval `AdaptiveThrottle` slows down to while things are quiet.
+pub(crate) const IDLE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How many changes after Discord rate limits us are held to a doubled interval.
+pub(crate) const COOLDOWN_CHANGES: u32 = 3;
+
+/// Picks the `RateLimiter` interval based on how often the presence is changing: the
+/// configured interval while changes are coming in quick succession (e.g, stocks being
+/// taken), and `IDLE_INTERVAL` once they've stopped.
+///
+/// A lone change after a quiet spell is held to the slower interval, which usually means it
+/// goes out straight away anyway, as nothing else has been sent in a while.
+///
+/// If Discord rate limits us anyway, the interval is doubled for the next `COOLDOWN_CHANGES`
+/// changes, whether or not adapting is enabled.
+#[derive(Debug)]
+pub(crate) struct AdaptiveThrottle<C> {
+    clock: C,
+    base: Duration,
+    enabled: bool,
+    last_change: Option<Instant>,
+    cooldown: u32,
+}
+
+impl<C: Clock> AdaptiveThrottle<C> {
+    /// Creates a new `AdaptiveThrottle`, using `base` while busy. If `enabled` is unset,
+    /// `base` is always used.
+    pub fn new(clock: C, base: Duration, enabled: bool) -> Self {
+        Self {
+            clock,
+            base,
+            enabled,
+            last_change: None,
+            cooldown: 0,
+        }
+    }
+
+    /// Reconfigures the throttle, e.g after a config update.
+    pub fn configure(&mut self, base: Duration, enabled: bool) {
+        self.base = base;
+        self.enabled = enabled;
+    }
+
+    /// Records that the presence just changed, returning the interval to use for it.
+    pub fn record_change(&mut self) -> Duration {
+        let now = self.clock.now();
+        let interval = self.interval_at(now);
+
+        self.last_change = Some(now);
+        self.cooldown = self.cooldown.saturating_sub(1);
+        interval
+    }
+
+    /// Records that Discord turned an update away for coming too quickly, which starts a
+    /// cooldown.
+    pub fn record_rate_limited(&mut self) {
+        self.cooldown = COOLDOWN_CHANGES;
+    }
+
+    /// The interval to use right now, without recording a change.
+    pub fn interval(&self) -> Duration {
+        self.interval_at(self.clock.now())
+    }
+
+    /// The last change counts as busy if it was recent enough that another would follow on
+    /// closely behind it.
+    fn interval_at(&self, now: Instant) -> Duration {
+        let busy = self
+            .last_change
+            .is_some_and(|at| now.saturating_duration_since(at) < IDLE_INTERVAL);
+
+        let interval = match self.enabled && !busy {
+            true => self.base.max(IDLE_INTERVAL),
+            false => self.base,
+        };
+
+        // A zero interval doubles to nothing, so it backs off to the smallest allowed instead.
+        match self.cooldown > 0 {
+            true => (interval * 2).max(Config::MIN_UPDATE_INTERVAL),
+            false => interval,
+        }
+    }
+}
+
+/// Coalesces queued activities so that at most one is flushed per `interval`.
+///
+/// `T` is whatever describes an update; in practice, an `Option<Activity>` where `None`
+/// clears the activity.
+#[derive(Debug)]
+pub(crate) struct RateLimiter<T> {
+    interval: Duration,
+    last_flush: Option<Instant>,
+    pending: Option<T>,
+    expedited: bool,
+
+    /// When the most recent (up to `DISCORD_RATE_LIMIT`) flushes happened, oldest first.
+    recent: VecDeque<Instant>,
+}
+
+impl<T> RateLimiter<T> {
+    /// Creates a new `RateLimiter` that flushes at most once per `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_flush: None,
+            pending: None,
+            expedited: false,
+            recent: VecDeque::with_capacity(DISCORD_RATE_LIMIT),
+        }
+    }
+
+    /// Changes the flush interval. Takes effect from the last flush onwards.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Queues an activity to be flushed, replacing anything already pending. Returns whether
+    /// a pending activity was replaced (and so will never be flushed).
+    pub fn queue(&mut self, update: T) -> bool {
+        self.pending.replace(update).is_some()
+    }
+
+    /// Whether an activity is waiting to be flushed.
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The activity waiting to be flushed, if there is one.
+    pub fn pending(&self) -> Option<&T> {
+        self.pending.as_ref()
+    }
+
+    /// Drops the pending activity if `predicate` holds for it, without counting as a flush.
+    /// Returns whether it was dropped.
+    pub fn discard_if(&mut self, predicate: impl FnOnce(&T) -> bool) -> bool {
+        match self.pending.as_ref().map(predicate) {
+            Some(true) => {
+                self.pending = None;
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Lets the pending activity (and anything that replaces it) skip the rest of the interval,
+    /// so it goes out as soon as Discord's limit allows. Does nothing if nothing is pending.
+    pub fn expedite(&mut self) {
+        self.expedited = self.pending.is_some();
+    }
+
+    /// Forgets when we last flushed, so that whatever's pending goes out on the next poll.
+    /// This is for a fresh connection, which Discord doesn't rate limit against the old one.
+    pub fn reset(&mut self) {
+        self.last_flush = None;
+        self.recent.clear();
+    }
+
+    /// How long until the pending activity may be flushed, or `None` if nothing is pending.
+    pub fn time_until_flush(&self, now: Instant) -> Option<Duration> {
+        self.pending.as_ref()?;
+
+        let until_interval = match self.last_flush {
+            Some(last_flush) => self.interval.saturating_sub(now.saturating_duration_since(last_flush)),
+            None => Duration::ZERO,
+        };
+
+        if !self.expedited {
+            return Some(until_interval);
+        }
+
+        // The oldest recent flush has to leave the window before there's room for another.
+        let until_window = match self.recent.len() < DISCORD_RATE_LIMIT {
+            true => Duration::ZERO,
+            false => (self.recent[0] + DISCORD_RATE_WINDOW).saturating_duration_since(now),
+        };
+
+        Some(until_interval.min(until_window))
+    }
+
+    /// Returns the pending activity if one is queued and the interval has elapsed since the
+    /// last flush.
+    pub fn poll(&mut self, now: Instant) -> Option<T> {
+        if self.time_until_flush(now)? > Duration::ZERO {
+            return None;
+        }
+
+        self.record_flush(now);
+        self.expedited = false;
+        self.pending.take()
+    }
+
+    /// Counts an update that was sent at `now` without going through `poll`, so that it's
+    /// rate limited against like any other.
+    pub fn record_flush(&mut self, now: Instant) {
+        if self.recent.len() == DISCORD_RATE_LIMIT {
+            self.recent.pop_front();
+        }
+
+        self.recent.push_back(now);
+        self.last_flush = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::Activity;
+
+    fn activity(details: &str) -> Activity {
+        Activity {
+            details: Some(details.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn first_update_flushes_immediately() {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(Duration::from_secs(4));
+
+        limiter.queue(activity("a"));
+        assert_eq!(limiter.poll(clock.now()), Some(activity("a")));
+        assert_eq!(limiter.poll(clock.now()), None);
+    }
+
+    #[test]
+    fn reset_allows_an_immediate_flush() {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(Duration::from_secs(4));
+
+        limiter.queue(activity("a"));
+        limiter.poll(clock.now());
+
+        limiter.queue(activity("b"));
+        assert!(limiter.has_pending());
+        assert_eq!(limiter.poll(clock.now()), None);
+
+        limiter.reset();
+        assert_eq!(limiter.poll(clock.now()), Some(activity("b")));
+        assert!(!limiter.has_pending());
+    }
+
+    #[test]
+    fn latest_update_wins_within_a_window() {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(Duration::from_secs(4));
+
+        limiter.queue(activity("a"));
+        limiter.poll(clock.now());
+
+        assert!(!limiter.queue(activity("b")));
+        assert!(limiter.queue(activity("c")));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.poll(clock.now()), None);
+        assert_eq!(limiter.time_until_flush(clock.now()), Some(Duration::from_secs(3)));
+
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(limiter.poll(clock.now()), Some(activity("c")));
+        assert_eq!(limiter.time_until_flush(clock.now()), None);
+    }
+
+    #[test]
+    fn discarding_leaves_the_window_alone() {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(Duration::from_secs(4));
+
+        limiter.queue(activity("a"));
+        assert!(!limiter.discard_if(|update| *update == activity("b")));
+        assert!(limiter.discard_if(|update| *update == activity("a")));
+        assert!(!limiter.has_pending());
+
+        limiter.queue(activity("b"));
+        assert_eq!(limiter.poll(clock.now()), Some(activity("b")));
+    }
+
+    #[test]
+    fn expedited_updates_skip_the_interval() {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(Duration::from_secs(10));
+
+        limiter.queue(activity("a"));
+        limiter.poll(clock.now());
+
+        limiter.expedite();
+        assert_eq!(limiter.poll(clock.now()), None);
+
+        limiter.queue(activity("b"));
+        assert_eq!(limiter.time_until_flush(clock.now()), Some(Duration::from_secs(10)));
+
+        limiter.expedite();
+        assert_eq!(limiter.poll(clock.now()), Some(activity("b")));
+
+        // That only applied to the one update.
+        limiter.queue(activity("c"));
+        assert_eq!(limiter.time_until_flush(clock.now()), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn expedited_updates_wait_for_discords_limit() {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(Duration::from_secs(30));
+
+        for i in 0..DISCORD_RATE_LIMIT {
+            limiter.queue(activity(&i.to_string()));
+            limiter.expedite();
+            assert!(limiter.poll(clock.now()).is_some());
+            clock.advance(Duration::from_secs(1));
+        }
+
+        // The first of those leaves the window 20s after it went out, which is 15s from now.
+        limiter.queue(activity("late"));
+        limiter.expedite();
+        assert_eq!(limiter.poll(clock.now()), None);
+        assert_eq!(limiter.time_until_flush(clock.now()), Some(Duration::from_secs(15)));
+
+        clock.advance(Duration::from_secs(15));
+        assert_eq!(limiter.poll(clock.now()), Some(activity("late")));
+    }
+
+    #[test]
+    fn adaptive_throttle_speeds_up_while_busy() {
+        let clock = ManualClock::new();
+        let mut throttle = AdaptiveThrottle::new(clock.clone(), Duration::from_secs(4), true);
+
+        // Nothing has happened in a while, so the first change is held to the idle interval...
+        assert_eq!(throttle.record_change(), IDLE_INTERVAL);
+
+        // ...but anything following closely behind speeds things up.
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(throttle.record_change(), Duration::from_secs(4));
+        assert_eq!(throttle.interval(), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn adaptive_throttle_slows_down_once_idle() {
+        let clock = ManualClock::new();
+        let mut throttle = AdaptiveThrottle::new(clock.clone(), Duration::from_secs(4), true);
+
+        throttle.record_change();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(throttle.record_change(), Duration::from_secs(4));
+
+        clock.advance(IDLE_INTERVAL);
+        assert_eq!(throttle.interval(), IDLE_INTERVAL);
+        assert_eq!(throttle.record_change(), IDLE_INTERVAL);
+
+        // A base interval that's slower than idle always wins, as does turning this off.
+        throttle.configure(Duration::from_secs(30), true);
+        assert_eq!(throttle.interval(), Duration::from_secs(30));
+
+        clock.advance(IDLE_INTERVAL);
+        throttle.configure(Duration::from_secs(4), false);
+        assert_eq!(throttle.record_change(), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn rate_limiting_doubles_the_interval_for_a_while() {
+        let mut throttle = AdaptiveThrottle::new(ManualClock::new(), Duration::from_secs(4), false);
+
+        throttle.record_rate_limited();
+        assert_eq!(throttle.interval(), Duration::from_secs(8));
+
+        for _ in 0..COOLDOWN_CHANGES {
+            assert_eq!(throttle.record_change(), Duration::from_secs(8));
+        }
+
+        assert_eq!(throttle.record_change(), Duration::from_secs(4));
+
+        throttle.configure(Duration::ZERO, false);
+        throttle.record_rate_limited();
+        assert_eq!(throttle.interval(), Config::MIN_UPDATE_INTERVAL);
+    }
+}
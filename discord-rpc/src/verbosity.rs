@@ -0,0 +1,117 @@
+//! How much of a handler's own logging makes it through to the subscriber. Each handler
+//! carries its own threshold, shared with every thread that logs on its behalf (the
+//! background thread, the IPC reader, the config watcher and whoever's calling into it), so
+//! it can be moved at runtime (e.g, bumped to debug while chasing down a presence issue)
+//! without a restart, and without touching the global subscriber or any other handler's
+//! logging.
+//!
+//! Logging goes through `log!` here rather than straight to `tracing`, so that the
+//! threshold is checked first. Whatever gets past it is still subject to the subscriber's own
+//! filtering; until a threshold is set, that's all there is.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use tracing::Level;
+
+/// A handler's logging threshold. Clones share the same one.
+#[derive(Clone, Debug)]
+pub(crate) struct Verbosity(Arc<AtomicU8>);
+
+impl Verbosity {
+    /// Sets the threshold, so that only events at `level` or above (i.e, at least as severe)
+    /// are logged from here on, from any thread.
+    pub fn set_level(&self, level: Level) {
+        self.0.store(rank(level), Ordering::Relaxed);
+    }
+
+    /// Whether an event at `level` gets past the threshold.
+    pub fn enabled(&self, level: Level) -> bool {
+        rank(level) <= self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Verbosity {
+    /// Lets everything through, leaving it to the subscriber.
+    fn default() -> Self {
+        Self(Arc::new(AtomicU8::new(rank(Level::TRACE))))
+    }
+}
+
+/// Numbers levels from least to most verbose.
+fn rank(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 0,
+        Level::WARN => 1,
+        Level::INFO => 2,
+        Level::DEBUG => 3,
+        Level::TRACE => 4,
+    }
+}
+
+/// Logs via the `tracing` macro for the given level (`error`, `warn`, `info` or `debug`), if
+/// the event gets past the given `Verbosity`'s threshold, e.g
+/// `verbosity::log!(self.verbosity, debug, target: Log::DiscordRPC, "...")`.
+macro_rules! log {
+    ($verbosity:expr, error, $($args:tt)+) => {
+        if $verbosity.enabled(tracing::Level::ERROR) {
+            tracing::error!($($args)+);
+        }
+    };
+
+    ($verbosity:expr, warn, $($args:tt)+) => {
+        if $verbosity.enabled(tracing::Level::WARN) {
+            tracing::warn!($($args)+);
+        }
+    };
+
+    ($verbosity:expr, info, $($args:tt)+) => {
+        if $verbosity.enabled(tracing::Level::INFO) {
+            tracing::info!($($args)+);
+        }
+    };
+
+    ($verbosity:expr, debug, $($args:tt)+) => {
+        if $verbosity.enabled(tracing::Level::DEBUG) {
+            tracing::debug!($($args)+);
+        }
+    };
+}
+
+pub(crate) use log;
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn events_below_the_threshold_are_suppressed() {
+        let verbosity = Verbosity::default();
+        assert!(verbosity.enabled(Level::TRACE));
+
+        verbosity.set_level(Level::DEBUG);
+        assert!(verbosity.enabled(Level::DEBUG));
+        assert!(verbosity.enabled(Level::ERROR));
+        assert!(!verbosity.enabled(Level::TRACE));
+
+        verbosity.set_level(Level::WARN);
+        assert!(verbosity.enabled(Level::WARN));
+        assert!(verbosity.enabled(Level::ERROR));
+        assert!(!verbosity.enabled(Level::INFO));
+        assert!(!verbosity.enabled(Level::DEBUG));
+    }
+
+    #[test]
+    fn thresholds_are_shared_between_clones_only() {
+        let verbosity = Verbosity::default();
+        let other = Verbosity::default();
+
+        let shared = verbosity.clone();
+        thread::spawn(move || shared.set_level(Level::WARN)).join().unwrap();
+
+        assert!(!verbosity.enabled(Level::INFO));
+        assert!(other.enabled(Level::INFO));
+    }
+}
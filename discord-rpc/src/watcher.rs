@@ -0,0 +1,79 @@
+//! Watches a settings file so that presence settings can be tweaked without restarting
+//! Dolphin. This just polls the file's modification time, which is plenty for a file that's
+//! edited by hand every so often.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use dolphin_integrations::Log;
+
+use crate::verbosity::{self, Verbosity};
+use crate::{Config, DiscordRPCError, Message, Result};
+use DiscordRPCError::*;
+
+/// How often the file's modification time is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Spawns a thread that sends `Message::UpdateConfig` to `tx` whenever the file at `path`
+/// changes (including when it first appears) and parses successfully. The thread exits once
+/// `stopped` is set or the handler's thread has gone away. Logs through the handler's
+/// `verbosity`.
+pub(crate) fn spawn(path: PathBuf, tx: Sender<Message>, stopped: Arc<AtomicBool>, verbosity: Verbosity) -> Result<()> {
+    thread::Builder::new()
+        .name("SlippiDiscordRPCConfigWatcher".to_string())
+        .spawn(move || {
+            let mut last_modified: Option<SystemTime> = None;
+
+            while !stopped.load(Ordering::Relaxed) {
+                let modified = path.metadata().and_then(|metadata| metadata.modified()).ok();
+
+                if modified.is_some() && modified != last_modified {
+                    last_modified = modified;
+
+                    if let Some(config) = load(&path, &verbosity) {
+                        if tx.send(Message::UpdateConfig(Box::new(config))).is_err() {
+                            break;
+                        }
+                    }
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        })
+        .map_err(ThreadSpawn)?;
+
+    Ok(())
+}
+
+/// Reads and parses the config at `path`. Failures are logged rather than returned, as a
+/// half-finished edit shouldn't take down the watcher; we'll pick the file up again once it's
+/// saved in a valid state.
+fn load(path: &Path, verbosity: &Verbosity) -> Option<Config> {
+    let result = std::fs::read_to_string(path)
+        .map_err(DiscordRPCError::from)
+        .and_then(|source| Config::from_toml_str(&source));
+
+    match result {
+        Ok(config) => {
+            verbosity::log!(verbosity, info, target: Log::DiscordRPC, path = %path.display(), "Reloaded Discord config file");
+            Some(config)
+        },
+
+        Err(e) => {
+            verbosity::log!(
+                verbosity,
+                warn,
+                target: Log::DiscordRPC,
+                error = ?e,
+                path = %path.display(),
+                "Ignoring invalid Discord config file"
+            );
+
+            None
+        },
+    }
+}
@@ -17,6 +17,20 @@ enum SlippiMatchmakingOnlinePlayMode {
   Teams = 3,
 };
 
+/// How the local player's match ended. This mirrors `slippi_discord_rpc::GameResult`, which
+/// cbindgen can't see.
+enum DiscordGameResult {
+  Win = 0,
+  Loss = 1,
+  NoContest = 2,
+};
+
+/// An opaque handle to a running `slippi_discord_rpc::DiscordHandler`.
+///
+/// This wraps the handler so that cbindgen, which can't see types in other crates, can still
+/// declare it for the C++ side.
+struct DiscordHandler;
+
 /// A configuration struct for passing over certain argument types from the C/C++ side.
 ///
 /// The number of arguments necessary to shuttle across the FFI boundary when starting the
@@ -53,6 +67,39 @@ struct RustChatMessages {
 
 extern "C" {
 
+/// Starts a handler for the application with the given ID (or the default Slippi one, if
+/// null), returning null if it couldn't be started (e.g, because one hasn't been freed yet).
+/// `ram_offset` is the host address that emulated main memory is mapped at.
+DiscordHandler *slprs_discord_new(uintptr_t ram_offset, const char *app_id);
+
+/// Calls through to `DiscordHandler::notify_game_start`.
+///
+/// A `timer_seconds` of zero means the match is untimed. `online_mode` is ignored unless
+/// `is_online` is set.
+void slprs_discord_game_start(DiscordHandler *handler,
+                              uint16_t stage_id,
+                              uint8_t p1_character_id,
+                              uint8_t p2_character_id,
+                              uint8_t p1_costume_id,
+                              uint8_t p2_costume_id,
+                              bool is_doubles,
+                              uint32_t timer_seconds,
+                              bool is_online,
+                              SlippiMatchmakingOnlinePlayMode online_mode);
+
+/// Calls through to `DiscordHandler::notify_game_end`.
+void slprs_discord_game_end(DiscordHandler *handler, DiscordGameResult result);
+
+/// Parses `config_toml` (see `Config::from_toml_str`) and calls through to
+/// `DiscordHandler::update_config` with it. Returns whether the config was applied.
+///
+/// This replaces the whole config, so it should repeat any `application_id` that was passed to
+/// `slprs_discord_new`.
+bool slprs_discord_update_config(DiscordHandler *handler, const char *config_toml);
+
+/// Shuts down and frees a handler created by `slprs_discord_new`, clearing the activity.
+void slprs_discord_free(DiscordHandler *handler);
+
 /// Creates and leaks a shadow EXI device with the provided configuration.
 ///
 /// The C++ (Dolphin) side of things should call this and pass the appropriate arguments. At
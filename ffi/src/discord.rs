@@ -0,0 +1,282 @@
+//! Entry points for driving Discord Rich Presence from Dolphin.
+//!
+//! Unlike the rest of this library, the handler isn't owned by the EXI device; Dolphin holds
+//! onto it directly. The ownership rules are:
+//!
+//! - `slprs_discord_new` returns a handler (or null, if it couldn't be started) that the C++
+//!   side owns. It must be passed to `slprs_discord_free` exactly once, after which the
+//!   pointer is dangling.
+//! - Every other function only borrows the handler for the duration of the call, and does
+//!   nothing if it's null.
+//! - Strings are UTF-8, and only borrowed for the duration of the call. Null strings are
+//!   treated as missing.
+//!
+//! Panics are caught at the boundary (and logged) rather than unwinding into C++.
+
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use dolphin_integrations::Log;
+use slippi_discord_rpc::{Config, GameInfo, GameKind, GameResult, MatchmakingMode};
+
+use crate::game_reporter::SlippiMatchmakingOnlinePlayMode;
+
+/// An opaque handle to a running `slippi_discord_rpc::DiscordHandler`.
+///
+/// This wraps the handler so that cbindgen, which can't see types in other crates, can still
+/// declare it for the C++ side.
+#[derive(Debug)]
+pub struct DiscordHandler(slippi_discord_rpc::DiscordHandler);
+
+/// How the local player's match ended. This mirrors `slippi_discord_rpc::GameResult`, which
+/// cbindgen can't see.
+#[derive(Debug)]
+#[repr(C)]
+pub enum DiscordGameResult {
+    Win = 0,
+    Loss = 1,
+    NoContest = 2,
+}
+
+/// Starts a handler for the application with the given ID (or the default Slippi one, if
+/// null), returning null if it couldn't be started (e.g, because one hasn't been freed yet).
+/// `ram_offset` is the host address that emulated main memory is mapped at.
+#[no_mangle]
+pub extern "C" fn slprs_discord_new(ram_offset: usize, app_id: *const c_char) -> *mut DiscordHandler {
+    guard("slprs_discord_new", std::ptr::null_mut(), || {
+        let config = match str_arg(app_id) {
+            Some(app_id) => Config::builder().application_id(app_id).build(),
+            None => Ok(Config::default()),
+        };
+
+        let handler = config.and_then(|config| slippi_discord_rpc::DiscordHandler::new(ram_offset, config));
+
+        match handler {
+            Ok(handler) => Box::into_raw(Box::new(DiscordHandler(handler))),
+
+            Err(e) => {
+                tracing::error!(target: Log::DiscordRPC, error = ?e, "Failed to start DiscordHandler");
+                std::ptr::null_mut()
+            },
+        }
+    })
+}
+
+/// Calls through to `DiscordHandler::notify_game_start`.
+///
+/// A `timer_seconds` of zero means the match is untimed. `online_mode` is ignored unless
+/// `is_online` is set.
+#[no_mangle]
+pub extern "C" fn slprs_discord_game_start(
+    handler: *mut DiscordHandler,
+    stage_id: u16,
+    p1_character_id: u8,
+    p2_character_id: u8,
+    p1_costume_id: u8,
+    p2_costume_id: u8,
+    is_doubles: bool,
+    timer_seconds: u32,
+    is_online: bool,
+    online_mode: SlippiMatchmakingOnlinePlayMode,
+) {
+    with_handler("slprs_discord_game_start", handler, |handler| {
+        let matchmaking = match (is_online, online_mode) {
+            (false, _) => MatchmakingMode::Offline,
+            (true, SlippiMatchmakingOnlinePlayMode::Ranked) => MatchmakingMode::Ranked,
+            (true, SlippiMatchmakingOnlinePlayMode::Unranked) => MatchmakingMode::Unranked,
+            (true, SlippiMatchmakingOnlinePlayMode::Direct) => MatchmakingMode::Direct,
+            (true, SlippiMatchmakingOnlinePlayMode::Teams) => MatchmakingMode::Teams,
+        };
+
+        let _ = handler.notify_game_start(GameInfo {
+            stage_id,
+            p1_character_id,
+            p2_character_id,
+            p1_costume_id,
+            p2_costume_id,
+            kind: match is_doubles {
+                true => GameKind::Doubles,
+                false => GameKind::Singles,
+            },
+            timer_seconds: (timer_seconds > 0).then_some(timer_seconds),
+            matchmaking,
+            players: [None; 4],
+            local_port: None,
+        });
+    });
+}
+
+/// Calls through to `DiscordHandler::notify_game_end`.
+#[no_mangle]
+pub extern "C" fn slprs_discord_game_end(handler: *mut DiscordHandler, result: DiscordGameResult) {
+    with_handler("slprs_discord_game_end", handler, |handler| {
+        let _ = handler.notify_game_end(match result {
+            DiscordGameResult::Win => GameResult::Win,
+            DiscordGameResult::Loss => GameResult::Loss,
+            DiscordGameResult::NoContest => GameResult::NoContest,
+        });
+    });
+}
+
+/// Parses `config_toml` (see `Config::from_toml_str`) and calls through to
+/// `DiscordHandler::update_config` with it. Returns whether the config was applied.
+///
+/// This replaces the whole config, so it should repeat any `application_id` that was passed to
+/// `slprs_discord_new`.
+#[no_mangle]
+pub extern "C" fn slprs_discord_update_config(handler: *mut DiscordHandler, config_toml: *const c_char) -> bool {
+    let config_toml = str_arg(config_toml);
+
+    with_handler("slprs_discord_update_config", handler, |handler| {
+        let config = match Config::from_toml_str(config_toml?) {
+            Ok(config) => config,
+
+            Err(e) => {
+                tracing::error!(target: Log::DiscordRPC, error = ?e, "Ignoring invalid DiscordRPC config");
+                return None;
+            },
+        };
+
+        handler.update_config(config).ok()
+    })
+    .flatten()
+    .is_some()
+}
+
+/// Shuts down and frees a handler created by `slprs_discord_new`, clearing the activity.
+#[no_mangle]
+pub extern "C" fn slprs_discord_free(handler: *mut DiscordHandler) {
+    if handler.is_null() {
+        return;
+    }
+
+    guard("slprs_discord_free", (), || {
+        if let Err(e) = take_handler(handler).0.shutdown() {
+            tracing::error!(target: Log::DiscordRPC, error = ?e, "DiscordHandler did not shut down cleanly");
+        }
+    });
+}
+
+/// Takes back ownership of a handler created by `slprs_discord_new`.
+fn take_handler(handler: *mut DiscordHandler) -> Box<DiscordHandler> {
+    // This is safe so long as the C++ side honors the ownership rules above: the pointer came
+    // from `slprs_discord_new`, and this is the last time it's used.
+    unsafe { Box::from_raw(handler) }
+}
+
+/// Runs `f` against the handler behind `handler`, returning `None` if it's null (or `f`
+/// panicked).
+fn with_handler<F, R>(fn_label: &str, handler: *mut DiscordHandler, f: F) -> Option<R>
+where
+    F: FnOnce(&slippi_discord_rpc::DiscordHandler) -> R,
+{
+    // This is safe so long as the C++ side honors the ownership rules above; the handler is
+    // only ever borrowed here.
+    let handler = unsafe { handler.as_ref() }?;
+
+    guard(fn_label, None, || Some(f(&handler.0)))
+}
+
+/// Runs `f`, logging and returning `fallback` if it panics.
+fn guard<F, R>(fn_label: &str, fallback: R, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+
+        Err(_) => {
+            tracing::error!(target: Log::DiscordRPC, "[{}] Panicked, ignoring", fn_label);
+            fallback
+        },
+    }
+}
+
+/// Borrows a string argument, returning `None` if it's null or isn't valid UTF-8.
+fn str_arg<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None;
+    }
+
+    // This is safe so long as the C++ side passes a nul-terminated string that outlives the
+    // call, which is all that's borrowed here.
+    let string = unsafe { CStr::from_ptr(string) };
+
+    match string.to_str() {
+        Ok(string) => Some(string),
+
+        Err(e) => {
+            tracing::error!(target: Log::DiscordRPC, error = ?e, "Ignoring non UTF-8 string argument");
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+
+    /// A handler as `slprs_discord_new` would return it, but that doesn't talk to whichever
+    /// Discord client happens to be running.
+    fn mock_handler() -> *mut DiscordHandler {
+        let config = Config::builder().application_id("1234").build().unwrap();
+        let handler = slippi_discord_rpc::DiscordHandler::with_mock_backend(0, config).unwrap();
+        Box::into_raw(Box::new(DiscordHandler(handler)))
+    }
+
+    #[test]
+    fn null_handlers_are_ignored() {
+        let config = CString::new("show_timer = false").unwrap();
+
+        slprs_discord_game_start(
+            std::ptr::null_mut(),
+            32,
+            1,
+            18,
+            0,
+            0,
+            false,
+            0,
+            false,
+            SlippiMatchmakingOnlinePlayMode::Ranked,
+        );
+        slprs_discord_game_end(std::ptr::null_mut(), DiscordGameResult::NoContest);
+        assert!(!slprs_discord_update_config(std::ptr::null_mut(), config.as_ptr()));
+        slprs_discord_free(std::ptr::null_mut());
+    }
+
+    #[test]
+    fn handlers_can_be_driven_and_freed() {
+        let handler = mock_handler();
+
+        slprs_discord_game_start(
+            handler,
+            32,
+            1,
+            18,
+            0,
+            0,
+            false,
+            480,
+            true,
+            SlippiMatchmakingOnlinePlayMode::Direct,
+        );
+        slprs_discord_game_end(handler, DiscordGameResult::Win);
+
+        let valid = CString::new("show_timer = false").unwrap();
+        let invalid = CString::new("show_timer = ").unwrap();
+        assert!(slprs_discord_update_config(handler, valid.as_ptr()));
+        assert!(!slprs_discord_update_config(handler, invalid.as_ptr()));
+        assert!(!slprs_discord_update_config(handler, std::ptr::null()));
+
+        slprs_discord_free(handler);
+    }
+
+    #[test]
+    fn invalid_application_ids_are_rejected() {
+        let app_id = CString::new("not a number").unwrap();
+        assert!(slprs_discord_new(0, app_id.as_ptr()).is_null());
+    }
+}
@@ -9,6 +9,7 @@ use std::ffi::{c_char, CStr};
 
 use dolphin_integrations::Log;
 
+pub mod discord;
 pub mod exi;
 pub mod game_reporter;
 pub mod jukebox;